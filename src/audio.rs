@@ -1,7 +1,30 @@
+use crate::{
+    dither::{Dither, Quantizer},
+    midi::{
+        control::{LiveEvent, MidiControl},
+        formats::Event,
+        player::Player,
+    },
+    synth::Synth,
+};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    BufferSize, Device, Sample, SampleFormat, Stream, StreamConfig,
+    BufferSize, BuildStreamError, DefaultStreamConfigError, Device, PlayStreamError, Sample,
+    SampleFormat, Stream, StreamConfig,
 };
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AudioError {
+    #[error("no output device available")]
+    NoDevice,
+    #[error("no usable output config: {0}")]
+    Config(#[from] DefaultStreamConfigError),
+    #[error("failed to build output stream: {0}")]
+    BuildStream(#[from] BuildStreamError),
+    #[error("failed to start output stream: {0}")]
+    PlayStream(#[from] PlayStreamError),
+}
 
 pub struct OutputStreamParams {
     output_device: Device,
@@ -9,43 +32,180 @@ pub struct OutputStreamParams {
     sample_format: SampleFormat,
 }
 
-impl Default for OutputStreamParams {
-    fn default() -> Self {
-        let output_device = cpal::default_host().default_output_device().unwrap();
-        let default_config = output_device.default_output_config().unwrap();
+impl OutputStreamParams {
+    /// Default output device at its default sample rate and sample format,
+    /// in stereo if the device supports it
+    pub fn new() -> Result<Self, AudioError> {
+        let output_device = cpal::default_host()
+            .default_output_device()
+            .ok_or(AudioError::NoDevice)?;
+        let default_config = output_device.default_output_config()?;
+        let sample_rate = default_config.sample_rate();
+        let sample_format = default_config.sample_format();
+        let stereo = output_device
+            .supported_output_configs()
+            .map(|mut configs| {
+                configs.any(|c| {
+                    c.channels() == 2
+                        && c.sample_format() == sample_format
+                        && (c.min_sample_rate()..=c.max_sample_rate()).contains(&sample_rate)
+                })
+            })
+            .unwrap_or(false);
 
-        Self {
+        Ok(Self {
             output_device,
             stream_config: StreamConfig {
-                channels: 2,
-                sample_rate: default_config.sample_rate(),
+                channels: if stereo { 2 } else { default_config.channels() },
+                sample_rate,
                 buffer_size: BufferSize::Default,
             },
-            sample_format: default_config.sample_format(),
-        }
+            sample_format,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.stream_config.sample_rate.0
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.stream_config.channels
     }
 }
 
-struct AudioRenderer {}
+/// Drives a [`Synth`] from a [`Player`] with sample accurate event timing,
+/// and from a live [`MidiControl`] at the start of every buffer
+pub struct AudioRenderer {
+    synth: Synth,
+    player: Option<Player>,
+    control: Option<MidiControl>,
+    channels: u16,
+    /// used for integer sample formats
+    quantizer: Quantizer,
+}
 
 impl AudioRenderer {
-    fn new() -> Self {
-        Self {}
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            synth: Synth::new(sample_rate as f32),
+            player: None,
+            control: None,
+            channels,
+            quantizer: Quantizer::new(Dither::Triangular),
+        }
+    }
+
+    /// The synth, to set up the mixer, tuning or presets before playing
+    pub fn synth_mut(&mut self) -> &mut Synth {
+        &mut self.synth
+    }
+
+    /// Dither of `SampleFormat::I16` and `SampleFormat::U16` output, TPDF by default
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.quantizer.set_dither(dither);
+    }
+
+    pub fn with_player(player: Player, channels: u16) -> Self {
+        let mut renderer = Self::new(player.sample_rate(), channels);
+        renderer.player = Some(player);
+        renderer
+    }
+
+    pub fn with_control(control: MidiControl, sample_rate: u32, channels: u16) -> Self {
+        let mut renderer = Self::new(sample_rate, channels);
+        renderer.control = Some(control);
+        renderer
+    }
+
+    /// Returns true while there are events left to play, always with live input
+    pub fn is_playing(&self) -> bool {
+        self.control.is_some()
+            || self
+                .player
+                .as_ref()
+                .is_some_and(|player| !player.is_finished())
+    }
+
+    /// Returns true once every event is played and the synth fell silent
+    pub fn is_finished(&self) -> bool {
+        !self.is_playing() && self.synth.is_idle()
+    }
+
+    /// Fill the interleaved `buffer`
+    pub fn render_audio<S: Sample>(&mut self, buffer: &mut [S]) {
+        let channels = usize::from(self.channels.max(1));
+        let frames = buffer.len() / channels;
+        let mut frame = 0usize;
+        if let Some(control) = self.control.as_ref() {
+            while let Some(live_event) = control.try_output() {
+                match live_event {
+                    LiveEvent::Midi { channel, midi_msg } => {
+                        self.synth.handle_event(&Event::Midi { channel, midi_msg })
+                    }
+                    LiveEvent::KnownSysex(message) => self.synth.handle_sysex_message(message),
+                    LiveEvent::Sysex(_) | LiveEvent::Realtime(_) => {}
+                }
+            }
+        }
+        if let Some(player) = self.player.as_mut() {
+            let start = player.position();
+            for timed_event in player.play(frames) {
+                let until = (timed_event.sample - start) as usize;
+                for out in buffer[frame * channels..until * channels].chunks_mut(channels) {
+                    write_frame(out, self.synth.render_frame(), &mut self.quantizer);
+                }
+                frame = frame.max(until);
+                self.synth.handle_event(&timed_event.event);
+            }
+        }
+        for out in buffer[frame * channels..].chunks_mut(channels) {
+            write_frame(out, self.synth.render_frame(), &mut self.quantizer);
+        }
     }
+}
 
-    fn render_audio<S: Sample>(&mut self, buffer: &mut [S]) {
-        for s in buffer.iter_mut() {
-            *s = S::from::<f32>(&0.0);
+/// Mono gets the average, more than two channels get silence beyond left and right
+fn write_frame<S: Sample>(out: &mut [S], [left, right]: [f32; 2], quantizer: &mut Quantizer) {
+    match out.len() {
+        1 => out[0] = convert(0.5 * (left + right), quantizer),
+        _ => {
+            for (i, s) in out.iter_mut().enumerate() {
+                *s = convert(
+                    match i {
+                        0 => left,
+                        1 => right,
+                        _ => 0.0,
+                    },
+                    quantizer,
+                );
+            }
         }
     }
 }
 
-struct AudioOut {
+/// Float passes through, integer formats are dithered and clipped to 16 bits
+fn convert<S: Sample>(value: f32, quantizer: &mut Quantizer) -> S {
+    match S::FORMAT {
+        SampleFormat::F32 => S::from::<f32>(&value),
+        SampleFormat::I16 | SampleFormat::U16 => {
+            S::from::<i16>(&(quantizer.quantize(value, 16) as i16))
+        }
+    }
+}
+
+pub struct AudioOut {
     renderer: AudioRenderer,
 }
 
 impl AudioOut {
-    fn start_stream(self, output_stream_params: OutputStreamParams) -> Stream {
+    pub fn new(renderer: AudioRenderer) -> Self {
+        Self { renderer }
+    }
+
+    pub fn start_stream(
+        self,
+        output_stream_params: OutputStreamParams,
+    ) -> Result<Stream, AudioError> {
         let OutputStreamParams {
             output_device,
             stream_config,
@@ -53,44 +213,90 @@ impl AudioOut {
         } = output_stream_params;
 
         let stream = match sample_format {
-            SampleFormat::I16 => panic!("I16 sample format not supported"),
-            SampleFormat::U16 => panic!("U16 sample format not supported"),
+            SampleFormat::I16 => self.create_stream::<i16>(&output_device, &stream_config),
+            SampleFormat::U16 => self.create_stream::<u16>(&output_device, &stream_config),
             SampleFormat::F32 => self.create_stream::<f32>(&output_device, &stream_config),
-        };
-        stream.play().unwrap();
-        stream
-    }
-
-    fn create_stream<S: Sample>(mut self, device: &Device, config: &StreamConfig) -> Stream {
-        device
-            .build_output_stream(
-                config,
-                move |buffer: &mut [S], _| {
-                    self.renderer.render_audio(buffer);
-                },
-                |err| eprintln!("{}", err),
-            )
-            .unwrap()
+        }?;
+        stream.play()?;
+        Ok(stream)
+    }
+
+    fn create_stream<S: Sample>(
+        mut self,
+        device: &Device,
+        config: &StreamConfig,
+    ) -> Result<Stream, AudioError> {
+        Ok(device.build_output_stream(
+            config,
+            move |buffer: &mut [S], _| {
+                self.renderer.render_audio(buffer);
+            },
+            |err| eprintln!("{}", err),
+        )?)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::controller::Controller;
     use std::{thread, time::Duration};
     #[test]
     fn default_channel_is_2() {
-        let audio = OutputStreamParams::default();
+        let audio = OutputStreamParams::new().unwrap();
         assert_eq!(audio.stream_config.channels, 2);
     }
 
     #[test]
     fn audio_out_start_stream() {
-        let audio_out = AudioOut {
-            renderer: AudioRenderer::new(),
-        };
-        let stream = audio_out.start_stream(OutputStreamParams::default());
+        let params = OutputStreamParams::new().unwrap();
+        let audio_out = AudioOut::new(AudioRenderer::new(params.sample_rate(), params.channels()));
+        let stream = audio_out.start_stream(params).unwrap();
         thread::sleep(Duration::from_millis(100));
         stream.pause().unwrap();
     }
+
+    fn render<S: Sample + Copy + Default>(channels: u16) -> Vec<S> {
+        let mut renderer = AudioRenderer::new(1000, channels);
+        renderer.set_dither(Dither::None);
+        let mut buffer = vec![S::default(); 64 * usize::from(channels)];
+        renderer.render_audio(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn renders_every_sample_format() {
+        // silence maps to the zero of each format
+        assert!(render::<f32>(2).iter().all(|s| *s == 0.0));
+        assert!(render::<i16>(1).iter().all(|s| *s == 0));
+        assert!(render::<u16>(3).iter().all(|s| *s == 32768));
+    }
+
+    #[test]
+    fn live_input_plays() {
+        let control = MidiControl::new();
+        control.get_connect().send(vec![0x90, 69, 127]).unwrap();
+        let mut renderer = AudioRenderer::with_control(control, 1000, 1);
+        let mut buffer = vec![0f32; 64];
+        // the decoder runs on a thread of its own
+        for _ in 0..100 {
+            renderer.render_audio(&mut buffer);
+            if buffer.iter().any(|s| *s != 0.0) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(buffer.iter().any(|s| *s != 0.0));
+        assert!(renderer.is_playing());
+    }
+
+    #[test]
+    fn integer_conversion_rounds_and_clips() {
+        let mut quantizer = Quantizer::new(Dither::None);
+        assert_eq!(convert::<i16>(1.5, &mut quantizer), i16::MAX);
+        assert_eq!(convert::<i16>(-0.5, &mut quantizer), -16384);
+        assert_eq!(convert::<u16>(0.0, &mut quantizer), 32768);
+        assert_eq!(convert::<u16>(-1.0, &mut quantizer), 1);
+        assert_eq!(convert::<f32>(0.25, &mut quantizer), 0.25);
+    }
 }
@@ -0,0 +1,92 @@
+/// Noise added before rounding to an integer sample format
+///
+/// Dither trades the harmonic distortion of plain rounding for a constant noise floor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Plain rounding
+    None,
+    /// Uniform noise of 1 LSB peak-to-peak
+    Rectangular,
+    /// Triangular noise of 2 LSB peak-to-peak (TPDF)
+    Triangular,
+}
+
+/// Converts `[-1.0, 1.0]` samples to signed integers of a given bit depth
+#[derive(Debug, Clone)]
+pub struct Quantizer {
+    dither: Dither,
+    /// xorshift state, fixed so renders stay reproducible
+    seed: u32,
+}
+
+impl Quantizer {
+    pub fn new(dither: Dither) -> Self {
+        Self {
+            dither,
+            seed: 0x2545_F491,
+        }
+    }
+
+    pub fn dither(&self) -> Dither {
+        self.dither
+    }
+
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.dither = dither;
+    }
+
+    /// Uniform noise in `[-0.5, 0.5)`
+    fn uniform(&mut self) -> f32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        (x >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+
+    /// Quantize `sample` to `bits` bits, clipping at full scale
+    pub fn quantize(&mut self, sample: f32, bits: u32) -> i32 {
+        let full_scale = ((1i64 << (bits - 1)) - 1) as f32;
+        let noise = match self.dither {
+            Dither::None => 0.0,
+            Dither::Rectangular => self.uniform(),
+            Dither::Triangular => self.uniform() + self.uniform(),
+        };
+        let value = (sample.clamp(-1.0, 1.0) * full_scale + noise).round();
+        value.clamp(-full_scale - 1.0, full_scale) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_rounding() {
+        let mut quantizer = Quantizer::new(Dither::None);
+        assert_eq!(quantizer.quantize(1.0, 16), 32767);
+        assert_eq!(quantizer.quantize(-2.0, 16), -32767);
+        assert_eq!(quantizer.quantize(0.5, 8), 64);
+        assert_eq!(quantizer.quantize(0.5, 24), 4_194_304);
+    }
+
+    #[test]
+    fn dither_decorrelates_small_signals() {
+        // a quarter LSB rounds to 0 without dither
+        let level = 0.25 / 32767.0;
+        let mut quantizer = Quantizer::new(Dither::None);
+        assert!((0..1000).all(|_| quantizer.quantize(level, 16) == 0));
+        for dither in [Dither::Rectangular, Dither::Triangular] {
+            let mut quantizer = Quantizer::new(dither);
+            let values = (0..100_000)
+                .map(|_| quantizer.quantize(level, 16))
+                .collect::<Vec<_>>();
+            let peak = if dither == Dither::Rectangular { 1 } else { 2 };
+            assert!(values.iter().all(|v| v.abs() <= peak));
+            // the mean keeps the signal below one LSB
+            let mean = values.iter().sum::<i32>() as f32 / values.len() as f32;
+            assert!((mean - 0.25).abs() < 0.02, "{:?} {}", dither, mean);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use super::{delay_line::DelayLine, Effect};
+use crate::{
+    modulation::lfo::{Lfo, LfoParams, LfoRate, LfoShape},
+    osc::OscKind,
+};
+
+/// Longest delay the LFO can sweep to in seconds, the buffers are allocated for it up front
+pub const MAX_DELAY: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChorusParams {
+    pub rate: LfoRate,
+    /// centre delay in seconds
+    pub delay: f32,
+    /// seconds the LFO sweeps the delay above and below `delay`
+    pub depth: f32,
+    /// `[-0.95, 0.95]`, delayed output fed back into the line
+    pub feedback: f32,
+    /// `[0, 1]`, phase between the left and right LFOs, half a cycle at 1
+    pub spread: f32,
+    /// `[0, 1]`, dry to wet
+    pub mix: f32,
+}
+
+impl Default for ChorusParams {
+    /// A wide chorus for a send bus, wet only
+    fn default() -> Self {
+        Self {
+            rate: LfoRate::Hz(0.8),
+            delay: 0.012,
+            depth: 0.003,
+            feedback: 0.0,
+            spread: 1.0,
+            mix: 1.0,
+        }
+    }
+}
+
+impl ChorusParams {
+    /// A flanger is a chorus with a delay short enough to comb the spectrum, and feedback
+    pub fn flanger() -> Self {
+        Self {
+            rate: LfoRate::Beats(8.0),
+            delay: 0.0025,
+            depth: 0.002,
+            feedback: 0.7,
+            spread: 0.25,
+            mix: 0.5,
+        }
+    }
+}
+
+/// Chorus or flanger, a delay line per side swept by an LFO
+#[derive(Debug, Clone)]
+pub struct Chorus {
+    params: ChorusParams,
+    sample_rate: f32,
+    lines: [DelayLine; 2],
+    lfos: [Lfo; 2],
+}
+
+impl Chorus {
+    pub fn new(params: ChorusParams, sample_rate: f32) -> Self {
+        let line = DelayLine::new((MAX_DELAY * sample_rate).ceil() as usize);
+        let mut chorus = Self {
+            params,
+            sample_rate,
+            lines: [line.clone(), line],
+            lfos: [Lfo::new(sample_rate), Lfo::new(sample_rate)],
+        };
+        chorus.set_params(params);
+        chorus
+    }
+
+    pub fn params(&self) -> &ChorusParams {
+        &self.params
+    }
+
+    /// Restarts the LFOs
+    pub fn set_params(&mut self, params: ChorusParams) {
+        let params = ChorusParams {
+            delay: params.delay.clamp(0.0, MAX_DELAY),
+            feedback: params.feedback.clamp(-0.95, 0.95),
+            spread: params.spread.clamp(0.0, 1.0),
+            mix: params.mix.clamp(0.0, 1.0),
+            ..params
+        };
+        self.params = params;
+        for (lfo, phase) in self.lfos.iter_mut().zip([0.0, params.spread * 0.5]) {
+            lfo.start(LfoParams {
+                shape: LfoShape::Wave(OscKind::Sine),
+                rate: params.rate,
+                phase,
+            });
+        }
+    }
+}
+
+impl Effect for Chorus {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let ChorusParams {
+            delay,
+            depth,
+            feedback,
+            mix,
+            ..
+        } = self.params;
+        let mut out = [0.0; 2];
+        for (i, (line, lfo)) in self.lines.iter_mut().zip(&mut self.lfos).enumerate() {
+            let delay = (delay + depth * lfo.next_sample()) * self.sample_rate;
+            // the last sample written is already one sample old
+            let wet = line.read(delay - 1.0);
+            line.write(frame[i] + wet * feedback);
+            out[i] = frame[i] + (wet - frame[i]) * mix;
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.lines.iter_mut().for_each(DelayLine::reset);
+    }
+
+    fn silent_gap(&self) -> f32 {
+        self.params.delay + self.params.depth
+    }
+
+    fn set_tempo(&mut self, quarter_seconds: f32) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_tempo(quarter_seconds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    #[test]
+    fn delay_follows_the_tempo() {
+        let mut chorus = Chorus::new(
+            ChorusParams {
+                rate: LfoRate::Beats(1.0),
+                delay: 0.01,
+                depth: 0.005,
+                ..ChorusParams::default()
+            },
+            SAMPLE_RATE,
+        );
+        // 240 quarter notes a minute, a cycle every 250 samples
+        chorus.set_tempo(0.25);
+        // linear interpolation is exact on a ramp, so the output gives back the delay
+        let delays = (0..1000)
+            .map(|n| {
+                let n = n as f32;
+                let [l, r] = chorus.process([n; 2]);
+                [n - l, n - r]
+            })
+            .skip(20)
+            .collect::<Vec<_>>();
+        let max = delays.iter().map(|d| d[0]).fold(0.0, f32::max);
+        let min = delays.iter().map(|d| d[0]).fold(100.0, f32::min);
+        assert!((max - 15.0).abs() < 0.01 && (min - 5.0).abs() < 0.01);
+        for (now, later) in delays.iter().zip(&delays[250..]) {
+            assert!((now[0] - later[0]).abs() < 1e-2);
+            // the right side sweeps the other way
+            assert!((now[0] + now[1] - 20.0).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn flanger_feedback_repeats() {
+        let mut flanger = Chorus::new(
+            ChorusParams {
+                rate: LfoRate::Hz(0.0),
+                delay: 0.004,
+                depth: 0.0,
+                feedback: 0.5,
+                spread: 0.0,
+                mix: 1.0,
+            },
+            SAMPLE_RATE,
+        );
+        let response = (0..13)
+            .map(|n| flanger.process(if n == 0 { [1.0, 0.5] } else { [0.0; 2] }))
+            .collect::<Vec<_>>();
+        assert_eq!(response[4], [1.0, 0.5]);
+        assert_eq!(response[8], [0.5, 0.25]);
+        assert_eq!(response[12], [0.25, 0.125]);
+        assert_eq!(response[5], [0.0; 2]);
+        flanger.reset();
+        assert_eq!(flanger.process([0.0; 2]), [0.0; 2]);
+    }
+}
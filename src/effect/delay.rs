@@ -0,0 +1,303 @@
+use super::{delay_line::DelayLine, Effect};
+use crate::{
+    filter::{biquad::Biquad, FilterKind, FilterParams},
+    modulation::lfo::DEFAULT_QUARTER_SECONDS,
+};
+
+/// Longest delay in seconds, the buffers are allocated for it up front
+pub const MAX_DELAY: f32 = 4.0;
+
+/// Time constant the delay glides to a new time with, so tempo changes do not click
+const GLIDE_TIME: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelayTime {
+    Seconds(f32),
+    /// Note value in quarter notes following the tempo, `0.75` is a dotted eighth
+    Beats(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelayParams {
+    pub time: DelayTime,
+    /// `[0, 0.95]`, level of each repeat relative to the one before
+    pub feedback: f32,
+    /// Repeats alternate between left and right, starting left
+    pub ping_pong: bool,
+    /// Hz, the repeats lose lows below and highs above these
+    pub low_cut: f32,
+    pub high_cut: f32,
+    /// `[0, 1]`, dry to wet
+    pub mix: f32,
+}
+
+impl Default for DelayParams {
+    /// Dotted eighth echoes
+    fn default() -> Self {
+        Self {
+            time: DelayTime::Beats(0.75),
+            feedback: 0.4,
+            ping_pong: false,
+            low_cut: 100.0,
+            high_cut: 6000.0,
+            mix: 0.3,
+        }
+    }
+}
+
+/// Stereo echo with filtered feedback
+#[derive(Debug, Clone)]
+pub struct Delay {
+    params: DelayParams,
+    sample_rate: f32,
+    quarter_seconds: f32,
+    lines: [DelayLine; 2],
+    /// high and low pass of each side in the feedback path
+    filters: [[Biquad; 2]; 2],
+    /// samples, `delay` glides to `target`
+    delay: f32,
+    target: f32,
+    glide: f32,
+}
+
+impl Delay {
+    pub fn new(params: DelayParams, sample_rate: f32) -> Self {
+        let line = DelayLine::new((MAX_DELAY * sample_rate).ceil() as usize);
+        let filter = |kind| {
+            Biquad::new(
+                FilterParams {
+                    kind,
+                    ..FilterParams::default()
+                },
+                sample_rate,
+            )
+        };
+        let filters = [filter(FilterKind::HighPass), filter(FilterKind::LowPass)];
+        let mut delay = Self {
+            params,
+            sample_rate,
+            quarter_seconds: DEFAULT_QUARTER_SECONDS,
+            lines: [line.clone(), line],
+            filters: [filters.clone(), filters],
+            delay: 0.0,
+            target: 0.0,
+            glide: (-1.0 / (GLIDE_TIME * sample_rate)).exp(),
+        };
+        delay.set_params(params);
+        delay.delay = delay.target;
+        delay
+    }
+
+    pub fn params(&self) -> &DelayParams {
+        &self.params
+    }
+
+    /// A new time glides in
+    pub fn set_params(&mut self, params: DelayParams) {
+        let params = DelayParams {
+            feedback: params.feedback.clamp(0.0, 0.95),
+            mix: params.mix.clamp(0.0, 1.0),
+            ..params
+        };
+        self.params = params;
+        for [high_pass, low_pass] in self.filters.iter_mut() {
+            high_pass.set_params(FilterParams {
+                cutoff: params.low_cut,
+                ..*high_pass.params()
+            });
+            low_pass.set_params(FilterParams {
+                cutoff: params.high_cut,
+                ..*low_pass.params()
+            });
+        }
+        self.update_target();
+    }
+
+    /// Seconds between repeats at the current tempo
+    pub fn seconds(&self) -> f32 {
+        let seconds = match self.params.time {
+            DelayTime::Seconds(seconds) => seconds,
+            DelayTime::Beats(beats) => beats * self.quarter_seconds,
+        };
+        seconds.clamp(0.0, MAX_DELAY)
+    }
+
+    fn update_target(&mut self) {
+        self.target = (self.seconds() * self.sample_rate).max(1.0);
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, [left, right]: [f32; 2]) -> [f32; 2] {
+        let DelayParams {
+            feedback,
+            ping_pong,
+            mix,
+            ..
+        } = self.params;
+        self.delay = self.target + (self.delay - self.target) * self.glide;
+        // the last sample written is already one sample old
+        let taps = [
+            self.lines[0].read(self.delay - 1.0),
+            self.lines[1].read(self.delay - 1.0),
+        ];
+        let mut repeats = taps;
+        for (repeat, [high_pass, low_pass]) in repeats.iter_mut().zip(&mut self.filters) {
+            *repeat = low_pass.process(high_pass.process(*repeat)) * feedback;
+        }
+        if ping_pong {
+            self.lines[0].write((left + right) * 0.5 + repeats[1]);
+            self.lines[1].write(repeats[0]);
+        } else {
+            self.lines[0].write(left + repeats[0]);
+            self.lines[1].write(right + repeats[1]);
+        }
+        [
+            left + (taps[0] - left) * mix,
+            right + (taps[1] - right) * mix,
+        ]
+    }
+
+    fn reset(&mut self) {
+        self.lines.iter_mut().for_each(DelayLine::reset);
+        self.filters.iter_mut().flatten().for_each(Biquad::reset);
+        self.delay = self.target;
+    }
+
+    fn set_tempo(&mut self, quarter_seconds: f32) {
+        self.quarter_seconds = quarter_seconds.max(1e-3);
+        self.update_target();
+    }
+
+    /// Repeats are apart by the delay time, and it may still be gliding from a longer one
+    fn silent_gap(&self) -> f32 {
+        self.delay.max(self.target) / self.sample_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    /// Echoes without filtering
+    fn wide_open(params: DelayParams) -> DelayParams {
+        DelayParams {
+            low_cut: 10.0,
+            high_cut: 490.0,
+            mix: 1.0,
+            ..params
+        }
+    }
+
+    fn impulse_response(delay: &mut Delay, impulse: [f32; 2], frames: usize) -> Vec<[f32; 2]> {
+        (0..frames)
+            .map(|n| delay.process(if n == 0 { impulse } else { [0.0; 2] }))
+            .collect()
+    }
+
+    #[test]
+    fn note_values_follow_the_tempo() {
+        let mut delay = Delay::new(
+            wide_open(DelayParams {
+                time: DelayTime::Beats(0.5),
+                feedback: 0.0,
+                ..DelayParams::default()
+            }),
+            SAMPLE_RATE,
+        );
+        let response = impulse_response(&mut delay, [1.0; 2], 400);
+        assert_eq!(response[250], [1.0; 2]);
+        // 150 quarter notes a minute, the eighth glides to 200 ms
+        delay.set_tempo(0.4);
+        assert_eq!(delay.seconds(), 0.2);
+        for _ in 0..1000 {
+            delay.process([0.0; 2]);
+        }
+        let response = impulse_response(&mut delay, [1.0; 2], 400);
+        assert!((response[200][0] - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tempo_changes_glide() {
+        let mut delay = Delay::new(
+            wide_open(DelayParams {
+                time: DelayTime::Beats(0.25),
+                feedback: 0.0,
+                ..DelayParams::default()
+            }),
+            SAMPLE_RATE,
+        );
+        // a ramp comes back delayed, a jump in the delay would jump the output
+        let mut last = 0.0;
+        for n in 0..2000 {
+            if n == 1000 {
+                delay.set_tempo(0.3);
+            }
+            let [out, _] = delay.process([n as f32; 2]);
+            // a glide only bends the ramp, a jump in the delay would step it
+            if n > 200 {
+                assert!(
+                    (-1e-3..2.001).contains(&(out - last)),
+                    "{} {}",
+                    n,
+                    out - last
+                );
+            }
+            last = out;
+        }
+    }
+
+    #[test]
+    fn ping_pong_alternates() {
+        let mut delay = Delay::new(
+            wide_open(DelayParams {
+                time: DelayTime::Seconds(0.1),
+                feedback: 0.5,
+                ping_pong: true,
+                ..DelayParams::default()
+            }),
+            SAMPLE_RATE,
+        );
+        let response = impulse_response(&mut delay, [1.0, 0.0], 400);
+        let level = |n: usize| {
+            response[n - 5..n + 5].iter().fold([0f32; 2], |peak, f| {
+                [peak[0].max(f[0].abs()), peak[1].max(f[1].abs())]
+            })
+        };
+        let [first, second, third] = [level(100), level(200), level(300)];
+        // the filters smear each repeat a little
+        assert_eq!(first, [0.5, 0.0]);
+        assert!(second[0] < 1e-3 && (second[1] - 0.25).abs() < 0.05);
+        assert!((third[0] - 0.125).abs() < 0.03 && third[1] < 1e-3);
+    }
+
+    #[test]
+    fn repeats_are_filtered() {
+        let energy = |params: DelayParams| {
+            let mut delay = Delay::new(
+                DelayParams {
+                    time: DelayTime::Seconds(0.05),
+                    feedback: 0.9,
+                    mix: 1.0,
+                    ..params
+                },
+                SAMPLE_RATE,
+            );
+            let response = impulse_response(&mut delay, [1.0; 2], 1000);
+            // the first repeat is the input itself, the rest went through the filters
+            let first = response[45..55].iter().map(|f| f[0] * f[0]).sum::<f32>();
+            let rest = response[95..].iter().map(|f| f[0] * f[0]).sum::<f32>();
+            (first, rest)
+        };
+        let open = energy(wide_open(DelayParams::default()));
+        let band = energy(DelayParams {
+            low_cut: 150.0,
+            high_cut: 250.0,
+            ..DelayParams::default()
+        });
+        assert_eq!(open.0, band.0);
+        assert!(band.1 < open.1 * 0.5, "{:?} {:?}", band, open);
+    }
+}
@@ -0,0 +1,65 @@
+/// Circular buffer read at fractional delays
+///
+/// The buffer is allocated once in `new`, reading and writing never allocate.
+#[derive(Debug, Clone)]
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    /// index of the next write
+    position: usize,
+}
+
+impl DelayLine {
+    /// Delays up to `max_delay` samples can be read
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 2],
+            position: 0,
+        }
+    }
+
+    /// Longest delay in samples
+    pub fn max_delay(&self) -> f32 {
+        (self.buffer.len() - 2) as f32
+    }
+
+    pub fn write(&mut self, sample: f32) {
+        self.buffer[self.position] = sample;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+
+    /// Sample written `delay` samples before the last one, linearly interpolated
+    pub fn read(&self, delay: f32) -> f32 {
+        let delay = delay.clamp(0.0, self.max_delay());
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        let len = self.buffer.len();
+        let newer = self.buffer[(self.position + len - 1 - whole) % len];
+        let older = self.buffer[(self.position + len - 2 - whole) % len];
+        newer + (older - newer) * fraction
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractional_reads() {
+        let mut line = DelayLine::new(4);
+        for sample in [1.0, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            line.write(sample);
+        }
+        assert_eq!(line.read(0.0), 6.0);
+        assert_eq!(line.read(2.0), 4.0);
+        assert_eq!(line.read(1.25), 4.75);
+        assert_eq!(line.read(4.0), 2.0);
+        // longer delays are clamped
+        assert_eq!(line.read(10.0), 2.0);
+        line.reset();
+        assert_eq!(line.read(1.0), 0.0);
+    }
+}
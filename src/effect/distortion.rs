@@ -0,0 +1,393 @@
+use std::f32::consts::PI;
+
+use super::{
+    delay_line::DelayLine,
+    oversample::{Oversampler, Oversampling, MAX_LATENCY},
+    Effect,
+};
+
+/// Input offset of the tube curve, it clips the positive side harder
+const TUBE_BIAS: f32 = 0.3;
+/// Hz, the DC blocker removes the offset the tube curve adds
+const DC_CUTOFF: f32 = 10.0;
+
+/// Waveshaping curve, each keeps `[-1, 1]` for any input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// tanh
+    SoftClip,
+    HardClip,
+    /// Reflects back from ±1 instead of clipping
+    Foldback,
+    /// Asymmetric soft clip, adds even harmonics
+    Tube,
+}
+
+impl Shape {
+    pub fn apply(self, x: f32) -> f32 {
+        match self {
+            Self::SoftClip => x.tanh(),
+            Self::HardClip => x.clamp(-1.0, 1.0),
+            Self::Foldback => 1.0 - ((x + 1.0).rem_euclid(4.0) - 2.0).abs(),
+            Self::Tube => ((x + TUBE_BIAS).tanh() - TUBE_BIAS.tanh()) / (1.0 + TUBE_BIAS.tanh()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistortionParams {
+    pub shape: Shape,
+    /// dB of gain into the curve
+    pub drive: f32,
+    /// dB of gain after it
+    pub output: f32,
+    /// `[0, 1]`, dry to wet
+    pub mix: f32,
+    pub oversampling: Oversampling,
+}
+
+impl Default for DistortionParams {
+    fn default() -> Self {
+        Self {
+            shape: Shape::SoftClip,
+            drive: 12.0,
+            output: -6.0,
+            mix: 1.0,
+            oversampling: Oversampling::X4,
+        }
+    }
+}
+
+/// One pole high pass, removes DC
+#[derive(Debug, Clone, Copy)]
+struct DcBlocker {
+    pole: f32,
+    input: f32,
+    output: f32,
+}
+
+impl DcBlocker {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            pole: (-2.0 * PI * DC_CUTOFF / sample_rate).exp(),
+            input: 0.0,
+            output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.output = input - self.input + self.pole * self.output;
+        self.input = input;
+        self.output
+    }
+
+    fn reset(&mut self) {
+        self.input = 0.0;
+        self.output = 0.0;
+    }
+}
+
+/// Oversampled waveshaper
+///
+/// The dry signal is delayed by the oversampler latency so a partial mix does not comb filter.
+#[derive(Debug, Clone)]
+pub struct Distortion {
+    params: DistortionParams,
+    oversamplers: [Oversampler; 2],
+    dc_blockers: [DcBlocker; 2],
+    dry: [DelayLine; 2],
+    /// samples, of the oversamplers
+    latency: f32,
+    drive: f32,
+    output: f32,
+}
+
+impl Distortion {
+    pub fn new(params: DistortionParams, sample_rate: f32) -> Self {
+        let oversampler = Oversampler::new(params.oversampling);
+        let mut distortion = Self {
+            params,
+            latency: oversampler.process_latency(),
+            oversamplers: [oversampler.clone(), oversampler],
+            dc_blockers: [DcBlocker::new(sample_rate); 2],
+            dry: [DelayLine::new(MAX_LATENCY), DelayLine::new(MAX_LATENCY)],
+            drive: 1.0,
+            output: 1.0,
+        };
+        distortion.set_params(params);
+        distortion
+    }
+
+    pub fn params(&self) -> &DistortionParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: DistortionParams) {
+        let params = DistortionParams {
+            mix: params.mix.clamp(0.0, 1.0),
+            ..params
+        };
+        if params.oversampling != self.params.oversampling {
+            for oversampler in self.oversamplers.iter_mut() {
+                oversampler.set_oversampling(params.oversampling);
+            }
+            self.latency = self.oversamplers[0].process_latency();
+        }
+        self.params = params;
+        self.drive = 10f32.powf(params.drive / 20.0);
+        self.output = 10f32.powf(params.output / 20.0);
+    }
+}
+
+impl Effect for Distortion {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let DistortionParams { shape, mix, .. } = self.params;
+        let drive = self.drive;
+        let mut out = [0.0; 2];
+        for (i, ((oversampler, dc_blocker), line)) in self
+            .oversamplers
+            .iter_mut()
+            .zip(&mut self.dc_blockers)
+            .zip(&mut self.dry)
+            .enumerate()
+        {
+            let shaped = oversampler.process(frame[i], |x| shape.apply(x * drive));
+            let wet = dc_blocker.process(shaped) * self.output;
+            line.write(frame[i]);
+            let dry = line.read(self.latency);
+            out[i] = dry + (wet - dry) * mix;
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.oversamplers.iter_mut().for_each(Oversampler::reset);
+        self.dc_blockers.iter_mut().for_each(DcBlocker::reset);
+        self.dry.iter_mut().for_each(DelayLine::reset);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitcrusherParams {
+    /// `[1, 24]`, fractional depths are allowed
+    pub bits: f32,
+    /// Hz the output is held at, at or above the sample rate it is not reduced
+    pub rate: f32,
+    /// `[0, 1]`, dry to wet
+    pub mix: f32,
+    /// for the quantizer, the rate reduction aliases on purpose
+    pub oversampling: Oversampling,
+}
+
+impl Default for BitcrusherParams {
+    fn default() -> Self {
+        Self {
+            bits: 8.0,
+            rate: 11_025.0,
+            mix: 1.0,
+            oversampling: Oversampling::X2,
+        }
+    }
+}
+
+/// Bit depth and sample rate reduction
+///
+/// The dry signal is delayed by the oversampler latency like in [`Distortion`].
+#[derive(Debug, Clone)]
+pub struct Bitcrusher {
+    params: BitcrusherParams,
+    sample_rate: f32,
+    oversamplers: [Oversampler; 2],
+    dry: [DelayLine; 2],
+    /// samples, of the oversamplers
+    latency: f32,
+    /// quantization levels on each side of zero
+    levels: f32,
+    /// held frame and the phase of the reduced rate, a new frame is taken when it wraps
+    held: [f32; 2],
+    phase: f32,
+}
+
+impl Bitcrusher {
+    pub fn new(params: BitcrusherParams, sample_rate: f32) -> Self {
+        let oversampler = Oversampler::new(params.oversampling);
+        let mut bitcrusher = Self {
+            params,
+            sample_rate,
+            latency: oversampler.process_latency(),
+            oversamplers: [oversampler.clone(), oversampler],
+            dry: [DelayLine::new(MAX_LATENCY), DelayLine::new(MAX_LATENCY)],
+            levels: 1.0,
+            held: [0.0; 2],
+            phase: 1.0,
+        };
+        bitcrusher.set_params(params);
+        bitcrusher
+    }
+
+    pub fn params(&self) -> &BitcrusherParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: BitcrusherParams) {
+        let params = BitcrusherParams {
+            bits: params.bits.clamp(1.0, 24.0),
+            rate: params.rate.max(1.0),
+            mix: params.mix.clamp(0.0, 1.0),
+            ..params
+        };
+        if params.oversampling != self.params.oversampling {
+            for oversampler in self.oversamplers.iter_mut() {
+                oversampler.set_oversampling(params.oversampling);
+            }
+            self.latency = self.oversamplers[0].process_latency();
+        }
+        self.params = params;
+        self.levels = (params.bits - 1.0).exp2();
+    }
+}
+
+impl Effect for Bitcrusher {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let levels = self.levels;
+        let mut crushed = [0.0; 2];
+        for (i, oversampler) in self.oversamplers.iter_mut().enumerate() {
+            crushed[i] = oversampler.process(frame[i], |x| (x * levels).round() / levels);
+        }
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.held = crushed;
+        }
+        self.phase += (self.params.rate / self.sample_rate).min(1.0);
+        let mix = self.params.mix;
+        let mut out = [0.0; 2];
+        for (i, line) in self.dry.iter_mut().enumerate() {
+            line.write(frame[i]);
+            let dry = line.read(self.latency);
+            out[i] = dry + (self.held[i] - dry) * mix;
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.oversamplers.iter_mut().for_each(Oversampler::reset);
+        self.dry.iter_mut().for_each(DelayLine::reset);
+        self.held = [0.0; 2];
+        self.phase = 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effect::oversample::tests::{aliasing, spectrum, SAMPLE_RATE};
+
+    /// A 4.8 kHz sine, its fifth harmonic is the first above Nyquist
+    const BIN: usize = 205;
+
+    fn distortion(shape: Shape, oversampling: Oversampling) -> Distortion {
+        Distortion::new(
+            DistortionParams {
+                shape,
+                drive: 12.0,
+                output: 0.0,
+                mix: 1.0,
+                oversampling,
+            },
+            SAMPLE_RATE,
+        )
+    }
+
+    fn distorted(distortion: &mut Distortion) -> Vec<f32> {
+        spectrum(BIN, 1.0, |x| distortion.process([x; 2])[0])
+    }
+
+    #[test]
+    fn curves_stay_in_range() {
+        for shape in [
+            Shape::SoftClip,
+            Shape::HardClip,
+            Shape::Foldback,
+            Shape::Tube,
+        ] {
+            for x in (-100..=100).map(|x| x as f32 * 0.1) {
+                assert!(shape.apply(x).abs() <= 1.0, "{:?} {}", shape, x);
+            }
+            assert_eq!(shape.apply(0.0), 0.0);
+        }
+        assert_eq!(Shape::Foldback.apply(1.5), 0.5);
+        assert_eq!(Shape::Foldback.apply(-3.5), 0.5);
+        assert!(Shape::Tube.apply(2.0) < Shape::Tube.apply(-2.0).abs());
+    }
+
+    #[test]
+    fn oversampling_reduces_aliasing() {
+        for shape in [Shape::HardClip, Shape::Foldback] {
+            let levels = [
+                Oversampling::Off,
+                Oversampling::X2,
+                Oversampling::X4,
+                Oversampling::X8,
+            ]
+            .map(|oversampling| aliasing(&distorted(&mut distortion(shape, oversampling)), BIN));
+            for pair in levels.windows(2) {
+                assert!(pair[1] < pair[0], "{:?} {:?}", shape, levels);
+            }
+            // at least 20 dB less
+            assert!(levels[3] < levels[0] * 0.01, "{:?} {:?}", shape, levels);
+        }
+    }
+
+    #[test]
+    fn tube_adds_even_harmonics() {
+        let soft = distorted(&mut distortion(Shape::SoftClip, Oversampling::X4));
+        let tube = distorted(&mut distortion(Shape::Tube, Oversampling::X4));
+        assert!(soft[BIN * 2] < 1e-6);
+        assert!(tube[BIN * 2] > 1e-3);
+        assert!(tube[BIN * 3] > 1e-3 && soft[BIN * 3] > 1e-3);
+    }
+
+    #[test]
+    fn dry_is_delayed_with_the_wet() {
+        let mut distortion = Distortion::new(
+            DistortionParams {
+                shape: Shape::SoftClip,
+                drive: -20.0,
+                output: 20.0,
+                mix: 0.5,
+                oversampling: Oversampling::X4,
+            },
+            SAMPLE_RATE,
+        );
+        let latency = distortion.oversamplers[0].process_latency();
+        // a quiet 1 kHz sine, tanh barely bends it
+        let sine = |n: f32| 0.5 * (2.0 * PI * 1000.0 * n / SAMPLE_RATE).sin();
+        for n in 0..4800 {
+            let [out, _] = distortion.process([sine(n as f32); 2]);
+            if n > 480 {
+                let dry = sine(n as f32 - latency);
+                assert!((out - dry).abs() < 0.01, "{} {} {}", n, out, dry);
+            }
+        }
+    }
+
+    #[test]
+    fn bitcrusher_quantizes_and_holds() {
+        let mut bitcrusher = Bitcrusher::new(
+            BitcrusherParams {
+                bits: 3.0,
+                rate: SAMPLE_RATE / 4.0,
+                mix: 1.0,
+                oversampling: Oversampling::Off,
+            },
+            SAMPLE_RATE,
+        );
+        let out = (0..64)
+            .map(|n| bitcrusher.process([n as f32 / 64.0; 2])[0])
+            .collect::<Vec<_>>();
+        // four levels above zero, a new one every fourth sample
+        for (n, y) in out.iter().enumerate() {
+            assert_eq!(*y, ((n - n % 4) as f32 / 16.0).round() / 4.0, "{}", n);
+        }
+    }
+}
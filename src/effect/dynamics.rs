@@ -0,0 +1,543 @@
+use std::collections::VecDeque;
+
+use super::{
+    delay_line::DelayLine,
+    oversample::{Oversampler, Oversampling},
+    Effect,
+};
+
+/// Longest limiter look-ahead in seconds, the buffers are allocated for it up front
+pub const MAX_LOOKAHEAD: f32 = 0.02;
+
+/// Level the compressor detector treats as silence
+const FLOOR_DB: f32 = -120.0;
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-6).log10()
+}
+
+/// Coefficient of a one pole smoother reaching 63 % of a step after `seconds`
+fn coefficient(seconds: f32, sample_rate: f32) -> f32 {
+    if seconds > 0.0 {
+        (-1.0 / (seconds * sample_rate)).exp()
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorParams {
+    /// dBFS the gain reduction starts at
+    pub threshold: f32,
+    /// dB over the threshold in per dB out, 1 leaves the signal alone
+    pub ratio: f32,
+    /// dB wide soft knee centred on the threshold, 0 is a hard knee
+    pub knee: f32,
+    /// seconds
+    pub attack: f32,
+    pub release: f32,
+    /// dB of gain after compression
+    pub makeup: f32,
+}
+
+impl Default for CompressorParams {
+    /// Transparent until a ratio is set
+    fn default() -> Self {
+        Self {
+            threshold: -18.0,
+            ratio: 1.0,
+            knee: 6.0,
+            attack: 0.01,
+            release: 0.1,
+            makeup: 0.0,
+        }
+    }
+}
+
+/// Feed-forward peak compressor
+///
+/// The gain is computed in dB from a key signal, the input itself or a sidechain,
+/// and smoothed with separate attack and release times.
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    params: CompressorParams,
+    sample_rate: f32,
+    attack: f32,
+    release: f32,
+    makeup: f32,
+    /// smoothed gain reduction in dB, never positive
+    reduction: f32,
+}
+
+impl Compressor {
+    pub fn new(params: CompressorParams, sample_rate: f32) -> Self {
+        let mut compressor = Self {
+            params,
+            sample_rate,
+            attack: 0.0,
+            release: 0.0,
+            makeup: 1.0,
+            reduction: 0.0,
+        };
+        compressor.set_params(params);
+        compressor
+    }
+
+    pub fn params(&self) -> &CompressorParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: CompressorParams) {
+        let params = CompressorParams {
+            ratio: params.ratio.max(1.0),
+            knee: params.knee.max(0.0),
+            ..params
+        };
+        self.params = params;
+        self.attack = coefficient(params.attack, self.sample_rate);
+        self.release = coefficient(params.release, self.sample_rate);
+        self.makeup = db_to_gain(params.makeup);
+    }
+
+    /// Current gain reduction in dB, 0 or less
+    pub fn reduction(&self) -> f32 {
+        self.reduction
+    }
+
+    /// Static curve, the output level in dB of a steady input level before makeup
+    pub fn curve(&self, level: f32) -> f32 {
+        let CompressorParams {
+            threshold,
+            ratio,
+            knee,
+            ..
+        } = self.params;
+        let over = level - threshold;
+        if 2.0 * over < -knee {
+            level
+        } else if knee > 0.0 && 2.0 * over.abs() <= knee {
+            let x = over + knee * 0.5;
+            level + (1.0 / ratio - 1.0) * x * x / (2.0 * knee)
+        } else {
+            threshold + over / ratio
+        }
+    }
+
+    /// Compress `frame` by the level of `key`
+    pub fn process_keyed(&mut self, [left, right]: [f32; 2], key: [f32; 2]) -> [f32; 2] {
+        let level = gain_to_db(key[0].abs().max(key[1].abs())).max(FLOOR_DB);
+        let target = self.curve(level) - level;
+        let coefficient = if target < self.reduction {
+            self.attack
+        } else {
+            self.release
+        };
+        self.reduction = target + (self.reduction - target) * coefficient;
+        let gain = db_to_gain(self.reduction) * self.makeup;
+        [left * gain, right * gain]
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        self.process_keyed(frame, frame)
+    }
+
+    fn reset(&mut self) {
+        self.reduction = 0.0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterParams {
+    /// dBTP the output never exceeds, between samples included
+    pub ceiling: f32,
+    /// seconds the gain starts to fall before a peak, up to [`MAX_LOOKAHEAD`]
+    pub lookahead: f32,
+    /// seconds
+    pub release: f32,
+}
+
+impl Default for LimiterParams {
+    fn default() -> Self {
+        Self {
+            ceiling: -0.1,
+            lookahead: 0.005,
+            release: 0.05,
+        }
+    }
+}
+
+/// Look-ahead brickwall limiter with true-peak detection
+///
+/// Peaks are found on the 4× upsampled input. The gain each needs is held for the
+/// look-ahead and averaged over it, so it ramps down smoothly and is fully down when
+/// the delayed peak comes out. The hold is a monotonic queue and the average a running
+/// sum, so a frame costs the same whatever the look-ahead.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    params: LimiterParams,
+    sample_rate: f32,
+    detectors: [Oversampler; 2],
+    lines: [DelayLine; 2],
+    /// gain each detected peak needs and its minimum over the look-ahead, newest at `index`
+    required: Vec<f32>,
+    held: Vec<f32>,
+    index: usize,
+    /// frames processed, and the frame and gain of every required gain that can still
+    /// become the minimum, rising from the front
+    frame: u64,
+    minima: VecDeque<(u64, f32)>,
+    /// sum of `held` over the look-ahead
+    held_sum: f64,
+    /// samples
+    lookahead: usize,
+    delay: usize,
+    ceiling: f32,
+    release: f32,
+    gain: f32,
+}
+
+impl Limiter {
+    pub fn new(params: LimiterParams, sample_rate: f32) -> Self {
+        let detector = Oversampler::new(Oversampling::X4);
+        let max_lookahead = (MAX_LOOKAHEAD * sample_rate).ceil() as usize;
+        let line = DelayLine::new(max_lookahead + detector.latency().ceil() as usize);
+        let mut limiter = Self {
+            params,
+            sample_rate,
+            detectors: [detector.clone(), detector],
+            lines: [line.clone(), line],
+            required: vec![1.0; max_lookahead + 2],
+            held: vec![1.0; max_lookahead + 2],
+            index: 0,
+            frame: 0,
+            minima: VecDeque::with_capacity(max_lookahead + 2),
+            held_sum: 0.0,
+            lookahead: 0,
+            delay: 0,
+            ceiling: 1.0,
+            release: 0.0,
+            gain: 1.0,
+        };
+        limiter.set_params(params);
+        limiter
+    }
+
+    pub fn params(&self) -> &LimiterParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: LimiterParams) {
+        let params = LimiterParams {
+            ceiling: params.ceiling.min(0.0),
+            lookahead: params.lookahead.clamp(0.0, MAX_LOOKAHEAD),
+            ..params
+        };
+        self.params = params;
+        self.lookahead = (params.lookahead * self.sample_rate) as usize;
+        self.delay = self.lookahead + self.detectors[0].latency().ceil() as usize;
+        self.ceiling = db_to_gain(params.ceiling);
+        self.release = coefficient(params.release, self.sample_rate);
+        self.recount();
+    }
+
+    /// Samples the output lags the input by
+    pub fn latency(&self) -> usize {
+        self.delay
+    }
+
+    /// Current gain, 1 when not limiting
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Rebuild the queue and the sum from the history, for a new look-ahead
+    fn recount(&mut self) {
+        let len = self.required.len();
+        let past = |k: usize| (self.index + len - k) % len;
+        self.minima.clear();
+        for k in (1..self.lookahead + 2).rev() {
+            let gain = self.required[past(k)];
+            while self.minima.back().is_some_and(|&(_, min)| min >= gain) {
+                self.minima.pop_back();
+            }
+            self.minima
+                .push_back((self.frame.wrapping_sub(k as u64), gain));
+        }
+        self.held_sum = (1..=self.lookahead + 1)
+            .map(|k| f64::from(self.held[past(k)]))
+            .sum();
+    }
+}
+
+impl Effect for Limiter {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let peak = self.detectors[0]
+            .peak(frame[0])
+            .max(self.detectors[1].peak(frame[1]));
+        let len = self.required.len();
+        let index = self.index;
+        let past = |k: usize| (index + len - k) % len;
+        let required = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+        self.required[index] = required;
+        // one sample more than the ramp, the detector latency is not a whole number
+        let window = self.lookahead as u64 + 2;
+        let now = self.frame;
+        while self
+            .minima
+            .front()
+            .is_some_and(|&(since, _)| now.wrapping_sub(since) >= window)
+        {
+            self.minima.pop_front();
+        }
+        while self.minima.back().is_some_and(|&(_, min)| min >= required) {
+            self.minima.pop_back();
+        }
+        self.minima.push_back((now, required));
+        let held = self.minima.front().map_or(1.0, |&(_, min)| min);
+        self.held_sum += f64::from(held) - f64::from(self.held[past(self.lookahead + 1)]);
+        self.held[index] = held;
+        let target = (self.held_sum / (self.lookahead + 1) as f64) as f32;
+        self.index = (index + 1) % len;
+        self.frame = now.wrapping_add(1);
+        self.gain = if target < self.gain {
+            target
+        } else {
+            target + (self.gain - target) * self.release
+        };
+        let mut out = [0.0; 2];
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            line.write(frame[i]);
+            out[i] = line.read(self.delay as f32) * self.gain;
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.detectors.iter_mut().for_each(Oversampler::reset);
+        self.lines.iter_mut().for_each(DelayLine::reset);
+        self.required.fill(1.0);
+        self.held.fill(1.0);
+        self.gain = 1.0;
+        self.recount();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48_000.0;
+
+    #[test]
+    fn compressor_curve() {
+        let mut compressor = Compressor::new(
+            CompressorParams {
+                threshold: -20.0,
+                ratio: 4.0,
+                knee: 0.0,
+                ..CompressorParams::default()
+            },
+            SAMPLE_RATE,
+        );
+        assert_eq!(compressor.curve(-30.0), -30.0);
+        assert_eq!(compressor.curve(-10.0), -17.5);
+        compressor.set_params(CompressorParams {
+            knee: 10.0,
+            ..*compressor.params()
+        });
+        // the knee starts 5 dB below the threshold and rounds the corner
+        assert_eq!(compressor.curve(-25.0), -25.0);
+        assert_eq!(compressor.curve(-20.0), -20.9375);
+        assert_eq!(compressor.curve(-10.0), -17.5);
+        assert_eq!(
+            Compressor::new(CompressorParams::default(), SAMPLE_RATE).curve(0.0),
+            0.0
+        );
+    }
+
+    #[test]
+    fn hard_knee_at_the_threshold() {
+        let mut compressor = Compressor::new(
+            CompressorParams {
+                threshold: -20.0,
+                knee: 0.0,
+                ..CompressorParams::default()
+            },
+            SAMPLE_RATE,
+        );
+        assert_eq!(compressor.curve(-20.0), -20.0);
+        // silence sits on the level floor, a threshold there must not poison the reduction
+        compressor.set_params(CompressorParams {
+            threshold: FLOOR_DB,
+            ..*compressor.params()
+        });
+        for _ in 0..10 {
+            compressor.process([0.0; 2]);
+        }
+        assert_eq!(compressor.reduction(), 0.0);
+        let [left, right] = compressor.process([0.5; 2]);
+        assert!(left.is_finite() && right.is_finite());
+    }
+
+    #[test]
+    fn compressor_attack_and_release() {
+        let mut compressor = Compressor::new(
+            CompressorParams {
+                threshold: -20.0,
+                ratio: 4.0,
+                knee: 0.0,
+                attack: 0.01,
+                release: 0.1,
+                makeup: 6.0,
+            },
+            SAMPLE_RATE,
+        );
+        // 0 dBFS wants 15 dB of reduction, 63 % of it after the attack time
+        for _ in 0..480 {
+            compressor.process([1.0; 2]);
+        }
+        assert!((compressor.reduction() + 15.0 * 0.632).abs() < 0.1);
+        for _ in 0..4800 {
+            compressor.process([1.0; 2]);
+        }
+        let [out, _] = compressor.process([1.0; 2]);
+        assert!((gain_to_db(out) - (-15.0 + 6.0)).abs() < 0.01);
+        // quiet again, 63 % of the way back after the release time
+        for _ in 0..4800 {
+            compressor.process([0.01; 2]);
+        }
+        assert!((compressor.reduction() + 15.0 * 0.368).abs() < 0.1);
+    }
+
+    #[test]
+    fn sidechain_ducks() {
+        let mut compressor = Compressor::new(
+            CompressorParams {
+                threshold: -30.0,
+                ratio: 10.0,
+                attack: 0.0,
+                ..CompressorParams::default()
+            },
+            SAMPLE_RATE,
+        );
+        assert_eq!(compressor.process_keyed([0.5; 2], [0.0; 2]), [0.5; 2]);
+        let [ducked, _] = compressor.process_keyed([0.5; 2], [1.0; 2]);
+        assert!((gain_to_db(ducked / 0.5) + 27.0).abs() < 0.01);
+    }
+
+    /// Largest magnitude between the samples of `signal` once it settles
+    fn true_peak(signal: &[[f32; 2]]) -> f32 {
+        let mut detector = Oversampler::new(Oversampling::X8);
+        signal
+            .iter()
+            .map(|frame| detector.peak(frame[0]))
+            .skip(100)
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn limiter_catches_peaks_between_samples() {
+        let mut limiter = Limiter::new(LimiterParams::default(), SAMPLE_RATE);
+        // samples of 0.707 around a true peak of 1
+        let input = (0..4800)
+            .map(|n| [(PI * 0.5 * n as f32 + PI * 0.25).sin(); 2])
+            .collect::<Vec<_>>();
+        let output = input
+            .iter()
+            .map(|frame| limiter.process(*frame))
+            .collect::<Vec<_>>();
+        let ceiling = db_to_gain(-0.1);
+        assert!(true_peak(&output[1000..]) <= ceiling * 1.002);
+        assert!(true_peak(&output[1000..]) > ceiling * 0.99);
+        assert!(limiter.gain() < 1.0);
+    }
+
+    #[test]
+    fn limiter_gain_is_the_held_minimum_averaged() {
+        let mut limiter = Limiter::new(LimiterParams::default(), SAMPLE_RATE);
+        // the same gain computed the slow way, from the gain each frame requires
+        let mut required = vec![1.0; limiter.required.len()];
+        let mut held = required.clone();
+        let mut gain = 1.0;
+        for n in 0..4800 {
+            if n == 2400 {
+                limiter.set_params(LimiterParams {
+                    lookahead: 0.01,
+                    ..*limiter.params()
+                });
+            }
+            // bursts of growing peaks
+            let level = if n % 700 < 50 {
+                1.0 + n as f32 / 1000.0
+            } else {
+                0.5
+            };
+            let index = limiter.index;
+            limiter.process([level; 2]);
+            let window = limiter.lookahead + 1;
+            required.push(limiter.required[index]);
+            held.push(
+                required[required.len() - window - 1..]
+                    .iter()
+                    .copied()
+                    .fold(1.0, f32::min),
+            );
+            let target = held[held.len() - window..].iter().sum::<f32>() / window as f32;
+            gain = if target < gain {
+                target
+            } else {
+                target + (gain - target) * limiter.release
+            };
+            assert!(
+                (limiter.gain() - gain).abs() < 1e-5,
+                "{} {} {}",
+                n,
+                limiter.gain(),
+                gain
+            );
+        }
+        assert!(held.iter().any(|gain| *gain < 0.5));
+    }
+
+    #[test]
+    fn limiter_looks_ahead() {
+        let mut limiter = Limiter::new(LimiterParams::default(), SAMPLE_RATE);
+        let latency = limiter.latency();
+        assert_eq!(latency, 240 + 16);
+        // a quiet tone jumping to +12 dB
+        let input = (0..9600)
+            .map(|n| {
+                let level = if n < 4800 { 0.25 } else { 4.0 };
+                [level * (2.0 * PI * 1000.0 * n as f32 / SAMPLE_RATE).sin(); 2]
+            })
+            .collect::<Vec<_>>();
+        let output = input
+            .iter()
+            .map(|frame| limiter.process(*frame))
+            .collect::<Vec<_>>();
+        let ceiling = db_to_gain(-0.1);
+        let peak = output.iter().map(|f| f[0].abs()).fold(0.0, f32::max);
+        assert!(peak <= ceiling * 1.002, "{}", peak);
+        // the gain is already falling before the jump comes out
+        let before = &output[4800 + latency - 48..4800 + latency];
+        let quiet = before.iter().map(|f| f[0].abs()).fold(0.0, f32::max);
+        assert!(quiet < 0.2, "{}", quiet);
+        // and comes back once the loud part is over
+        for _ in 0..48_000 {
+            limiter.process([0.0; 2]);
+        }
+        assert!(limiter.gain() > 0.99);
+    }
+}
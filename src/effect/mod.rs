@@ -0,0 +1,35 @@
+/// Modulated delays, chorus and flanger
+pub mod chorus;
+/// Stereo and ping-pong echo synced to the tempo
+pub mod delay;
+/// Fractional delay line shared by the delay based effects
+pub mod delay_line;
+/// Waveshaping and bitcrushing
+pub mod distortion;
+/// Compressor and true-peak limiter of the master bus
+pub mod dynamics;
+/// Oversampling for the nonlinear effects
+pub mod oversample;
+/// Swept allpass phaser
+pub mod phaser;
+/// Algorithmic stereo reverb
+pub mod reverb;
+
+/// Stereo audio processor inserted on a [`crate::mix::Bus`]
+///
+/// `process` runs in the audio callback and must not allocate or block.
+pub trait Effect: Send {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2];
+
+    /// Clear internal state such as delay lines
+    fn reset(&mut self) {}
+
+    /// Seconds per quarter note, for effects synced to the song tempo
+    fn set_tempo(&mut self, _quarter_seconds: f32) {}
+
+    /// Longest time in seconds the output can stay silent while the effect still holds
+    /// signal, like the pre-delay of a reverb
+    fn silent_gap(&self) -> f32 {
+        0.0
+    }
+}
@@ -0,0 +1,247 @@
+use std::f32::consts::PI;
+
+/// Filter taps per input sample, the anti-aliasing filter has `factor` times as many
+const TAPS_PER_PHASE: usize = 32;
+const MAX_FACTOR: usize = 8;
+/// Cutoff of the anti-aliasing filter relative to the original Nyquist frequency
+const CUTOFF: f32 = 0.8;
+/// Upper bound of [`Oversampler::process_latency`] at any factor, in input samples
+pub const MAX_LATENCY: usize = TAPS_PER_PHASE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversampling {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Oversampling {
+    pub fn factor(self) -> usize {
+        match self {
+            Self::Off => 1,
+            Self::X2 => 2,
+            Self::X4 => 4,
+            Self::X8 => 8,
+        }
+    }
+}
+
+/// Runs a nonlinearity at a multiple of the sample rate
+///
+/// The input is upsampled with a polyphase windowed sinc, and the same filter removes
+/// what the nonlinearity put above the original Nyquist frequency before decimating.
+/// Buffers are allocated for 8× up front, changing the factor never allocates.
+#[derive(Debug, Clone)]
+pub struct Oversampler {
+    factor: usize,
+    taps: Vec<f32>,
+    /// newest first, input samples and oversampled output of the nonlinearity
+    input: [f32; TAPS_PER_PHASE],
+    output: Vec<f32>,
+}
+
+impl Oversampler {
+    pub fn new(oversampling: Oversampling) -> Self {
+        let mut oversampler = Self {
+            factor: 1,
+            taps: Vec::with_capacity(MAX_FACTOR * TAPS_PER_PHASE),
+            input: [0.0; TAPS_PER_PHASE],
+            output: Vec::with_capacity(MAX_FACTOR * TAPS_PER_PHASE),
+        };
+        oversampler.set_oversampling(oversampling);
+        oversampler
+    }
+
+    pub fn oversampling(&self) -> Oversampling {
+        match self.factor {
+            2 => Oversampling::X2,
+            4 => Oversampling::X4,
+            8 => Oversampling::X8,
+            _ => Oversampling::Off,
+        }
+    }
+
+    /// Blackman windowed sinc low pass at the original Nyquist frequency
+    ///
+    /// The filter has an odd length, padded with a zero tap, so its delay is a whole
+    /// number of upsampled samples and they line up with the input samples.
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        self.factor = oversampling.factor();
+        let len = self.factor * TAPS_PER_PHASE - 1;
+        let cutoff = CUTOFF * 0.5 / self.factor as f32;
+        let centre = (len / 2) as f32;
+        self.taps.clear();
+        self.taps.extend((0..len).map(|i| {
+            let t = i as f32 - centre;
+            let sinc = if t == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * t).sin() / (PI * t)
+            };
+            let phase = 2.0 * PI * i as f32 / (len - 1) as f32;
+            sinc * (0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos())
+        }));
+        self.taps.push(0.0);
+        let sum: f32 = self.taps.iter().sum();
+        self.taps.iter_mut().for_each(|tap| *tap /= sum);
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.input = [0.0; TAPS_PER_PHASE];
+        self.output.clear();
+        self.output.resize(self.taps.len(), 0.0);
+    }
+
+    /// Apply `shape` to `factor` samples of the upsampled input, returns one decimated sample
+    pub fn process<F: FnMut(f32) -> f32>(&mut self, sample: f32, mut shape: F) -> f32 {
+        let factor = self.factor;
+        if factor == 1 {
+            return shape(sample);
+        }
+        self.push(sample);
+        let len = self.output.len();
+        self.output.copy_within(..len - factor, factor);
+        for phase in 0..factor {
+            // the last phase is the newest sample
+            self.output[factor - 1 - phase] = shape(self.upsampled(phase));
+        }
+        self.taps.iter().zip(&self.output).map(|(h, y)| h * y).sum()
+    }
+
+    /// Largest magnitude of the upsampled input, an estimate of the peak between samples
+    ///
+    /// It lags the input by [`Oversampler::latency`].
+    pub fn peak(&mut self, sample: f32) -> f32 {
+        if self.factor == 1 {
+            return sample.abs();
+        }
+        self.push(sample);
+        (0..self.factor)
+            .map(|phase| self.upsampled(phase).abs())
+            .fold(0.0, f32::max)
+    }
+
+    /// Delay of the upsampled signal in input samples
+    pub fn latency(&self) -> f32 {
+        match self.factor {
+            1 => 0.0,
+            factor => ((self.taps.len() - 2) / 2) as f32 / factor as f32,
+        }
+    }
+
+    /// Delay of [`Oversampler::process`] in input samples, through both filters
+    ///
+    /// The decimated sample lines up with the newest phase, `factor - 1` upsampled
+    /// samples ahead of twice the filter delay.
+    pub fn process_latency(&self) -> f32 {
+        match self.factor {
+            1 => 0.0,
+            factor => (self.taps.len() - 1 - factor) as f32 / factor as f32,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.input.copy_within(..TAPS_PER_PHASE - 1, 1);
+        self.input[0] = sample;
+    }
+
+    fn upsampled(&self, phase: usize) -> f32 {
+        let sum: f32 = self
+            .input
+            .iter()
+            .enumerate()
+            .map(|(k, x)| self.taps[k * self.factor + phase] * x)
+            .sum();
+        sum * self.factor as f32
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub const SAMPLE_RATE: f32 = 48_000.0;
+    /// DFT length, a sine on a bin repeats exactly in it
+    pub const SIZE: usize = 2048;
+
+    /// Power of every bin up to Nyquist of the steady state response to a sine on `bin`
+    pub fn spectrum<F: FnMut(f32) -> f32>(bin: usize, amplitude: f32, mut process: F) -> Vec<f32> {
+        let signal = (0..SIZE * 2)
+            .map(|n| process(amplitude * (2.0 * PI * (bin * n) as f32 / SIZE as f32).sin()))
+            .skip(SIZE)
+            .collect::<Vec<_>>();
+        let table = (0..SIZE)
+            .map(|n| (2.0 * PI * n as f32 / SIZE as f32).sin_cos())
+            .collect::<Vec<_>>();
+        (0..=SIZE / 2)
+            .map(|k| {
+                let (re, im) = signal
+                    .iter()
+                    .enumerate()
+                    .fold((0.0, 0.0), |(re, im), (n, x)| {
+                        let (sin, cos) = table[(k * n) % SIZE];
+                        (re + x * cos, im - x * sin)
+                    });
+                (re * re + im * im) / (SIZE * SIZE) as f32
+            })
+            .collect()
+    }
+
+    /// Power on bins that are not harmonics of `bin`, relative to the total
+    pub fn aliasing(spectrum: &[f32], bin: usize) -> f32 {
+        let total: f32 = spectrum[1..].iter().sum();
+        let aliases: f32 = spectrum
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(k, _)| k % bin != 0)
+            .map(|(_, power)| power)
+            .sum();
+        aliases / total
+    }
+
+    #[test]
+    fn linear_path_is_transparent() {
+        for oversampling in [Oversampling::X2, Oversampling::X4, Oversampling::X8] {
+            let mut oversampler = Oversampler::new(oversampling);
+            let spectrum = spectrum(205, 1.0, |x| oversampler.process(x, |y| y));
+            // a 4.8 kHz sine keeps its level of 1/4 and gains nothing else
+            assert!((spectrum[205] - 0.25).abs() < 1e-3, "{:?}", oversampling);
+            assert!(aliasing(&spectrum, 205) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn process_latency_is_the_impulse_delay() {
+        for oversampling in [Oversampling::X2, Oversampling::X4, Oversampling::X8] {
+            let mut oversampler = Oversampler::new(oversampling);
+            let response = (0..MAX_LATENCY * 2)
+                .map(|n| oversampler.process(if n == 0 { 1.0 } else { 0.0 }, |y| y))
+                .collect::<Vec<_>>();
+            // the filters are symmetric, the centroid is the delay
+            let centroid = response
+                .iter()
+                .enumerate()
+                .map(|(n, y)| n as f32 * y)
+                .sum::<f32>()
+                / response.iter().sum::<f32>();
+            let latency = oversampler.process_latency();
+            assert!((centroid - latency).abs() < 1e-3, "{:?}", oversampling);
+            assert!(latency < MAX_LATENCY as f32);
+        }
+    }
+
+    #[test]
+    fn finds_peaks_between_samples() {
+        let mut oversampler = Oversampler::new(Oversampling::X4);
+        // a quarter of the sample rate, sampled half way between its peaks
+        let peak = (0..200)
+            .map(|n| oversampler.peak((PI * 0.5 * n as f32 + PI * 0.25).sin()))
+            .skip(100)
+            .fold(0.0, f32::max);
+        assert!((peak - 1.0).abs() < 0.01, "{}", peak);
+        assert_eq!(oversampler.latency(), 15.75);
+    }
+}
@@ -0,0 +1,176 @@
+use std::f32::consts::PI;
+
+use super::Effect;
+use crate::{
+    modulation::lfo::{Lfo, LfoParams, LfoRate, LfoShape},
+    osc::OscKind,
+};
+
+/// Most allpass stages per side
+pub const MAX_STAGES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhaserParams {
+    pub rate: LfoRate,
+    /// Hz, bottom of the sweep
+    pub frequency: f32,
+    /// octaves the sweep goes above `frequency`
+    pub depth: f32,
+    /// `[-0.95, 0.95]`
+    pub feedback: f32,
+    /// allpass stages, each pair adds a notch, up to [`MAX_STAGES`]
+    pub stages: usize,
+    /// `[0, 1]`, phase between the left and right LFOs, half a cycle at 1
+    pub spread: f32,
+    /// `[0, 1]`, dry to wet, notches are deepest at 0.5
+    pub mix: f32,
+}
+
+impl Default for PhaserParams {
+    fn default() -> Self {
+        Self {
+            rate: LfoRate::Hz(0.5),
+            frequency: 300.0,
+            depth: 3.0,
+            feedback: 0.5,
+            stages: 6,
+            spread: 0.5,
+            mix: 0.5,
+        }
+    }
+}
+
+/// Chain of first order allpass filters swept by an LFO, mixed with the dry signal
+#[derive(Debug, Clone)]
+pub struct Phaser {
+    params: PhaserParams,
+    sample_rate: f32,
+    lfos: [Lfo; 2],
+    /// allpass states and last output of each side
+    states: [[f32; MAX_STAGES]; 2],
+    last: [f32; 2],
+}
+
+impl Phaser {
+    pub fn new(params: PhaserParams, sample_rate: f32) -> Self {
+        let mut phaser = Self {
+            params,
+            sample_rate,
+            lfos: [Lfo::new(sample_rate), Lfo::new(sample_rate)],
+            states: [[0.0; MAX_STAGES]; 2],
+            last: [0.0; 2],
+        };
+        phaser.set_params(params);
+        phaser
+    }
+
+    pub fn params(&self) -> &PhaserParams {
+        &self.params
+    }
+
+    /// Restarts the LFOs
+    pub fn set_params(&mut self, params: PhaserParams) {
+        let params = PhaserParams {
+            frequency: params.frequency.max(1.0),
+            depth: params.depth.max(0.0),
+            feedback: params.feedback.clamp(-0.95, 0.95),
+            stages: params.stages.min(MAX_STAGES),
+            spread: params.spread.clamp(0.0, 1.0),
+            mix: params.mix.clamp(0.0, 1.0),
+            ..params
+        };
+        self.params = params;
+        for (lfo, phase) in self.lfos.iter_mut().zip([0.0, params.spread * 0.5]) {
+            lfo.start(LfoParams {
+                shape: LfoShape::Wave(OscKind::Sine),
+                rate: params.rate,
+                phase,
+            });
+        }
+    }
+}
+
+impl Effect for Phaser {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let PhaserParams {
+            frequency,
+            depth,
+            feedback,
+            stages,
+            mix,
+            ..
+        } = self.params;
+        let mut out = [0.0; 2];
+        for i in 0..2 {
+            let sweep = (self.lfos[i].next_sample() + 1.0) * 0.5;
+            let frequency = (frequency * (depth * sweep).exp2()).min(self.sample_rate * 0.45);
+            let t = (PI * frequency / self.sample_rate).tan();
+            let a = (t - 1.0) / (t + 1.0);
+            let mut wet = frame[i] + self.last[i] * feedback;
+            for state in self.states[i][..stages].iter_mut() {
+                let y = a * wet + *state;
+                *state = wet - a * y;
+                wet = y;
+            }
+            self.last[i] = wet;
+            out[i] = frame[i] + (wet - frame[i]) * mix;
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.states = [[0.0; MAX_STAGES]; 2];
+        self.last = [0.0; 2];
+    }
+
+    fn set_tempo(&mut self, quarter_seconds: f32) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_tempo(quarter_seconds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 8000.0;
+
+    /// Peak output level of a sine after the phaser settles
+    fn level(phaser: &mut Phaser, frequency: f32) -> f32 {
+        phaser.reset();
+        (0..8000)
+            .map(|n| {
+                let x = (2.0 * PI * frequency * n as f32 / SAMPLE_RATE).sin();
+                phaser.process([x; 2])[0]
+            })
+            .skip(4000)
+            .fold(0.0, |peak, y| y.abs().max(peak))
+    }
+
+    #[test]
+    fn two_stages_notch_at_the_frequency() {
+        // a still LFO in the middle of a sweep of no depth
+        let mut phaser = Phaser::new(
+            PhaserParams {
+                rate: LfoRate::Hz(0.0),
+                frequency: 500.0,
+                depth: 0.0,
+                feedback: 0.0,
+                stages: 2,
+                spread: 0.0,
+                mix: 0.5,
+            },
+            SAMPLE_RATE,
+        );
+        assert!(level(&mut phaser, 500.0) < 0.01);
+        assert!(level(&mut phaser, 2000.0) > 0.5);
+        assert!(level(&mut phaser, 60.0) > 0.9);
+        // the allpass chain alone keeps every level
+        phaser.set_params(PhaserParams {
+            mix: 1.0,
+            ..*phaser.params()
+        });
+        assert!((level(&mut phaser, 500.0) - 1.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,332 @@
+use super::Effect;
+
+/// Comb and allpass lengths in samples at 44.1 kHz, tuned by Jezar
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+/// Extra samples on the right channel that decorrelate it from the left
+const STEREO_SPREAD: usize = 23;
+const TUNING_RATE: f32 = 44_100.0;
+
+const INPUT_GAIN: f32 = 0.015;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+/// Map `size` and `damping` in `[0, 1]` to comb feedback and damping
+const ROOM_SCALE: f32 = 0.28;
+const ROOM_OFFSET: f32 = 0.7;
+const DAMP_SCALE: f32 = 0.4;
+
+/// Longest pre-delay, the buffer is allocated for it up front
+pub const MAX_PRE_DELAY: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReverbParams {
+    /// `[0, 1]`, longer decay
+    pub size: f32,
+    /// `[0, 1]`, darker tail
+    pub damping: f32,
+    /// seconds before the tail starts, up to [`MAX_PRE_DELAY`]
+    pub pre_delay: f32,
+    /// `[0, 1]`, 0 is mono
+    pub width: f32,
+    /// linear levels of the tail and of the input
+    pub wet: f32,
+    pub dry: f32,
+}
+
+impl Default for ReverbParams {
+    /// A medium hall for a send bus, wet only
+    fn default() -> Self {
+        Self {
+            size: 0.7,
+            damping: 0.5,
+            pre_delay: 0.02,
+            width: 1.0,
+            wet: 1.0,
+            dry: 0.0,
+        }
+    }
+}
+
+/// Feedback comb with a one pole low pass in the loop
+#[derive(Debug, Clone)]
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(1)],
+            index: 0,
+            store: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.store = output * (1.0 - damp) + self.store * damp;
+        self.buffer[self.index] = input + self.store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.store = 0.0;
+    }
+}
+
+/// Schroeder allpass
+#[derive(Debug, Clone)]
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(1)],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        self.buffer[self.index] = input + buffered * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+        buffered - input
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+    }
+}
+
+/// One channel of the tank, eight parallel combs into four allpasses in series
+#[derive(Debug, Clone)]
+struct Tank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Tank {
+    fn new(sample_rate: f32, spread: usize) -> Self {
+        let scale = |len: usize| ((len + spread) as f32 * sample_rate / TUNING_RATE) as usize;
+        Self {
+            combs: COMB_TUNING
+                .iter()
+                .map(|len| Comb::new(scale(*len)))
+                .collect(),
+            allpasses: ALLPASS_TUNING
+                .iter()
+                .map(|len| Allpass::new(scale(*len)))
+                .collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let mut out = 0.0;
+        for comb in self.combs.iter_mut() {
+            out += comb.process(input, feedback, damp);
+        }
+        for allpass in self.allpasses.iter_mut() {
+            out = allpass.process(out);
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.combs.iter_mut().for_each(Comb::reset);
+        self.allpasses.iter_mut().for_each(Allpass::reset);
+    }
+}
+
+/// Freeverb stereo reverb with a pre-delay
+///
+/// Every buffer is allocated in `new`, so processing and changing parameters never allocate.
+///
+/// [Freeverb](https://ccrma.stanford.edu/~jos/pasp/Freeverb.html)
+#[derive(Debug, Clone)]
+pub struct Reverb {
+    params: ReverbParams,
+    sample_rate: f32,
+    tanks: [Tank; 2],
+    /// mono input delayed by the pre-delay
+    pre_delay: Vec<f32>,
+    pre_delay_index: usize,
+    pre_delay_len: usize,
+    feedback: f32,
+    damp: f32,
+    /// wet gain into the same and into the opposite side
+    wet_same: f32,
+    wet_cross: f32,
+}
+
+impl Reverb {
+    pub fn new(params: ReverbParams, sample_rate: f32) -> Self {
+        let mut reverb = Self {
+            params,
+            sample_rate,
+            tanks: [
+                Tank::new(sample_rate, 0),
+                Tank::new(sample_rate, STEREO_SPREAD),
+            ],
+            pre_delay: vec![0.0; (MAX_PRE_DELAY * sample_rate) as usize + 1],
+            pre_delay_index: 0,
+            pre_delay_len: 0,
+            feedback: 0.0,
+            damp: 0.0,
+            wet_same: 0.0,
+            wet_cross: 0.0,
+        };
+        reverb.set_params(params);
+        reverb
+    }
+
+    pub fn params(&self) -> &ReverbParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ReverbParams) {
+        let params = ReverbParams {
+            size: params.size.clamp(0.0, 1.0),
+            damping: params.damping.clamp(0.0, 1.0),
+            pre_delay: params.pre_delay.clamp(0.0, MAX_PRE_DELAY),
+            width: params.width.clamp(0.0, 1.0),
+            ..params
+        };
+        self.params = params;
+        self.feedback = params.size * ROOM_SCALE + ROOM_OFFSET;
+        self.damp = params.damping * DAMP_SCALE;
+        self.wet_same = params.wet * (params.width * 0.5 + 0.5);
+        self.wet_cross = params.wet * (0.5 - params.width * 0.5);
+        self.pre_delay_len =
+            ((params.pre_delay * self.sample_rate) as usize).min(self.pre_delay.len() - 1);
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, [left, right]: [f32; 2]) -> [f32; 2] {
+        let len = self.pre_delay.len();
+        self.pre_delay[self.pre_delay_index] = (left + right) * INPUT_GAIN;
+        let input = self.pre_delay[(self.pre_delay_index + len - self.pre_delay_len) % len];
+        self.pre_delay_index = (self.pre_delay_index + 1) % len;
+        let wet_left = self.tanks[0].process(input, self.feedback, self.damp);
+        let wet_right = self.tanks[1].process(input, self.feedback, self.damp);
+        let dry = self.params.dry;
+        [
+            wet_left * self.wet_same + wet_right * self.wet_cross + left * dry,
+            wet_right * self.wet_same + wet_left * self.wet_cross + right * dry,
+        ]
+    }
+
+    fn reset(&mut self) {
+        self.tanks.iter_mut().for_each(Tank::reset);
+        self.pre_delay.fill(0.0);
+    }
+
+    fn silent_gap(&self) -> f32 {
+        self.params.pre_delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 8_000.0;
+
+    /// Left and right response to a unit impulse on both inputs
+    fn impulse_response(reverb: &mut Reverb, frames: usize) -> Vec<[f32; 2]> {
+        (0..frames)
+            .map(|i| reverb.process(if i == 0 { [1.0; 2] } else { [0.0; 2] }))
+            .collect()
+    }
+
+    fn energy(frames: &[[f32; 2]]) -> f32 {
+        frames.iter().map(|[l, r]| l * l + r * r).sum()
+    }
+
+    /// Samples until the energy left in the response falls 60 dB below the total
+    fn decay_time(response: &[[f32; 2]]) -> usize {
+        let total = energy(response);
+        let mut remaining = total;
+        response
+            .iter()
+            .position(|[l, r]| {
+                remaining -= l * l + r * r;
+                remaining < total * 1e-6
+            })
+            .unwrap_or(response.len())
+    }
+
+    #[test]
+    fn tail_starts_after_pre_delay() {
+        let mut reverb = Reverb::new(
+            ReverbParams {
+                pre_delay: 0.1,
+                ..ReverbParams::default()
+            },
+            SAMPLE_RATE,
+        );
+        let response = impulse_response(&mut reverb, 16_000);
+        let first = response.iter().position(|f| *f != [0.0; 2]).unwrap();
+        // the shortest comb comes on top of the 800 samples of pre-delay
+        assert!((800..1200).contains(&first), "{}", first);
+        assert!(energy(&response) > 0.01);
+        assert!(energy(&response[12_000..]) < energy(&response) * 1e-3);
+    }
+
+    #[test]
+    fn size_lengthens_decay() {
+        let decay = |size| {
+            let mut reverb = Reverb::new(
+                ReverbParams {
+                    size,
+                    ..ReverbParams::default()
+                },
+                SAMPLE_RATE,
+            );
+            decay_time(&impulse_response(&mut reverb, 40_000))
+        };
+        let (small, large) = (decay(0.2), decay(0.9));
+        assert!(large > small * 2, "{} {}", small, large);
+    }
+
+    #[test]
+    fn width_and_dry() {
+        let mut reverb = Reverb::new(
+            ReverbParams {
+                width: 0.0,
+                ..ReverbParams::default()
+            },
+            SAMPLE_RATE,
+        );
+        assert!(impulse_response(&mut reverb, 4000)
+            .iter()
+            .all(|[l, r]| (l - r).abs() < 1e-6));
+        let mut reverb = Reverb::new(ReverbParams::default(), SAMPLE_RATE);
+        let response = impulse_response(&mut reverb, 4000);
+        assert!(response.iter().any(|[l, r]| (l - r).abs() > 1e-3));
+        // dry only
+        reverb.set_params(ReverbParams {
+            wet: 0.0,
+            dry: 0.5,
+            ..ReverbParams::default()
+        });
+        assert_eq!(reverb.process([1.0, -1.0]), [0.5, -0.5]);
+    }
+
+    #[test]
+    fn reset_clears_tail() {
+        let mut reverb = Reverb::new(ReverbParams::default(), SAMPLE_RATE);
+        impulse_response(&mut reverb, 1000);
+        reverb.reset();
+        let silence = (0..4000)
+            .map(|_| reverb.process([0.0; 2]))
+            .collect::<Vec<_>>();
+        assert_eq!(energy(&silence), 0.0);
+    }
+}
@@ -1 +1,336 @@
+/// Delay-Attack-Hold-Decay-Sustain-Release gain envelope
+///
+/// Every timed stage takes exactly its length in samples, whatever level it starts from.
+/// Stages start from the current level, so retriggering a sounding voice does not click.
+///
+/// Exponential segments follow a one-pole curve aimed past their target.
+///
+/// [Envelope generators—ADSR code](https://www.earlevel.com/main/2013/06/03/envelope-generators-adsr-code/)
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    sample_rate: f32,
+    params: EnvelopeParams,
+    stage: Stage,
+    level: f32,
+    /// attack target set by the velocity
+    peak: f32,
+    /// samples left in the current stage
+    remaining: u32,
+    /// level the current stage ends at
+    target: f32,
+    /// per sample increment of a linear segment
+    step: f32,
+    /// pole and asymptote of an exponential segment
+    coef: f32,
+    asymptote: f32,
+}
 
+/// Stage lengths are in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvelopeParams {
+    pub delay: f32,
+    pub attack: f32,
+    pub hold: f32,
+    pub decay: f32,
+    /// `[0, 1]` of the peak
+    pub sustain: f32,
+    pub release: f32,
+    pub curve: Curve,
+    /// 0 ignores velocity, 1 scales the peak linearly with it
+    pub velocity_sensitivity: f32,
+}
+
+impl Default for EnvelopeParams {
+    fn default() -> Self {
+        Self {
+            delay: 0.0,
+            attack: 0.005,
+            hold: 0.0,
+            decay: 0.1,
+            sustain: 0.7,
+            release: 0.05,
+            curve: Curve::Exponential,
+            velocity_sensitivity: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    Linear,
+    Exponential,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Idle,
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// How far past the target exponential segments aim, relative to their span
+const ATTACK_OVERSHOOT: f32 = 0.3;
+const DECAY_OVERSHOOT: f32 = 0.0001;
+
+impl Envelope {
+    pub fn new(sample_rate: f32) -> Self {
+        Self::with_params(EnvelopeParams::default(), sample_rate)
+    }
+
+    pub fn with_params(params: EnvelopeParams, sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            params,
+            stage: Stage::Idle,
+            level: 0.0,
+            peak: 1.0,
+            remaining: 0,
+            target: 0.0,
+            step: 0.0,
+            coef: 0.0,
+            asymptote: 0.0,
+        }
+    }
+
+    pub fn params(&self) -> &EnvelopeParams {
+        &self.params
+    }
+
+    /// New stage lengths apply from the next stage on, the sustain level immediately
+    pub fn set_params(&mut self, params: EnvelopeParams) {
+        self.params = params;
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Start the envelope.
+    ///
+    /// With `legato` a held envelope keeps its stage and peak instead of restarting.
+    pub fn gate_on(&mut self, velocity: u8, legato: bool) {
+        let held = !matches!(self.stage, Stage::Idle | Stage::Release);
+        if legato && held {
+            return;
+        }
+        let sensitivity = self.params.velocity_sensitivity.clamp(0.0, 1.0);
+        self.peak = 1.0 - sensitivity + sensitivity * f32::from(velocity.min(127)) / 127.0;
+        self.enter(Stage::Delay);
+    }
+
+    pub fn gate_off(&mut self) {
+        if self.stage != Stage::Idle {
+            self.enter(Stage::Release);
+        }
+    }
+
+    /// Jump to silence
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+        self.stage = Stage::Idle;
+    }
+
+    /// Returns true once the release has faded out
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Idle
+    }
+
+    fn samples(&self, seconds: f32) -> u32 {
+        (seconds.max(0.0) * self.sample_rate).round() as u32
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        let (seconds, target, overshoot) = match stage {
+            Stage::Idle => {
+                self.level = 0.0;
+                return;
+            }
+            Stage::Sustain => return,
+            Stage::Delay => (self.params.delay, self.level, 0.0),
+            Stage::Attack => (self.params.attack, self.peak, ATTACK_OVERSHOOT),
+            Stage::Hold => (self.params.hold, self.peak, 0.0),
+            Stage::Decay => (self.params.decay, self.sustain_level(), DECAY_OVERSHOOT),
+            Stage::Release => (self.params.release, 0.0, DECAY_OVERSHOOT),
+        };
+        self.remaining = self.samples(seconds);
+        self.target = target;
+        if self.remaining == 0 {
+            return self.finish_stage();
+        }
+        let span = target - self.level;
+        self.step = span / self.remaining as f32;
+        // x(n) = asymptote + (x(0) - asymptote) * coef^n reaches the target after `remaining` samples
+        if overshoot > 0.0 {
+            self.asymptote = target + span * overshoot;
+            self.coef = (-((1.0 + overshoot) / overshoot).ln() / self.remaining as f32).exp();
+        }
+    }
+
+    fn finish_stage(&mut self) {
+        self.level = self.target;
+        match self.stage {
+            Stage::Delay => self.enter(Stage::Attack),
+            Stage::Attack => self.enter(Stage::Hold),
+            Stage::Hold => self.enter(Stage::Decay),
+            Stage::Decay => self.enter(Stage::Sustain),
+            Stage::Release => self.enter(Stage::Idle),
+            Stage::Idle | Stage::Sustain => {}
+        }
+    }
+
+    fn sustain_level(&self) -> f32 {
+        self.params.sustain.clamp(0.0, 1.0) * self.peak
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        match self.stage {
+            Stage::Idle => {}
+            Stage::Sustain => self.level = self.sustain_level(),
+            Stage::Delay | Stage::Hold => {}
+            Stage::Attack | Stage::Decay | Stage::Release => {
+                let exponential = self.params.curve == Curve::Exponential;
+                self.level = if exponential && self.coef > 0.0 && self.coef < 1.0 {
+                    self.asymptote + (self.level - self.asymptote) * self.coef
+                } else {
+                    self.level + self.step
+                };
+            }
+        }
+        if matches!(
+            self.stage,
+            Stage::Delay | Stage::Attack | Stage::Hold | Stage::Decay | Stage::Release
+        ) {
+            self.remaining = self.remaining.saturating_sub(1);
+            if self.remaining == 0 {
+                self.finish_stage();
+            }
+        }
+        self.level
+    }
+
+    /// Fill `buffer` with the curve
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        for s in buffer.iter_mut() {
+            *s = self.next_sample();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    fn params(curve: Curve) -> EnvelopeParams {
+        EnvelopeParams {
+            delay: 0.010,
+            attack: 0.020,
+            hold: 0.010,
+            decay: 0.030,
+            sustain: 0.5,
+            release: 0.020,
+            curve,
+            velocity_sensitivity: 1.0,
+        }
+    }
+
+    fn curve(curve: Curve) -> Vec<f32> {
+        let mut env = Envelope::with_params(params(curve), SAMPLE_RATE);
+        env.gate_on(127, false);
+        let mut buffer = vec![0f32; 100];
+        env.render(&mut buffer[..80]);
+        env.gate_off();
+        env.render(&mut buffer[80..]);
+        assert!(env.is_finished());
+        buffer
+    }
+
+    #[test]
+    fn stages_take_their_length() {
+        for kind in [Curve::Linear, Curve::Exponential] {
+            let buffer = curve(kind);
+            // delay
+            assert!(buffer[..9].iter().all(|s| *s == 0.0));
+            // attack ends on the peak and hold keeps it
+            assert!(buffer[28] < 1.0);
+            assert!(buffer[29..40].iter().all(|s| *s == 1.0));
+            // decay ends on the sustain level
+            assert!(buffer[68] > 0.5);
+            assert!(buffer[69..80].iter().all(|s| *s == 0.5));
+            // release
+            assert!(buffer[98] > 0.0);
+            assert_eq!(buffer[99], 0.0);
+        }
+    }
+
+    #[test]
+    fn segments_are_monotonic() {
+        for kind in [Curve::Linear, Curve::Exponential] {
+            let buffer = curve(kind);
+            assert!(buffer[..40].windows(2).all(|w| w[1] >= w[0]));
+            assert!(buffer[40..].windows(2).all(|w| w[1] <= w[0]));
+        }
+    }
+
+    #[test]
+    fn linear_attack_is_a_ramp() {
+        let buffer = curve(Curve::Linear);
+        assert!((buffer[19] - 0.5).abs() < 1e-5);
+        // exponential decay drops faster at first
+        let exp = curve(Curve::Exponential);
+        assert!(exp[55] < buffer[55]);
+    }
+
+    #[test]
+    fn velocity_scales_peak() {
+        let mut env = Envelope::with_params(params(Curve::Linear), SAMPLE_RATE);
+        env.gate_on(64, false);
+        let mut buffer = [0f32; 70];
+        env.render(&mut buffer);
+        assert!((buffer[35] - 64.0 / 127.0).abs() < 1e-5);
+        assert!((buffer[69] - 0.5 * 64.0 / 127.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn retrigger_starts_from_current_level() {
+        let mut p = params(Curve::Linear);
+        p.delay = 0.0;
+        let mut env = Envelope::with_params(p, SAMPLE_RATE);
+        env.gate_on(127, false);
+        let mut buffer = [0f32; 200];
+        env.render(&mut buffer[..50]);
+        env.gate_off();
+        env.render(&mut buffer[50..60]);
+        env.gate_on(127, false);
+        env.render(&mut buffer[60..]);
+        assert_eq!(env.stage(), Stage::Sustain);
+        assert!(buffer
+            .windows(2)
+            .all(|w| (w[1] - w[0]).abs() <= 0.05 + 1e-5));
+    }
+
+    #[test]
+    fn legato_keeps_stage() {
+        let mut env = Envelope::with_params(params(Curve::Linear), SAMPLE_RATE);
+        env.gate_on(127, false);
+        let mut buffer = [0f32; 75];
+        env.render(&mut buffer);
+        env.gate_on(10, true);
+        assert_eq!(env.stage(), Stage::Sustain);
+        assert_eq!(env.next_sample(), 0.5);
+        // legato after release restarts
+        env.gate_off();
+        env.gate_on(127, true);
+        assert_eq!(env.stage(), Stage::Delay);
+    }
+}
@@ -0,0 +1,153 @@
+use std::f32::consts::PI;
+
+use super::{FilterKind, FilterParams};
+
+/// Second order IIR filter in transposed direct form II
+///
+/// Coefficients are recomputed on every parameter change, modulate [`super::svf::Svf`] instead.
+///
+/// [Audio EQ Cookbook](https://www.w3.org/TR/audio-eq-cookbook/)
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    sample_rate: f32,
+    params: FilterParams,
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    pub fn new(params: FilterParams, sample_rate: f32) -> Self {
+        let mut biquad = Self {
+            sample_rate,
+            params,
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            z1: 0.0,
+            z2: 0.0,
+        };
+        biquad.set_params(params);
+        biquad
+    }
+
+    pub fn params(&self) -> &FilterParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: FilterParams) {
+        self.params = params.clamped(self.sample_rate);
+        let FilterParams {
+            kind,
+            cutoff,
+            q,
+            gain_db,
+        } = self.params;
+        let a = 10f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * cutoff / self.sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            FilterKind::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterKind::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterKind::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterKind::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterKind::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterKind::LowShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + sq),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sq),
+                    (a + 1.0) + (a - 1.0) * cos + sq,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sq,
+                )
+            }
+            FilterKind::HighShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + sq),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sq),
+                    (a + 1.0) - (a - 1.0) * cos + sq,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sq,
+                )
+            }
+        };
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+
+    /// Clear the filter memory
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [f32]) {
+        for s in buffer.iter_mut() {
+            *s = self.process(*s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::{check_responses, SAMPLE_RATE};
+
+    #[test]
+    fn cookbook_responses() {
+        check_responses(|kind, q, gain_db| {
+            let mut biquad = Biquad::new(
+                FilterParams {
+                    kind,
+                    cutoff: 1000.0,
+                    q,
+                    gain_db,
+                },
+                SAMPLE_RATE,
+            );
+            move |x| biquad.process(x)
+        });
+    }
+}
@@ -1 +1,126 @@
+pub mod biquad;
+pub mod svf;
 
+/// Response shape shared by [`biquad::Biquad`] and [`svf::Svf`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+    /// Constant 0 dB peak gain
+    BandPass,
+    Notch,
+    /// Bell boosting or cutting `gain_db` around the cutoff
+    Peak,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterParams {
+    pub kind: FilterKind,
+    /// Hz
+    pub cutoff: f32,
+    /// Quality factor, `0.7071` is Butterworth
+    pub q: f32,
+    /// Only used by `Peak`, `LowShelf` and `HighShelf`
+    pub gain_db: f32,
+}
+
+impl Default for FilterParams {
+    fn default() -> Self {
+        Self {
+            kind: FilterKind::LowPass,
+            cutoff: 1000.0,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+            gain_db: 0.0,
+        }
+    }
+}
+
+impl FilterParams {
+    /// Clamp into the range both filters stay stable in
+    fn clamped(mut self, sample_rate: f32) -> Self {
+        self.cutoff = self.cutoff.clamp(10.0, sample_rate * 0.49);
+        self.q = self.q.clamp(0.1, 40.0);
+        self.gain_db = self.gain_db.clamp(-48.0, 48.0);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilterKind::{self, *};
+
+    pub const SAMPLE_RATE: f32 = 48_000.0;
+
+    /// Steady state gain in dB of `process` for a sine at `freq`
+    pub fn gain_db<F: FnMut(f32) -> f32>(mut process: F, freq: f32) -> f32 {
+        let sine = |i: usize| (std::f32::consts::TAU * freq * i as f32 / SAMPLE_RATE).sin();
+        let settle = 9600;
+        for i in 0..settle {
+            process(sine(i));
+        }
+        let (mut input, mut output) = (0f64, 0f64);
+        for i in settle..settle + 48_000 {
+            let x = sine(i);
+            let y = process(x);
+            input += f64::from(x * x);
+            output += f64::from(y * y);
+        }
+        (10.0 * (output / input).log10()) as f32
+    }
+
+    pub fn assert_db(actual: f32, expected: f32, tolerance: f32, what: &str) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{}: {} dB, expected {} dB",
+            what,
+            actual,
+            expected
+        );
+    }
+
+    /// Checks shared by both filter types, `make` builds a filter at 1 kHz
+    pub fn check_responses<F: FnMut(f32) -> f32>(mut make: impl FnMut(FilterKind, f32, f32) -> F) {
+        let q = std::f32::consts::FRAC_1_SQRT_2;
+        let mut at = |kind, q, gain, freq| gain_db(make(kind, q, gain), freq);
+        assert_db(
+            at(LowPass, q, 0.0, 1000.0),
+            -3.01,
+            0.1,
+            "low pass at cutoff",
+        );
+        assert_db(at(LowPass, q, 0.0, 100.0), 0.0, 0.1, "low pass pass band");
+        // 12 dB per octave
+        assert!(at(LowPass, q, 0.0, 8000.0) < -34.0);
+        assert_db(
+            at(HighPass, q, 0.0, 1000.0),
+            -3.01,
+            0.1,
+            "high pass at cutoff",
+        );
+        assert_db(
+            at(HighPass, q, 0.0, 10_000.0),
+            0.0,
+            0.1,
+            "high pass pass band",
+        );
+        assert!(at(HighPass, q, 0.0, 125.0) < -34.0);
+        assert_db(at(BandPass, q, 0.0, 1000.0), 0.0, 0.1, "band pass center");
+        let edge = 1000.0 * (((1.0 + 4.0 * q * q).sqrt() + 1.0) / (2.0 * q));
+        assert_db(
+            at(BandPass, q, 0.0, edge),
+            -3.01,
+            0.15,
+            "band pass upper edge",
+        );
+        assert!(at(Notch, 5.0, 0.0, 1000.0) < -40.0);
+        assert_db(at(Notch, 5.0, 0.0, 100.0), 0.0, 0.1, "notch pass band");
+        assert_db(at(Peak, 1.0, 6.0, 1000.0), 6.0, 0.1, "peak center");
+        assert_db(at(Peak, 1.0, 6.0, 20.0), 0.0, 0.1, "peak far below");
+        assert_db(at(LowShelf, q, -6.0, 30.0), -6.0, 0.1, "low shelf");
+        assert_db(at(LowShelf, q, -6.0, 15_000.0), 0.0, 0.1, "low shelf above");
+        assert_db(at(HighShelf, q, 6.0, 20_000.0), 6.0, 0.15, "high shelf");
+        assert_db(at(HighShelf, q, 6.0, 30.0), 0.0, 0.1, "high shelf below");
+    }
+}
@@ -0,0 +1,227 @@
+use std::f32::consts::PI;
+
+use super::{FilterKind, FilterParams};
+
+/// Time constant of the cutoff, Q and gain smoothing in seconds
+const SMOOTHING_TIME: f32 = 0.002;
+
+/// Zero-delay-feedback state variable filter
+///
+/// Trapezoidal integration keeps it stable while cutoff and Q change every sample,
+/// and parameter changes are smoothed so stepped modulation does not zipper.
+///
+/// [Linear Trapezoidal Integrated SVF](https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf)
+#[derive(Debug, Clone)]
+pub struct Svf {
+    sample_rate: f32,
+    /// target parameters
+    params: FilterParams,
+    /// smoothed parameters the coefficients are computed from
+    cutoff: f32,
+    q: f32,
+    gain_db: f32,
+    smoothing: f32,
+    ic1eq: f32,
+    ic2eq: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+    k: f32,
+    /// output mix of input, band and low
+    m0: f32,
+    m1: f32,
+    m2: f32,
+}
+
+impl Svf {
+    pub fn new(params: FilterParams, sample_rate: f32) -> Self {
+        let params = params.clamped(sample_rate);
+        let mut svf = Self {
+            sample_rate,
+            params,
+            cutoff: params.cutoff,
+            q: params.q,
+            gain_db: params.gain_db,
+            smoothing: (-1.0 / (SMOOTHING_TIME * sample_rate)).exp(),
+            ic1eq: 0.0,
+            ic2eq: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            a3: 0.0,
+            k: 0.0,
+            m0: 0.0,
+            m1: 0.0,
+            m2: 0.0,
+        };
+        svf.update_coefficients();
+        svf
+    }
+
+    pub fn params(&self) -> &FilterParams {
+        &self.params
+    }
+
+    /// Cutoff, Q and gain glide to the new values, the kind switches immediately
+    pub fn set_params(&mut self, params: FilterParams) {
+        let kind_changed = params.kind != self.params.kind;
+        self.params = params.clamped(self.sample_rate);
+        if kind_changed {
+            self.update_coefficients();
+        }
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        self.set_params(FilterParams {
+            cutoff,
+            ..self.params
+        });
+    }
+
+    pub fn set_q(&mut self, q: f32) {
+        self.set_params(FilterParams { q, ..self.params });
+    }
+
+    /// Cutoff currently in effect
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    /// Clear the filter memory and jump to the target parameters
+    pub fn reset(&mut self) {
+        self.ic1eq = 0.0;
+        self.ic2eq = 0.0;
+        self.cutoff = self.params.cutoff;
+        self.q = self.params.q;
+        self.gain_db = self.params.gain_db;
+        self.update_coefficients();
+    }
+
+    fn update_coefficients(&mut self) {
+        let a = 10f32.powf(self.gain_db / 40.0);
+        let mut g = (PI * self.cutoff / self.sample_rate).tan();
+        let mut k = 1.0 / self.q;
+        let (m0, m1, m2) = match self.params.kind {
+            FilterKind::LowPass => (0.0, 0.0, 1.0),
+            FilterKind::HighPass => (1.0, -k, -1.0),
+            FilterKind::BandPass => (0.0, k, 0.0),
+            FilterKind::Notch => (1.0, -k, 0.0),
+            FilterKind::Peak => {
+                k = 1.0 / (self.q * a);
+                (1.0, k * (a * a - 1.0), 0.0)
+            }
+            FilterKind::LowShelf => {
+                g /= a.sqrt();
+                (1.0, k * (a - 1.0), a * a - 1.0)
+            }
+            FilterKind::HighShelf => {
+                g *= a.sqrt();
+                (a * a, k * (1.0 - a) * a, 1.0 - a * a)
+            }
+        };
+        self.k = k;
+        self.a1 = 1.0 / (1.0 + g * (g + k));
+        self.a2 = g * self.a1;
+        self.a3 = g * self.a2;
+        self.m0 = m0;
+        self.m1 = m1;
+        self.m2 = m2;
+    }
+
+    fn smooth(&mut self) {
+        let FilterParams {
+            cutoff, q, gain_db, ..
+        } = self.params;
+        if self.cutoff == cutoff && self.q == q && self.gain_db == gain_db {
+            return;
+        }
+        let s = self.smoothing;
+        let glide = |current: f32, target: f32, epsilon: f32| {
+            let next = target + (current - target) * s;
+            if (next - target).abs() < epsilon {
+                target
+            } else {
+                next
+            }
+        };
+        // glide the cutoff in octaves
+        self.cutoff = glide(self.cutoff.log2(), cutoff.log2(), 1e-4).exp2();
+        if (self.cutoff - cutoff).abs() < 1e-3 {
+            self.cutoff = cutoff;
+        }
+        self.q = glide(self.q, q, 1e-4);
+        self.gain_db = glide(self.gain_db, gain_db, 1e-3);
+        self.update_coefficients();
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.smooth();
+        let v3 = input - self.ic2eq;
+        let v1 = self.a1 * self.ic1eq + self.a2 * v3;
+        let v2 = self.ic2eq + self.a2 * self.ic1eq + self.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        self.m0 * input + self.m1 * v1 + self.m2 * v2
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [f32]) {
+        for s in buffer.iter_mut() {
+            *s = self.process(*s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::{check_responses, SAMPLE_RATE};
+
+    fn svf(kind: FilterKind, q: f32, gain_db: f32) -> Svf {
+        Svf::new(
+            FilterParams {
+                kind,
+                cutoff: 1000.0,
+                q,
+                gain_db,
+            },
+            SAMPLE_RATE,
+        )
+    }
+
+    #[test]
+    fn trapezoidal_responses() {
+        check_responses(|kind, q, gain_db| {
+            let mut svf = svf(kind, q, gain_db);
+            move |x| svf.process(x)
+        });
+    }
+
+    #[test]
+    fn stable_under_audio_rate_modulation() {
+        let mut svf = svf(FilterKind::LowPass, 30.0, 0.0);
+        let mut noise = 1u32;
+        for i in 0..SAMPLE_RATE as usize {
+            // sweep 20 Hz to 20 kHz ten times a second
+            let lfo = (std::f32::consts::TAU * 10.0 * i as f32 / SAMPLE_RATE).sin();
+            svf.set_cutoff(20.0 * 1000f32.powf(0.5 + 0.5 * lfo));
+            noise ^= noise << 13;
+            noise ^= noise >> 17;
+            noise ^= noise << 5;
+            let x = noise as f32 / u32::MAX as f32 - 0.5;
+            let y = svf.process(x);
+            assert!(y.is_finite() && y.abs() < 100.0, "{} at {}", y, i);
+        }
+    }
+
+    #[test]
+    fn cutoff_steps_glide() {
+        let mut svf = svf(FilterKind::LowPass, 0.7, 0.0);
+        svf.set_cutoff(4000.0);
+        svf.process(0.0);
+        let first = svf.cutoff();
+        assert!(first > 1000.0 && first < 1100.0, "{}", first);
+        for _ in 0..(SAMPLE_RATE * 0.05) as usize {
+            svf.process(0.0);
+        }
+        assert_eq!(svf.cutoff(), 4000.0);
+    }
+}
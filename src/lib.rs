@@ -0,0 +1,16 @@
+pub mod audio;
+pub mod controller;
+pub mod dither;
+pub mod effect;
+pub mod envelope;
+pub mod filter;
+pub mod midi;
+pub mod mix;
+pub mod modulation;
+pub mod osc;
+pub mod preset;
+pub mod render;
+pub mod synth;
+pub mod tuning;
+pub mod voice;
+pub mod wav;
@@ -1,9 +1,217 @@
-mod audio;
-mod controller;
-mod envelope;
-mod filter;
-mod midi;
-mod mix;
-mod osc;
-
-fn main() {}
+use std::{
+    env,
+    io::{stdin, BufRead},
+    process, thread,
+    time::Duration,
+};
+
+use cpal::traits::StreamTrait;
+use simple_synth::{
+    audio::{AudioOut, AudioRenderer, OutputStreamParams},
+    dither::Dither,
+    effect::dynamics::CompressorParams,
+    midi::{control::MidiControl, formats::Smf, player::Player},
+    preset::Patch,
+    render,
+    synth::Synth,
+    tuning::Tuning,
+    wav::{WavSampleFormat, WavSpec},
+};
+
+const USAGE: &str = "usage:
+    simple_synth play <file.mid> [--lenient] [mix options]
+    simple_synth live [<raw midi device or fifo>] [mix options]
+    simple_synth render <file.mid> <out.wav> [--lenient] [--rate 44100] [--channels 2]
+        [--format s16|s24|f32] [--dither none|rect|tpdf] [mix options]
+
+    --lenient   recover what can be recovered from a damaged MIDI file
+
+mix options:
+    --threshold <dBFS>  master compressor threshold, -18 by default
+    --ratio <ratio>     master compressor ratio, 1 leaves the mix alone
+    --sidechain <1-16>  key the compressor from a MIDI channel, to duck the rest
+    --scl <file.scl>    play in a Scala scale, laid out linearly with A4 at 440 Hz
+    --kbm <file.kbm>    lay the scale out with a Scala keyboard mapping
+    --patch <1-128>=<file>  play a patch file on a General MIDI program, repeatable";
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let result = match args.as_slice() {
+        ["play", input, options @ ..] => {
+            parse_options(options).and_then(|options| play(input, options))
+        }
+        ["live", device, options @ ..] if !device.starts_with("--") => parse_options(options)
+            .and_then(|options| {
+                let control = MidiControl::open(device).map_err(|e| e.to_string())?;
+                live(control, options)
+            }),
+        #[cfg(target_os = "linux")]
+        ["live", options @ ..] => parse_options(options).and_then(|options| {
+            let control = MidiControl::alsa_seq("simple_synth").map_err(|e| e.to_string())?;
+            live(control, options)
+        }),
+        #[cfg(not(target_os = "linux"))]
+        ["live", ..] => {
+            Err("ALSA sequencer input needs Linux, pass a raw MIDI device or FIFO".to_string())
+        }
+        ["render", input, output, options @ ..] => {
+            parse_options(options).and_then(|options| render(input, output, options))
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Open a MIDI file, a lenient parse reports each repair on stderr
+fn open_smf(input: &str, lenient: bool) -> Result<Smf, String> {
+    if !lenient {
+        return Smf::open(input).map_err(|e| e.to_string());
+    }
+    let (smf, warnings) = Smf::open_lenient(input).map_err(|e| e.to_string())?;
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(smf)
+}
+
+fn play(input: &str, options: Options) -> Result<(), String> {
+    let smf = open_smf(input, options.lenient)?;
+    let params = OutputStreamParams::new().map_err(|e| e.to_string())?;
+    let player = Player::new(smf, params.sample_rate());
+    let duration = player.duration() as f64 / f64::from(params.sample_rate());
+    let mut renderer = AudioRenderer::with_player(player, params.channels());
+    options.configure(renderer.synth_mut());
+    let stream = AudioOut::new(renderer)
+        .start_stream(params)
+        .map_err(|e| e.to_string())?;
+    thread::sleep(Duration::from_secs_f64(duration + 1.0));
+    stream.pause().map_err(|e| e.to_string())
+}
+
+/// Play live input until enter is pressed
+fn live(control: MidiControl, options: Options) -> Result<(), String> {
+    let params = OutputStreamParams::new().map_err(|e| e.to_string())?;
+    let mut renderer =
+        AudioRenderer::with_control(control, params.sample_rate(), params.channels());
+    options.configure(renderer.synth_mut());
+    let stream = AudioOut::new(renderer)
+        .start_stream(params)
+        .map_err(|e| e.to_string())?;
+    println!("playing live input, press enter to quit");
+    stdin().lock().lines().next();
+    stream.pause().map_err(|e| e.to_string())
+}
+
+fn render(input: &str, output: &str, options: Options) -> Result<(), String> {
+    let smf = open_smf(input, options.lenient)?;
+    render::render_file(smf, output, options.spec, options.dither, |synth| {
+        options.configure(synth)
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// Command line options, `play` and `live` only use the ones that are not about the output file
+struct Options {
+    spec: WavSpec,
+    dither: Dither,
+    lenient: bool,
+    compressor: CompressorParams,
+    /// zero based
+    sidechain: Option<u8>,
+    tuning: Option<Tuning>,
+    /// zero based programs of bank 0
+    patches: Vec<(u8, Patch)>,
+}
+
+impl Options {
+    fn configure(&self, synth: &mut Synth) {
+        let master = synth.mixer().master_mut();
+        master.compressor_mut().set_params(self.compressor);
+        master.sidechain = self.sidechain;
+        if let Some(tuning) = &self.tuning {
+            synth.set_tuning(tuning.clone());
+        }
+        for (program, patch) in &self.patches {
+            synth.load_patch(0, *program, patch.clone());
+        }
+    }
+}
+
+fn parse_options(options: &[&str]) -> Result<Options, String> {
+    let mut parsed = Options {
+        spec: WavSpec {
+            sample_rate: 44_100,
+            channels: 2,
+            sample_format: WavSampleFormat::Int16,
+        },
+        dither: Dither::Triangular,
+        lenient: false,
+        compressor: CompressorParams::default(),
+        sidechain: None,
+        tuning: None,
+        patches: Vec::new(),
+    };
+    let (mut scl, mut kbm) = (None, None);
+    let spec = &mut parsed.spec;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        if *option == "--lenient" {
+            parsed.lenient = true;
+            continue;
+        }
+        let value = options.next().ok_or_else(|| USAGE.to_string())?;
+        match (*option, *value) {
+            ("--rate", rate) => spec.sample_rate = rate.parse().map_err(|_| USAGE.to_string())?,
+            ("--channels", channels) => {
+                spec.channels = channels.parse().map_err(|_| USAGE.to_string())?
+            }
+            ("--format", "s16") => spec.sample_format = WavSampleFormat::Int16,
+            ("--format", "s24") => spec.sample_format = WavSampleFormat::Int24,
+            ("--format", "f32") => spec.sample_format = WavSampleFormat::Float32,
+            ("--dither", "none") => parsed.dither = Dither::None,
+            ("--dither", "rect") => parsed.dither = Dither::Rectangular,
+            ("--dither", "tpdf") => parsed.dither = Dither::Triangular,
+            ("--threshold", threshold) => {
+                parsed.compressor.threshold = parse_finite(threshold)?.min(0.0)
+            }
+            ("--ratio", ratio) => parsed.compressor.ratio = parse_finite(ratio)?,
+            ("--sidechain", channel) => match channel.parse::<u8>() {
+                Ok(channel @ 1..=16) => parsed.sidechain = Some(channel - 1),
+                _ => return Err(USAGE.to_string()),
+            },
+            ("--scl", path) => scl = Some(path),
+            ("--kbm", path) => kbm = Some(path),
+            ("--patch", patch) => {
+                let (program, path) = patch.split_once('=').ok_or_else(|| USAGE.to_string())?;
+                let program = match program.parse::<u8>() {
+                    Ok(program @ 1..=128) => program - 1,
+                    _ => return Err(USAGE.to_string()),
+                };
+                let patch = Patch::open(path).map_err(|e| format!("{}: {}", path, e))?;
+                parsed.patches.push((program, patch));
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if parsed.spec.sample_rate == 0 || parsed.spec.channels == 0 {
+        return Err(USAGE.to_string());
+    }
+    parsed.tuning = match (scl, kbm) {
+        (Some(scl), kbm) => Some(Tuning::open(scl, kbm).map_err(|e| e.to_string())?),
+        (None, Some(_)) => return Err(USAGE.to_string()),
+        (None, None) => None,
+    };
+    Ok(parsed)
+}
+
+fn parse_finite(value: &str) -> Result<f32, String> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| USAGE.to_string())
+}
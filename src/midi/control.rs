@@ -1,96 +1,409 @@
-// use crate::controller::Controller;
-// use std::sync::mpsc::{channel, Receiver, Sender};
-
-// const MIDDLE_C: f64 = 440.0;
-// const NEGATIVE_C: f64 = MIDDLE_C / 32_f64;
-
-// pub struct MidiControl<I> {
-//     input: (Sender<I>, Receiver<I>),
-// }
-
-// impl<I> MidiControl<I> {
-//     fn new() -> Self {
-//         let input = channel::<I>();
-//         Self { input }
-//     }
-// }
-
-// impl Controller for MidiControl<(bool, u8)> {
-//     type InputMsg = (bool, u8);
-//     type OutputMsg = MidiMessage;
-
-//     fn get_connect(&self) -> Sender<Self::InputMsg> {
-//         self.input.0.clone()
-//     }
-
-//     fn output(&self) -> Option<Self::OutputMsg> {
-//         self.input.1.recv().ok().map(|(on, code)| {
-//             if on {
-//                 MidiMessage::NoteOn(KeyCode(code))
-//             } else {
-//                 MidiMessage::NoteOff(KeyCode(code))
-//             }
-//         })
-//     }
-// }
-
-// #[derive(Debug, PartialEq, Eq)]
-// pub struct KeyCode(u8);
-
-// impl KeyCode {
-//     const MIN: u8 = 0;
-//     const MAX: u8 = 127;
-
-//     fn as_hz(self) -> f64 {
-//         NEGATIVE_C * 2_f64.powf(f64::from(self.0) / 12_f64)
-//     }
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use std::{thread, time::Duration};
-//     #[test]
-//     fn key_to_f64_freq() {
-//         let c4 = KeyCode(60);
-//         let d4 = KeyCode(62);
-//         assert!(c4.as_hz() - 440.0 < 0.01);
-//         assert!(d4.as_hz() - 493.88 < 0.01);
-//     }
-
-//     #[test]
-//     fn midi_in_out() {
-//         let midictl = MidiControl::<(bool, u8)>::new();
-//         let midictl_conn = midictl.get_connect();
-//         let press_code = thread::spawn(move || {
-//             // press c4
-//             midictl_conn.send((true, 60)).unwrap();
-//             thread::sleep(Duration::from_millis(100));
-//             midictl_conn.send((false, 60)).unwrap();
-//             // wait
-//             thread::sleep(Duration::from_millis(200));
-//             // press c5
-//             midictl_conn.send((true, 61)).unwrap();
-//             thread::sleep(Duration::from_millis(100));
-//             midictl_conn.send((false, 61)).unwrap();
-//         });
-//         let _listener = thread::spawn(move || {
-//             while let Some(msg) = midictl.output() {
-//                 println!(
-//                     "{}",
-//                     match msg {
-//                         MidiMessage::NoteOn(code) => format!("on {}", code.0),
-//                         MidiMessage::NoteOff(code) => format!("off {}", code.0),
-//                     }
-//                 )
-//             }
-//         });
-//         press_code.join().unwrap();
-//     }
-
-//     #[test]
-//     fn variablelenval_to_real_value() {
-//         let vlv = VariableLenVal::new(vec![82, 80, 00]);
-//         assert_eq!(vlv.to_real_value(), 32768);
-//     }
-// }
+use std::{
+    fs::File,
+    io::{Error as StdIoError, ErrorKind, Read},
+    path::Path,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+};
+
+use crate::{
+    controller::Controller,
+    midi::{
+        formats::{MidiMessage, U14, U7},
+        sysex::SysexMessage,
+    },
+};
+
+/// Event decoded from a live byte stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveEvent {
+    /// Channel message, the same as in a file
+    Midi { channel: u8, midi_msg: MidiMessage },
+    /// Sysex data after the leading 0xF0, ending with 0xF7 like in a file
+    Sysex(Vec<u8>),
+    /// Sysex the synth acts on, decoded as it arrives instead of passed on as data
+    KnownSysex(SysexMessage),
+    /// System realtime status byte: clock, start, continue, stop, active sensing or reset
+    Realtime(u8),
+}
+
+/// Raw MIDI byte stream decoder
+///
+/// Follows the running status, passes realtime bytes through wherever they appear,
+/// even inside a sysex message, and drops system common messages and stray data bytes.
+/// Sysex messages the synth knows are decoded here, off the audio thread.
+///
+/// [MIDI Communication Protocol](http://www.ccarh.org/courses/253/handout/midiprotocol/)
+#[derive(Debug, Default)]
+pub struct MidiDecoder {
+    /// status of the current channel message, 0 if none
+    status: u8,
+    data: [u8; 2],
+    len: usize,
+    /// sysex data received so far
+    sysex: Option<Vec<u8>>,
+    /// data bytes of a system common message left to drop
+    skip: usize,
+}
+
+impl MidiDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the event `byte` completes
+    pub fn feed(&mut self, byte: u8) -> Option<LiveEvent> {
+        match byte {
+            0xF8..=0xFF => return Some(LiveEvent::Realtime(byte)),
+            0xF0 => {
+                self.status = 0;
+                self.skip = 0;
+                self.sysex = Some(Vec::new());
+            }
+            0xF7 => {
+                self.status = 0;
+                if let Some(mut sysex) = self.sysex.take() {
+                    if let Some(message) = SysexMessage::decode(&sysex) {
+                        return Some(LiveEvent::KnownSysex(message));
+                    }
+                    sysex.push(0xF7);
+                    return Some(LiveEvent::Sysex(sysex));
+                }
+            }
+            // system common messages cancel the running status and an unfinished sysex
+            0xF1..=0xF6 => {
+                self.status = 0;
+                self.sysex = None;
+                self.skip = match byte {
+                    0xF1 | 0xF3 => 1,
+                    0xF2 => 2,
+                    _ => 0,
+                };
+            }
+            0x80..=0xEF => {
+                self.status = byte;
+                self.len = 0;
+                self.sysex = None;
+                self.skip = 0;
+            }
+            data => {
+                if let Some(sysex) = self.sysex.as_mut() {
+                    sysex.push(data);
+                } else if self.skip > 0 {
+                    self.skip -= 1;
+                } else if self.status != 0 {
+                    self.data[self.len] = data;
+                    self.len += 1;
+                    if self.len == data_len(self.status) {
+                        self.len = 0;
+                        return Some(self.message());
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Decode a chunk of the stream, a message may span several chunks
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<LiveEvent> {
+        bytes.iter().filter_map(|byte| self.feed(*byte)).collect()
+    }
+
+    fn message(&self) -> LiveEvent {
+        let [first, second] = self.data;
+        let (key, vel) = (U7::new(first), U7::new(second));
+        LiveEvent::Midi {
+            channel: self.status & 0xF,
+            midi_msg: match self.status >> 4 {
+                0x8 => MidiMessage::NoteOff { key, vel },
+                0x9 => MidiMessage::NoteOn { key, vel },
+                0xA => MidiMessage::Aftertouch { key, vel },
+                0xB => MidiMessage::ControlChange {
+                    controller: key,
+                    value: vel,
+                },
+                0xC => MidiMessage::PatchChange { program: key },
+                0xD => MidiMessage::ChannelPressure { vel: key },
+                0xE => MidiMessage::PitchBend {
+                    value: U14::new(u16::from(second) << 7 | u16::from(first)),
+                },
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+/// Number of data bytes of a channel message
+fn data_len(status: u8) -> usize {
+    match status >> 4 {
+        0xC | 0xD => 1,
+        _ => 2,
+    }
+}
+
+/// Live MIDI input
+///
+/// Raw bytes sent through [`Controller::get_connect`] are decoded on a thread of their own,
+/// events come out of [`Controller::output`] or [`MidiControl::try_output`].
+pub struct MidiControl {
+    input: Sender<Vec<u8>>,
+    output: Receiver<LiveEvent>,
+}
+
+impl Default for MidiControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiControl {
+    pub fn new() -> Self {
+        let (input, bytes) = channel::<Vec<u8>>();
+        let (events, output) = channel();
+        thread::spawn(move || {
+            let mut decoder = MidiDecoder::new();
+            for chunk in bytes {
+                for event in decoder.decode(&chunk) {
+                    if events.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Self { input, output }
+    }
+
+    /// Read a raw byte stream: a FIFO, or an ALSA raw MIDI device such as `/dev/snd/midiC1D0`
+    ///
+    /// Opening a FIFO blocks until a writer opens it too.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StdIoError> {
+        Ok(Self::from_reader(File::open(path)?))
+    }
+
+    /// Forward everything `reader` yields until it ends
+    pub fn from_reader<R: Read + Send + 'static>(mut reader: R) -> Self {
+        let control = Self::new();
+        let input = control.get_connect();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 256];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        if input.send(buffer[..len].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        control
+    }
+
+    /// Create an ALSA sequencer client called `name` with a port other clients connect to,
+    /// e.g. `aconnect <keyboard> <name>`
+    #[cfg(target_os = "linux")]
+    pub fn alsa_seq(name: &str) -> Result<Self, alsa::Error> {
+        use alsa::seq::{MidiEvent, PortCap, PortType, Seq};
+        use std::{ffi::CString, time::Duration};
+
+        /// Failed reads in a row before the input thread gives up
+        const RETRIES: u32 = 10;
+        const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+        let seq = Seq::open(None, Some(alsa::Direction::Capture), false)?;
+        let name = CString::new(name.replace('\0', "")).unwrap_or_default();
+        seq.set_client_name(&name)?;
+        seq.create_simple_port(
+            &name,
+            PortCap::WRITE | PortCap::SUBS_WRITE,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+
+        let control = Self::new();
+        let input = control.get_connect();
+        thread::spawn(move || {
+            // the coder is not `Send`
+            let Ok(coder) = MidiEvent::new(256) else {
+                return;
+            };
+            coder.enable_running_status(false);
+            let mut seq_input = seq.input();
+            let mut buffer = [0u8; 256];
+            let mut failures = 0;
+            loop {
+                // overruns and interrupts are worth a retry, a sequencer that keeps failing is gone
+                let mut event = match seq_input.event_input() {
+                    Ok(event) => {
+                        failures = 0;
+                        event
+                    }
+                    Err(_) if failures < RETRIES => {
+                        failures += 1;
+                        thread::sleep(RETRY_DELAY);
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+                if let Ok(len) = coder.decode(&mut buffer, &mut event) {
+                    if len > 0 && input.send(buffer[..len].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(control)
+    }
+
+    /// Returns the next event without waiting
+    pub fn try_output(&self) -> Option<LiveEvent> {
+        self.output.try_recv().ok()
+    }
+}
+
+impl Controller for MidiControl {
+    type InputMsg = Sender<Vec<u8>>;
+    type OutputMsg = LiveEvent;
+
+    fn get_connect(&self) -> Self::InputMsg {
+        self.input.clone()
+    }
+
+    /// Blocks until the next event
+    fn output(&self) -> Option<Self::OutputMsg> {
+        self.output.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::sysex::UniversalSysex;
+    use std::io::Cursor;
+
+    fn note_on(channel: u8, key: u8, vel: u8) -> LiveEvent {
+        LiveEvent::Midi {
+            channel,
+            midi_msg: MidiMessage::NoteOn {
+                key: U7::new(key),
+                vel: U7::new(vel),
+            },
+        }
+    }
+
+    #[test]
+    fn running_status() {
+        let mut decoder = MidiDecoder::new();
+        let events = decoder.decode(&[0x91, 60, 100, 64, 90, 60, 0]);
+        assert_eq!(
+            events,
+            vec![note_on(1, 60, 100), note_on(1, 64, 90), note_on(1, 60, 0)]
+        );
+        // one data byte messages
+        assert_eq!(
+            decoder.decode(&[0xC2, 5, 6]),
+            vec![
+                LiveEvent::Midi {
+                    channel: 2,
+                    midi_msg: MidiMessage::PatchChange {
+                        program: U7::new(5)
+                    }
+                },
+                LiveEvent::Midi {
+                    channel: 2,
+                    midi_msg: MidiMessage::PatchChange {
+                        program: U7::new(6)
+                    }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn message_split_across_chunks() {
+        let mut decoder = MidiDecoder::new();
+        assert!(decoder.decode(&[0xE0, 0x00]).is_empty());
+        assert_eq!(
+            decoder.decode(&[0x40]),
+            vec![LiveEvent::Midi {
+                channel: 0,
+                midi_msg: MidiMessage::PitchBend {
+                    value: U14::new(0x2000)
+                }
+            }]
+        );
+    }
+
+    #[test]
+    fn realtime_interleaved() {
+        let mut decoder = MidiDecoder::new();
+        let events = decoder.decode(&[0x90, 0xF8, 60, 0xFA, 100, 0xF0, 0x7E, 0xF8, 0x09, 0xF7]);
+        assert_eq!(
+            events,
+            vec![
+                LiveEvent::Realtime(0xF8),
+                LiveEvent::Realtime(0xFA),
+                note_on(0, 60, 100),
+                LiveEvent::Realtime(0xF8),
+                LiveEvent::Sysex(vec![0x7E, 0x09, 0xF7]),
+            ]
+        );
+    }
+
+    #[test]
+    fn sysex_cancels_running_status() {
+        let mut decoder = MidiDecoder::new();
+        let events = decoder.decode(&[0x90, 60, 100, 0xF0, 0x01, 0xF7, 62, 100]);
+        assert_eq!(
+            events,
+            vec![note_on(0, 60, 100), LiveEvent::Sysex(vec![0x01, 0xF7])]
+        );
+    }
+
+    #[test]
+    fn known_sysex_is_decoded() {
+        let mut decoder = MidiDecoder::new();
+        assert_eq!(
+            decoder.decode(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7]),
+            vec![LiveEvent::KnownSysex(SysexMessage::Universal(
+                UniversalSysex::GmSystemOn
+            ))]
+        );
+    }
+
+    #[test]
+    fn system_common_is_dropped() {
+        let mut decoder = MidiDecoder::new();
+        // stray data, song position, tune request, then a note
+        let events = decoder.decode(&[60, 0xF2, 0x10, 0x20, 0xF6, 0x93, 60, 1]);
+        assert_eq!(events, vec![note_on(3, 60, 1)]);
+        // a status byte aborts an unfinished sysex
+        assert_eq!(
+            decoder.decode(&[0xF0, 1, 2, 0x93, 61, 1]),
+            vec![note_on(3, 61, 1)]
+        );
+    }
+
+    #[test]
+    fn reads_byte_stream() {
+        let control = MidiControl::from_reader(Cursor::new(vec![0x90, 60, 100, 60, 0, 0xFC]));
+        assert_eq!(control.output(), Some(note_on(0, 60, 100)));
+        assert_eq!(control.output(), Some(note_on(0, 60, 0)));
+        assert_eq!(control.output(), Some(LiveEvent::Realtime(0xFC)));
+    }
+
+    #[test]
+    fn controller_connection() {
+        let control = MidiControl::new();
+        let connection = control.get_connect();
+        let sender = thread::spawn(move || {
+            connection.send(vec![0x95, 72]).unwrap();
+            connection.send(vec![127]).unwrap();
+        });
+        sender.join().unwrap();
+        assert_eq!(control.output(), Some(note_on(5, 72, 127)));
+        assert_eq!(control.try_output(), None);
+    }
+}
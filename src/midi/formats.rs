@@ -1,16 +1,22 @@
 use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
     fs::File,
-    io::{BufRead, BufReader, Error as StdIoError},
+    io::{
+        copy, sink, BufRead, BufReader, BufWriter, Cursor, Error as StdIoError, ErrorKind, Read,
+        Write,
+    },
     path::Path,
 };
 
 use thiserror::Error;
 
+use super::time::{MeterMap, TempoMap};
+
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error("midi buffer parse error")]
     IOError(#[from] StdIoError),
-    #[error("unexpected tag `{0:?}`")]
+    #[error("unexpected tag `{}`", .0.escape_ascii())]
     UnexpectedTag([u8; 4]),
     #[error("unexpected format `{0}`")]
     UnexpectedFormat(u16),
@@ -20,22 +26,174 @@ pub enum ParseError {
     NotData(u8),
     #[error("not supported system message `{0}`")]
     NotSupportedSystemMessage(u8),
+    #[error("meta event `{kind:#04x}` has unexpected length `{len}`")]
+    MetaLength { kind: u8, len: usize },
+    #[error("meta event `{kind:#04x}` has invalid data `{data:?}`")]
+    MetaValue { kind: u8, data: Vec<u8> },
+    #[error("file ends in the middle of a chunk")]
+    Truncated,
+    #[error("track ends without an end of track event")]
+    MissingEndOfTrack,
+    #[error("{source} {context}")]
+    Context {
+        context: ErrorContext,
+        source: Box<ParseError>,
+    },
+}
+
+/// Where in the file a [`ParseError`] happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// Absolute byte offset of the chunk or event being parsed
+    pub offset: u64,
+    /// Index of the track chunk
+    pub track: Option<usize>,
+    /// Index of the event in the track
+    pub event: Option<usize>,
+}
+
+impl ErrorContext {
+    fn at(offset: u64) -> Self {
+        Self {
+            offset,
+            track: None,
+            event: None,
+        }
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "at byte {}", self.offset)?;
+        if let Some(track) = self.track {
+            write!(f, " in track {}", track)?;
+        }
+        if let Some(event) = self.event {
+            write!(f, " event {}", event)?;
+        }
+        Ok(())
+    }
 }
 
 impl ParseError {
-    pub fn is_eof(&self) -> bool {
+    /// Where the error happened, if known
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context
+    pub fn kind(&self) -> &ParseError {
+        match self {
+            Self::Context { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// Returns true if the file is cut short, as opposed to malformed
+    pub fn is_truncated(&self) -> bool {
+        matches!(self.kind(), Self::Truncated)
+    }
+
+    /// An unexpected end of the input becomes `Truncated`
+    fn bare(self) -> Self {
+        if self.is_eof() {
+            Self::Truncated
+        } else {
+            self
+        }
+    }
+
+    fn at(self, context: ErrorContext) -> Self {
         match self {
-            Self::IOError(ioe) => match ioe.kind() {
-                std::io::ErrorKind::UnexpectedEof => true,
-                _ => false,
+            Self::Context { .. } => self,
+            error => Self::Context {
+                context,
+                source: Box::new(error.bare()),
             },
-            _ => false,
         }
     }
+
+    pub fn is_eof(&self) -> bool {
+        matches!(self, Self::IOError(ioe) if ioe.kind() == std::io::ErrorKind::UnexpectedEof)
+    }
+}
+
+/// Problem a lenient parse recovered from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// Absolute byte offset in the file
+    pub offset: u64,
+    pub kind: WarningKind,
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} at byte {}", self.kind, self.offset)
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    #[error("unknown chunk `{}` of {len} bytes skipped", .tag.escape_ascii())]
+    UnknownChunk { tag: [u8; 4], len: u32 },
+    #[error("chunk of {len} bytes truncated to {available}")]
+    TruncatedChunk { len: u32, available: u64 },
+    #[error("track {track}: rest of the track skipped after {error}")]
+    BadEvent { track: usize, error: String },
+    #[error("track {track}: missing end of track added")]
+    MissingEndOfTrack { track: usize },
+    #[error("track {track}: {len} bytes after the end of track skipped")]
+    TrailingBytes { track: usize, len: usize },
+    #[error("header announces {header} tracks, found {found}")]
+    TrackCount { header: u16, found: usize },
+}
+
+/// Counts the bytes read, for the offsets of diagnostics
+struct CountingReader<B> {
+    inner: B,
+    offset: u64,
+}
+
+impl<B: BufRead> CountingReader<B> {
+    fn new(inner: B) -> Self {
+        Self { inner, offset: 0 }
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<B: BufRead> Read for CountingReader<B> {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, StdIoError> {
+        let len = self.inner.read(out)?;
+        self.offset += len as u64;
+        Ok(len)
+    }
+}
+
+impl<B: BufRead> BufRead for CountingReader<B> {
+    fn fill_buf(&mut self) -> Result<&[u8], StdIoError> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.offset += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+/// Skip up to `len` bytes, returns the number skipped
+fn skip<B: BufRead>(buf: &mut B, len: u64) -> Result<u64, StdIoError> {
+    copy(&mut buf.take(len), &mut sink())
 }
 
 trait ByteChunk: Sized {
     fn read<B: BufRead>(buf: &mut B) -> Result<Self, ParseError>;
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError>;
 }
 
 #[derive(Debug)]
@@ -45,39 +203,116 @@ pub struct Smf {
 }
 
 impl Smf {
+    pub fn new(format: Format, division: Division, tracks: Vec<TrackChunk>) -> Self {
+        Self {
+            header: HeaderChunk {
+                header_len: 6,
+                format,
+                track_num: tracks.len() as u16,
+                division: i16::from(division),
+            },
+            tracks,
+        }
+    }
+
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ParseError> {
         let mut file_buffer = BufReader::new(File::open(path)?);
         Self::read(&mut file_buffer)
     }
 
-    /// Returns the number of milliseconds at 1 tick
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        Self::read(&mut BufReader::new(bytes))
+    }
+
+    /// Recover what can be recovered from a damaged file, see [`Smf::from_bytes_lenient`]
+    pub fn open_lenient<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<ParseWarning>), ParseError> {
+        let mut warnings = Vec::new();
+        let mut file_buffer = BufReader::new(File::open(path)?);
+        let smf = Self::read_with(&mut file_buffer, Some(&mut warnings))?;
+        Ok((smf, warnings))
+    }
+
+    /// Parse, skipping unknown chunks, resyncing on the next chunk after a damaged track
+    /// and adding missing ends of track, with a warning for each repair.
     ///
-    /// [time-division-of-a-midi-file](https://www.recordingblogs.com/wiki/time-division-of-a-midi-file)
-    pub fn timebase(&self) -> usize {
-        let division = self.header.division;
-        let default_tempo: usize = 500_000;
-        if division > 0 {
-            default_tempo / division as usize
-        } else {
-            let fps = ((division >> 8) & 0x007F) as usize;
-            let tpf = (division & 0x00FF) as usize;
-            1_000_000 / (fps * tpf)
+    /// Only a missing or damaged header is an error.
+    pub fn from_bytes_lenient(bytes: &[u8]) -> Result<(Self, Vec<ParseWarning>), ParseError> {
+        let mut warnings = Vec::new();
+        let smf = Self::read_with(&mut BufReader::new(bytes), Some(&mut warnings))?;
+        Ok((smf, warnings))
+    }
+
+    /// Write a .mid file, see [`Smf::to_bytes`]
+    pub fn save<P: AsRef<Path>>(&self, path: P, running_status: bool) -> Result<(), StdIoError> {
+        let mut file_buffer = BufWriter::new(File::create(path)?);
+        self.write_with(&mut file_buffer, running_status)?;
+        file_buffer.flush()
+    }
+
+    /// Serialize back to the file format
+    ///
+    /// With `running_status` the status byte of a channel message is omitted
+    /// when it repeats the previous one.
+    pub fn to_bytes(&self, running_status: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_with(&mut bytes, running_status)
+            .expect("writing to a Vec does not fail");
+        bytes
+    }
+
+    fn write_with<W: Write>(&self, buf: &mut W, running_status: bool) -> Result<(), StdIoError> {
+        // the track count follows the tracks actually written
+        HeaderChunk {
+            track_num: self.tracks.len() as u16,
+            ..self.header.clone()
+        }
+        .write(buf)?;
+        for track in &self.tracks {
+            track.write_with(buf, running_status)?;
+        }
+        Ok(())
+    }
+
+    /// Tracks played together: all of them, or the first song of a `Format::MultipleSong` file
+    fn song_tracks(&self) -> &[TrackChunk] {
+        match self.format() {
+            Format::SingleTrack | Format::MultipleTrack => &self.tracks,
+            Format::MultipleSong => &self.tracks[..self.tracks.len().min(1)],
         }
     }
 
+    /// Tick to wall clock conversion of the song
+    ///
+    /// [time-division-of-a-midi-file](https://www.recordingblogs.com/wiki/time-division-of-a-midi-file)
+    pub fn tempo_map(&self) -> TempoMap {
+        TempoMap::from_tracks(self.division(), self.song_tracks())
+    }
+
+    /// Tick to bar:beat:tick conversion of the song
+    pub fn meter_map(&self) -> MeterMap {
+        MeterMap::from_tracks(self.division(), self.song_tracks())
+    }
+
     /// Returns format type and number of tracks
     pub fn format(&self) -> Format {
         self.header.format
     }
 
+    pub fn division(&self) -> Division {
+        self.header.division()
+    }
+
     pub fn tracks(&self) -> &Vec<TrackChunk> {
         &self.tracks
     }
+
+    pub fn tracks_mut(&mut self) -> &mut Vec<TrackChunk> {
+        &mut self.tracks
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HeaderChunk {
-    tag: Tag,
     header_len: u32,
     format: Format,
     track_num: u16,
@@ -91,9 +326,52 @@ impl HeaderChunk {
     pub fn track_num(&self) -> u16 {
         self.track_num
     }
+
+    pub fn division(&self) -> Division {
+        Division::from(self.division)
+    }
+}
+
+/// Time division of a header chunk
+///
+/// [time-division-of-a-midi-file](https://www.recordingblogs.com/wiki/time-division-of-a-midi-file)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Division {
+    /// Ticks per quarter note
+    Metrical(u16),
+    /// SMPTE frames per second (24, 25, 29 = 29.97 drop frame, 30) and ticks per frame
+    Timecode { fps: u8, ticks_per_frame: u8 },
+}
+
+impl From<i16> for Division {
+    fn from(division: i16) -> Self {
+        if division >= 0 {
+            Self::Metrical(division as u16)
+        } else {
+            // the upper byte is the negative frame rate in two's complement
+            let fps = ((division >> 8) as i8).unsigned_abs();
+            let ticks_per_frame = (division & 0x00FF) as u8;
+            Self::Timecode {
+                fps,
+                ticks_per_frame,
+            }
+        }
+    }
+}
+
+impl From<Division> for i16 {
+    fn from(division: Division) -> Self {
+        match division {
+            Division::Metrical(ticks) => (ticks & 0x7FFF) as i16,
+            Division::Timecode {
+                fps,
+                ticks_per_frame,
+            } => i16::from_be_bytes([(fps as i8).wrapping_neg() as u8, ticks_per_frame]),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Format {
     SingleTrack,
     MultipleTrack,
@@ -102,24 +380,85 @@ pub enum Format {
 
 #[derive(Debug)]
 pub struct TrackChunk {
-    tag: Tag,
-    track_len: u32,
     events: Vec<TrackEvent>,
 }
 
-#[derive(Debug)]
+impl TrackChunk {
+    /// An end of track event is added on write if `events` lacks one
+    pub fn new(events: Vec<TrackEvent>) -> Self {
+        Self { events }
+    }
+
+    pub fn events(&self) -> &Vec<TrackEvent> {
+        &self.events
+    }
+
+    pub fn events_mut(&mut self) -> &mut Vec<TrackEvent> {
+        &mut self.events
+    }
+
+    /// The chunk length is computed from the events
+    fn write_with<W: Write>(&self, buf: &mut W, running_status: bool) -> Result<(), StdIoError> {
+        let mut events = Vec::new();
+        let mut previous_status = 0u8;
+        for TrackEvent { delta, event } in &self.events {
+            delta.write(&mut events)?;
+            match event {
+                Event::Midi { channel, midi_msg } => {
+                    let status = midi_msg.command() << 4 | channel & 0xF;
+                    if !running_status || status != previous_status {
+                        status.write(&mut events)?;
+                    }
+                    previous_status = status;
+                    midi_msg.write(&mut events)?;
+                }
+                // sysex and meta events cancel the running status
+                Event::Sysex { sysex_msg } => {
+                    previous_status = 0;
+                    0xF0u8.write(&mut events)?;
+                    sysex_msg.write(&mut events)?;
+                }
+                Event::SysexContinuation { sysex_msg: data } | Event::Escape { data } => {
+                    previous_status = 0;
+                    0xF7u8.write(&mut events)?;
+                    data.write(&mut events)?;
+                }
+                Event::Meta { meta_msg } => {
+                    previous_status = 0;
+                    0xFFu8.write(&mut events)?;
+                    meta_msg.write(&mut events)?;
+                }
+            }
+        }
+        if !self.events.last().is_some_and(|e| e.event.is_end()) {
+            events.extend([0x00, 0xFF, 0x2F, 0x00]);
+        }
+        Tag::Track.write(buf)?;
+        (events.len() as u32).write(buf)?;
+        buf.write_all(&events)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Tag {
     Header,
     Track,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TrackEvent {
     delta: U28,
     event: Event,
 }
 
 impl TrackEvent {
+    pub fn new(delta: u32, event: Event) -> Self {
+        Self {
+            delta: U28(delta & 0x0FFF_FFFF),
+            event,
+        }
+    }
+
     /// Returns tick and event pair
     pub fn event(&self) -> (u32, &Event) {
         (self.delta.0, &self.event)
@@ -127,18 +466,76 @@ impl TrackEvent {
 }
 
 /// Variable Length Values
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct U28(u32);
 
 /// U28 + U28 * u8
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Slice(Vec<u8>);
 
+impl From<Vec<u8>> for Slice {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
 impl Slice {
-    fn to_ascii(self) -> String {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Inverse of `to_ascii`
+    fn from_ascii(text: &str) -> Self {
+        #[inline]
+        fn unhexify(b: u8) -> u8 {
+            match b {
+                b'0'..=b'9' => b - b'0',
+                b'a'..=b'f' => b - b'a' + 10,
+                _ => 0,
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(text.len());
+        let mut chars = text.bytes();
+        while let Some(c) = chars.next() {
+            if c != b'\\' {
+                bytes.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some(b't') => bytes.push(b'\t'),
+                Some(b'r') => bytes.push(b'\r'),
+                Some(b'n') => bytes.push(b'\n'),
+                Some(b'x') => {
+                    let high = chars.next().map_or(0, unhexify);
+                    let low = chars.next().map_or(0, unhexify);
+                    bytes.push(high << 4 | low);
+                }
+                Some(c) => bytes.push(c),
+                None => bytes.push(b'\\'),
+            }
+        }
+        Self(bytes)
+    }
+
+    /// Returns the data if it has exactly `N` bytes
+    fn exact<const N: usize>(self, kind: u8) -> Result<[u8; N], ParseError> {
+        let len = self.0.len();
+        self.0
+            .try_into()
+            .map_err(|_| ParseError::MetaLength { kind, len })
+    }
+
+    /// Big endian, at most 4 bytes
+    fn from_u32(num: u32, len: usize) -> Self {
+        let len = len.min(4);
+        Self(num.to_be_bytes()[4 - len..].to_vec())
+    }
+
+    fn to_ascii(&self) -> String {
         self.0
-            .into_iter()
-            .map(|c| {
+            .iter()
+            .map(|&c| {
                 #[inline]
                 fn hexify(b: u8) -> u8 {
                     match b {
@@ -163,9 +560,9 @@ impl Slice {
             .collect::<String>()
     }
 
-    fn to_u32(self) -> u32 {
+    fn to_u32(&self) -> u32 {
         let mut num = 0u32;
-        let nums = self.0;
+        let nums = &self.0;
         for i in 0..4 {
             if let Some(n) = nums.get(i) {
                 num <<= 8;
@@ -179,33 +576,64 @@ impl Slice {
 /// ```txt
 /// Midi: delta(U28)+status(u4>7)+channel(u4)+data
 /// Meta: delta(U28)+status(0xFF)+type(u8)+U28+U28*u8
-/// Sysex: delta(U28)+status(0xF0|0xF7)+data(n*u8)+end(0xF7)
+/// Sysex: delta(U28)+status(0xF0)+U28+data(n*u8)[+end(0xF7)]
+/// Sysex continuation or escape: delta(U28)+status(0xF7)+U28+data(n*u8)
 /// if status < 8: omit status; status = previous status
 /// ```
+/// A sysex message without the closing 0xF7 goes on in the following 0xF7 packets,
+/// every other 0xF7 packet escapes bytes that are sent as they are.
+///
 /// - [midi-event](https://www.recordingblogs.com/wiki/midi-event)
 /// - [status-byte-of-a-midi-message](https://www.recordingblogs.com/wiki/status-byte-of-a-midi-message)
-#[derive(Debug)]
+/// - [sysex in SMF](http://www.somascape.org/midi/tech/mfile.html#sysex)
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
-    Meta { meta_msg: MetaMessage },
-    Midi { channel: u8, midi_msg: MidiMessage },
-    Sysex { sysex_msg: Slice },
+    Meta {
+        meta_msg: MetaMessage,
+    },
+    Midi {
+        channel: u8,
+        midi_msg: MidiMessage,
+    },
+    /// Data after the leading 0xF0
+    Sysex {
+        sysex_msg: Slice,
+    },
+    /// Next packet of a sysex message that did not end with 0xF7
+    SysexContinuation {
+        sysex_msg: Slice,
+    },
+    /// Arbitrary bytes such as realtime or song position messages
+    Escape {
+        data: Slice,
+    },
 }
 
 impl Event {
     pub fn is_end(&self) -> bool {
-        match &self {
-            Event::Meta { meta_msg } => match meta_msg {
-                MetaMessage::EndOfTrack(_) => true,
-                _ => false,
-            },
+        matches!(
+            self,
+            Event::Meta {
+                meta_msg: MetaMessage::EndOfTrack(_)
+            }
+        )
+    }
+
+    /// Returns true for a sysex packet that is followed by a continuation
+    pub fn opens_sysex(&self) -> bool {
+        match self {
+            Event::Sysex { sysex_msg } | Event::SysexContinuation { sysex_msg } => {
+                sysex_msg.0.last() != Some(&0xF7)
+            }
             _ => false,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MetaMessage {
-    SequenceNumber(Slice),
+    /// `None` numbers sequences by their position in the file
+    SequenceNumber(Option<u16>),
     Text(String),
     Copyright(String),
     TrackName(String),
@@ -213,7 +641,8 @@ pub enum MetaMessage {
     Lyric(String),
     Marker(String),
     CuePoint(String),
-    ChannelPrefix(Slice),
+    /// Channel the following meta and sysex events refer to
+    ChannelPrefix(u8),
     EndOfTrack(Slice),
     /// value 0x07A120 (500000 decimal) means that there are 500,000 microseconds per quarter note.
     ///
@@ -225,24 +654,173 @@ pub enum MetaMessage {
     ///
     /// [midi-set-tempo-meta-message](https://www.recordingblogs.com/wiki/midi-set-tempo-meta-message)
     Tempo(u32),
-    SmpteOffset(Slice),
-    TimeSignature(Slice),
-    KeySignature(Slice),
+    SmpteOffset(SmpteOffset),
+    TimeSignature(TimeSignature),
+    KeySignature(KeySignature),
     SequencerSpecific(Slice),
-    Unknown(Slice),
+    /// meta type and data
+    Unknown(u8, Slice),
+}
+
+/// SMPTE time the track starts at
+///
+/// [midi-smpte-offset-meta-message](https://www.recordingblogs.com/wiki/midi-smpte-offset-meta-message)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmpteOffset {
+    /// 24, 25, 29 (29.97 drop frame) or 30
+    pub fps: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    /// 1/100 of a frame
+    pub subframes: u8,
+}
+
+impl SmpteOffset {
+    /// The frame rate is in the top bits of the hour byte
+    const FPS: [u8; 4] = [24, 25, 29, 30];
+
+    fn from_bytes([hour, minutes, seconds, frames, subframes]: [u8; 5]) -> Option<Self> {
+        let offset = Self {
+            fps: Self::FPS[usize::from(hour >> 5 & 0x3)],
+            hours: hour & 0x1F,
+            minutes,
+            seconds,
+            frames,
+            subframes,
+        };
+        let valid = hour < 0x80
+            && offset.hours < 24
+            && minutes < 60
+            && seconds < 60
+            && frames < 30
+            && subframes < 100;
+        valid.then_some(offset)
+    }
+
+    fn to_bytes(self) -> [u8; 5] {
+        let rate = Self::FPS
+            .iter()
+            .position(|fps| *fps == self.fps)
+            .unwrap_or(0) as u8;
+        [
+            rate << 5 | self.hours & 0x1F,
+            self.minutes,
+            self.seconds,
+            self.frames,
+            self.subframes,
+        ]
+    }
+}
+
+/// [midi-time-signature-meta-message](https://www.recordingblogs.com/wiki/midi-time-signature-meta-message)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    /// The denominator is 2 to this power
+    pub denominator_power: u8,
+    /// MIDI clocks in a metronome click
+    pub clocks_per_click: u8,
+    /// Notated 32nd notes in a MIDI quarter note (24 MIDI clocks)
+    pub thirty_seconds_per_quarter: u8,
+}
+
+impl TimeSignature {
+    pub fn denominator(&self) -> u32 {
+        1 << self.denominator_power.min(31)
+    }
+
+    fn from_bytes(
+        [numerator, denominator_power, clocks_per_click, thirty_seconds_per_quarter]: [u8; 4],
+    ) -> Option<Self> {
+        let valid = numerator > 0 && denominator_power < 32;
+        valid.then_some(Self {
+            numerator,
+            denominator_power,
+            clocks_per_click,
+            thirty_seconds_per_quarter,
+        })
+    }
+
+    fn to_bytes(self) -> [u8; 4] {
+        [
+            self.numerator,
+            self.denominator_power,
+            self.clocks_per_click,
+            self.thirty_seconds_per_quarter,
+        ]
+    }
+}
+
+impl Default for TimeSignature {
+    /// 4/4, a click every quarter note
+    fn default() -> Self {
+        Self {
+            numerator: 4,
+            denominator_power: 2,
+            clocks_per_click: 24,
+            thirty_seconds_per_quarter: 8,
+        }
+    }
+}
+
+/// [midi-key-signature-meta-message](https://www.recordingblogs.com/wiki/midi-key-signature-meta-message)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySignature {
+    /// Number of sharps, negative for flats, in `[-7, 7]`
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+impl KeySignature {
+    fn from_bytes([sharps, mode]: [u8; 2]) -> Option<Self> {
+        let sharps = sharps as i8;
+        let valid = (-7..=7).contains(&sharps) && mode <= 1;
+        valid.then_some(Self {
+            sharps,
+            minor: mode == 1,
+        })
+    }
+
+    fn to_bytes(self) -> [u8; 2] {
+        [self.sharps as u8, u8::from(self.minor)]
+    }
 }
 
 /// Midi data < 80
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct U7(u8);
 
+impl U7 {
+    /// The top bit is dropped
+    pub fn new(value: u8) -> Self {
+        Self(value & 0x7F)
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
 /// Little endian and removing the top-most bit of each byte
 ///
 /// [midi-pitch-wheel-message](https://www.recordingblogs.com/wiki/midi-pitch-wheel-message)
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct U14(u16);
 
-#[derive(Debug)]
+impl U14 {
+    /// The top two bits are dropped
+    pub fn new(value: u16) -> Self {
+        Self(value & 0x3FFF)
+    }
+
+    pub fn value(&self) -> u16 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MidiMessage {
     /// Stop playing a note.
     NoteOff { key: U7, vel: U7 },
@@ -260,12 +838,62 @@ pub enum MidiMessage {
     PitchBend { value: U14 },
 }
 
+impl MidiMessage {
+    /// High nibble of the status byte
+    fn command(&self) -> u8 {
+        match self {
+            Self::NoteOff { .. } => 0x8,
+            Self::NoteOn { .. } => 0x9,
+            Self::Aftertouch { .. } => 0xA,
+            Self::ControlChange { .. } => 0xB,
+            Self::PatchChange { .. } => 0xC,
+            Self::ChannelPressure { .. } => 0xD,
+            Self::PitchBend { .. } => 0xE,
+        }
+    }
+
+    /// Data bytes, without the status byte
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        match self {
+            Self::NoteOff { key, vel }
+            | Self::NoteOn { key, vel }
+            | Self::Aftertouch { key, vel } => {
+                key.write(buf)?;
+                vel.write(buf)
+            }
+            Self::ControlChange { controller, value } => {
+                controller.write(buf)?;
+                value.write(buf)
+            }
+            Self::PatchChange { program } => program.write(buf),
+            Self::ChannelPressure { vel } => vel.write(buf),
+            Self::PitchBend { value } => value.write(buf),
+        }
+    }
+}
+
+impl ByteChunk for [u8; 4] {
+    fn read<B: BufRead>(buf: &mut B) -> Result<Self, ParseError> {
+        let mut bytes = [0u8; 4];
+        buf.read_exact(bytes.as_mut())?;
+        Ok(bytes)
+    }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        buf.write_all(self)
+    }
+}
+
 impl ByteChunk for u8 {
     fn read<B: BufRead>(buf: &mut B) -> Result<Self, ParseError> {
         let mut bytes = [0u8; 1];
         buf.read_exact(bytes.as_mut())?;
         Ok(u8::from_be_bytes(bytes))
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        buf.write_all(&self.to_be_bytes())
+    }
 }
 
 impl ByteChunk for u32 {
@@ -274,6 +902,10 @@ impl ByteChunk for u32 {
         buf.read_exact(bytes.as_mut())?;
         Ok(u32::from_be_bytes(bytes))
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        buf.write_all(&self.to_be_bytes())
+    }
 }
 
 impl ByteChunk for u16 {
@@ -282,6 +914,10 @@ impl ByteChunk for u16 {
         buf.read_exact(bytes.as_mut())?;
         Ok(u16::from_be_bytes(bytes))
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        buf.write_all(&self.to_be_bytes())
+    }
 }
 
 impl ByteChunk for i16 {
@@ -290,44 +926,138 @@ impl ByteChunk for i16 {
         buf.read_exact(bytes.as_mut())?;
         Ok(i16::from_be_bytes(bytes))
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        buf.write_all(&self.to_be_bytes())
+    }
 }
 
 impl ByteChunk for Smf {
     fn read<B: BufRead>(buf: &mut B) -> Result<Self, ParseError> {
-        Ok(Self {
-            header: HeaderChunk::read(buf)?,
-            tracks: {
-                let mut tracks = Vec::<TrackChunk>::new();
-                loop {
-                    match TrackChunk::read(buf) {
-                        Ok(track) => {
-                            tracks.push(track);
-                        }
-                        Err(pe) => {
-                            if pe.is_eof() {
-                                break;
-                            } else {
-                                Err(pe)?
-                            }
+        Self::read_with(buf, None)
+    }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        self.write_with(buf, false)
+    }
+}
+
+impl Smf {
+    /// Strict without `warnings`: any unknown chunk, bad event or truncation is an error,
+    /// the file may only end on a chunk boundary.
+    ///
+    /// Lenient with `warnings`: unknown chunks are skipped by their length,
+    /// truncated and damaged tracks keep the events before the damage.
+    fn read_with<B: BufRead>(
+        buf: &mut B,
+        mut warnings: Option<&mut Vec<ParseWarning>>,
+    ) -> Result<Self, ParseError> {
+        let mut buf = CountingReader::new(buf);
+        let header = HeaderChunk::read(&mut buf).map_err(|e| e.at(ErrorContext::at(0)))?;
+        skip(&mut buf, u64::from(header.header_len.saturating_sub(6)))?;
+        let mut tracks = Vec::<TrackChunk>::new();
+        loop {
+            let offset = buf.offset();
+            let (tag, len) =
+                match <[u8; 4]>::read(&mut buf).and_then(|tag| Ok((tag, u32::read(&mut buf)?))) {
+                    Ok(chunk) => chunk,
+                    // a clean end
+                    Err(pe) if pe.is_eof() && buf.offset() == offset => break,
+                    Err(pe) if pe.is_eof() => match warnings.as_deref_mut() {
+                        Some(warnings) => {
+                            warnings.push(ParseWarning {
+                                offset,
+                                kind: WarningKind::TruncatedChunk {
+                                    len: 8,
+                                    available: buf.offset() - offset,
+                                },
+                            });
+                            break;
                         }
-                    }
+                        None => Err(ParseError::Truncated.at(ErrorContext::at(offset)))?,
+                    },
+                    Err(pe) => Err(pe.at(ErrorContext::at(offset)))?,
+                };
+            let body_offset = buf.offset();
+            if &tag != b"MTrk" {
+                let Some(warnings) = warnings.as_deref_mut() else {
+                    Err(ParseError::UnexpectedTag(tag).at(ErrorContext::at(offset)))?
+                };
+                warnings.push(ParseWarning {
+                    offset,
+                    kind: WarningKind::UnknownChunk { tag, len },
+                });
+                let available = skip(&mut buf, u64::from(len))?;
+                if available < u64::from(len) {
+                    warnings.push(ParseWarning {
+                        offset: body_offset,
+                        kind: WarningKind::TruncatedChunk { len, available },
+                    });
                 }
-                tracks
-            },
-        })
+                continue;
+            }
+            let context = ErrorContext {
+                track: Some(tracks.len()),
+                ..ErrorContext::at(offset)
+            };
+            let mut body = Vec::new();
+            (&mut buf)
+                .take(u64::from(len))
+                .read_to_end(&mut body)
+                .map_err(|e| ParseError::from(e).at(context))?;
+            if body.len() < len as usize {
+                match warnings.as_deref_mut() {
+                    Some(warnings) => warnings.push(ParseWarning {
+                        offset: body_offset,
+                        kind: WarningKind::TruncatedChunk {
+                            len,
+                            available: body.len() as u64,
+                        },
+                    }),
+                    None => Err(ParseError::Truncated.at(context))?,
+                }
+            }
+            tracks.push(TrackChunk::parse(
+                &body,
+                body_offset,
+                tracks.len(),
+                warnings.as_deref_mut(),
+            )?);
+        }
+        if let Some(warnings) = warnings {
+            if tracks.len() != usize::from(header.track_num) {
+                warnings.push(ParseWarning {
+                    offset: buf.offset(),
+                    kind: WarningKind::TrackCount {
+                        header: header.track_num,
+                        found: tracks.len(),
+                    },
+                });
+            }
+        }
+        Ok(Self { header, tracks })
     }
 }
 
 impl ByteChunk for HeaderChunk {
     fn read<B: BufRead>(buf: &mut B) -> Result<Self, ParseError> {
+        Tag::read(buf)?;
         Ok(Self {
-            tag: Tag::read(buf)?,
             header_len: u32::read(buf)?,
             format: Format::read(buf)?,
             track_num: u16::read(buf)?,
             division: i16::read(buf)?,
         })
     }
+
+    /// Only the 6 bytes known are written, whatever `header_len` was read
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        Tag::Header.write(buf)?;
+        6u32.write(buf)?;
+        self.format.write(buf)?;
+        self.track_num.write(buf)?;
+        self.division.write(buf)
+    }
 }
 
 impl ByteChunk for Format {
@@ -340,82 +1070,195 @@ impl ByteChunk for Format {
             _ => Err(ParseError::UnexpectedFormat(format))?,
         })
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        let format: u16 = match self {
+            Self::SingleTrack => 0,
+            Self::MultipleTrack => 1,
+            Self::MultipleSong => 2,
+        };
+        format.write(buf)
+    }
 }
 
 impl ByteChunk for TrackChunk {
     fn read<B: BufRead>(buf: &mut B) -> Result<Self, ParseError> {
-        Ok(Self {
-            tag: Tag::read(buf)?,
-            track_len: u32::read(buf)?,
-            events: {
-                let mut events = Vec::<TrackEvent>::new();
-                let mut previous_status = 0u8;
-                loop {
-                    let delta = U28::read(buf)?;
-                    let mut new_status = buf.fill_buf()?[0];
-                    if new_status < 0x80 {
-                        if previous_status < 0x80 {
-                            Err(ParseError::NotCommand(new_status))?
-                        } else {
-                            new_status = previous_status;
+        Tag::read(buf)?;
+        let track_len = u32::read(buf)?;
+        // the length is not trusted with an allocation
+        let mut body = Vec::new();
+        buf.take(u64::from(track_len)).read_to_end(&mut body)?;
+        if body.len() < track_len as usize {
+            Err(ParseError::Truncated)?
+        }
+        Self::parse(&body, 0, 0, None)
+    }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        self.write_with(buf, false)
+    }
+}
+
+impl TrackChunk {
+    /// Parse the events of a chunk body that starts at `offset` in the file
+    ///
+    /// In the lenient mode, with `warnings`, the rest of the track is skipped after an
+    /// event that does not parse, and a missing end of track is added.
+    fn parse(
+        body: &[u8],
+        offset: u64,
+        track: usize,
+        mut warnings: Option<&mut Vec<ParseWarning>>,
+    ) -> Result<Self, ParseError> {
+        let mut buf = Cursor::new(body);
+        let mut events = Vec::<TrackEvent>::new();
+        let mut previous_status = 0u8;
+        // the last sysex packet did not end with 0xF7
+        let mut sysex_open = false;
+        loop {
+            let at = offset + buf.position();
+            let error = if buf.position() == body.len() as u64 {
+                match warnings.as_deref_mut() {
+                    Some(warnings) => {
+                        warnings.push(ParseWarning {
+                            offset: at,
+                            kind: WarningKind::MissingEndOfTrack { track },
+                        });
+                        break;
+                    }
+                    None => ParseError::MissingEndOfTrack,
+                }
+            } else {
+                match TrackEvent::read(&mut buf, &mut previous_status) {
+                    Ok(mut event) => {
+                        if let (true, Event::Escape { data }) = (sysex_open, &mut event.event) {
+                            event.event = Event::SysexContinuation {
+                                sysex_msg: std::mem::replace(data, Slice(vec![])),
+                            };
+                        }
+                        sysex_open = match &event.event {
+                            Event::Midi { .. } => false,
+                            Event::Meta { .. } | Event::Escape { .. } => sysex_open,
+                            packet => packet.opens_sysex(),
+                        };
+                        let is_end = event.event.is_end();
+                        events.push(event);
+                        if is_end {
+                            let len = body.len() - buf.position() as usize;
+                            if let (Some(warnings), true) = (warnings.as_deref_mut(), len > 0) {
+                                warnings.push(ParseWarning {
+                                    offset: offset + buf.position(),
+                                    kind: WarningKind::TrailingBytes { track, len },
+                                });
+                            }
+                            return Ok(Self::new(events));
                         }
-                    } else {
-                        previous_status = new_status;
-                        buf.consume(1);
+                        continue;
                     }
-                    let (high, low) = (new_status >> 4, new_status & 0xF);
-                    let event = match high {
-                        command if command >= 0x8 && command < 0xF => Event::Midi {
-                            channel: low,
-                            midi_msg: match command {
-                                0x8 => MidiMessage::NoteOff {
-                                    key: U7::read(buf)?,
-                                    vel: U7::read(buf)?,
-                                },
-                                0x9 => MidiMessage::NoteOn {
-                                    key: U7::read(buf)?,
-                                    vel: U7::read(buf)?,
-                                },
-                                0xA => MidiMessage::Aftertouch {
-                                    key: U7::read(buf)?,
-                                    vel: U7::read(buf)?,
-                                },
-                                0xB => MidiMessage::ControlChange {
-                                    controller: U7::read(buf)?,
-                                    value: U7::read(buf)?,
-                                },
-                                0xC => MidiMessage::PatchChange {
-                                    program: U7::read(buf)?,
-                                },
-                                0xD => MidiMessage::ChannelPressure {
-                                    vel: U7::read(buf)?,
-                                },
-                                0xE => MidiMessage::PitchBend {
-                                    value: U14::read(buf)?,
-                                },
-                                _ => unreachable!(),
-                            },
-                        },
-                        0xF => match low {
-                            0x0 | 0x7 => Event::Sysex {
-                                sysex_msg: Slice::read(buf)?,
-                            },
-                            0xF => Event::Meta {
-                                meta_msg: MetaMessage::read(buf)?,
-                            },
-                            _ => Err(ParseError::NotSupportedSystemMessage(new_status))?,
+                    Err(error) => error.bare(),
+                }
+            };
+            match warnings.as_deref_mut() {
+                Some(warnings) => {
+                    warnings.push(ParseWarning {
+                        offset: at,
+                        kind: WarningKind::BadEvent {
+                            track,
+                            error: error.to_string(),
                         },
-                        _ => unreachable!(),
-                    };
-                    let is_end = event.is_end();
-                    events.push(TrackEvent { delta, event });
-                    if is_end {
-                        break;
-                    }
+                    });
+                    break;
+                }
+                None => {
+                    return Err(error.at(ErrorContext {
+                        offset: at,
+                        track: Some(track),
+                        event: Some(events.len()),
+                    }))
                 }
-                events
+            }
+        }
+        events.push(TrackEvent::new(
+            0,
+            Event::Meta {
+                meta_msg: MetaMessage::EndOfTrack(Slice(vec![])),
             },
-        })
+        ));
+        Ok(Self::new(events))
+    }
+}
+
+impl TrackEvent {
+    /// `previous_status` is the running status
+    fn read<B: BufRead>(buf: &mut B, previous_status: &mut u8) -> Result<Self, ParseError> {
+        let delta = U28::read(buf)?;
+        let mut new_status = buf
+            .fill_buf()?
+            .first()
+            .copied()
+            .ok_or_else(|| StdIoError::from(ErrorKind::UnexpectedEof))?;
+        if new_status < 0x80 {
+            if *previous_status < 0x80 {
+                Err(ParseError::NotCommand(new_status))?
+            } else {
+                new_status = *previous_status;
+            }
+        } else {
+            // only channel messages set the running status
+            if new_status < 0xF0 {
+                *previous_status = new_status;
+            }
+            buf.consume(1);
+        }
+        let (high, low) = (new_status >> 4, new_status & 0xF);
+        let event = match high {
+            command @ 0x8..=0xE => Event::Midi {
+                channel: low,
+                midi_msg: match command {
+                    0x8 => MidiMessage::NoteOff {
+                        key: U7::read(buf)?,
+                        vel: U7::read(buf)?,
+                    },
+                    0x9 => MidiMessage::NoteOn {
+                        key: U7::read(buf)?,
+                        vel: U7::read(buf)?,
+                    },
+                    0xA => MidiMessage::Aftertouch {
+                        key: U7::read(buf)?,
+                        vel: U7::read(buf)?,
+                    },
+                    0xB => MidiMessage::ControlChange {
+                        controller: U7::read(buf)?,
+                        value: U7::read(buf)?,
+                    },
+                    0xC => MidiMessage::PatchChange {
+                        program: U7::read(buf)?,
+                    },
+                    0xD => MidiMessage::ChannelPressure {
+                        vel: U7::read(buf)?,
+                    },
+                    0xE => MidiMessage::PitchBend {
+                        value: U14::read(buf)?,
+                    },
+                    _ => unreachable!(),
+                },
+            },
+            0xF => match low {
+                0x0 => Event::Sysex {
+                    sysex_msg: Slice::read(buf)?,
+                },
+                // a continuation is told apart by the track
+                0x7 => Event::Escape {
+                    data: Slice::read(buf)?,
+                },
+                0xF => Event::Meta {
+                    meta_msg: MetaMessage::read(buf)?,
+                },
+                _ => Err(ParseError::NotSupportedSystemMessage(new_status))?,
+            },
+            _ => unreachable!(),
+        };
+        Ok(Self { delta, event })
     }
 }
 
@@ -429,6 +1272,13 @@ impl ByteChunk for Tag {
             _ => Err(ParseError::UnexpectedTag(tag))?,
         })
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        buf.write_all(match self {
+            Self::Header => b"MThd",
+            Self::Track => b"MTrk",
+        })
+    }
 }
 
 impl ByteChunk for U28 {
@@ -447,6 +1297,21 @@ impl ByteChunk for U28 {
         }
         Ok(Self(inner))
     }
+
+    /// Big endian groups of 7 bits, the top bit set on all but the last byte
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        let mut bytes = [0u8; 4];
+        let mut start = 3;
+        let mut rest = self.0 & 0x0FFF_FFFF;
+        bytes[3] = (rest & 0x7F) as u8;
+        rest >>= 7;
+        while rest > 0 {
+            start -= 1;
+            bytes[start] = (rest & 0x7F) as u8 | 0x80;
+            rest >>= 7;
+        }
+        buf.write_all(&bytes[start..])
+    }
 }
 
 impl ByteChunk for Slice {
@@ -456,12 +1321,28 @@ impl ByteChunk for Slice {
         buf.read_exact(slice.as_mut())?;
         Ok(Self(slice))
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        U28(self.0.len() as u32).write(buf)?;
+        buf.write_all(&self.0)
+    }
 }
 
 impl ByteChunk for MetaMessage {
     fn read<B: BufRead>(buf: &mut B) -> Result<Self, ParseError> {
-        Ok(match u8::read(buf)? {
-            0x00 => Self::SequenceNumber(Slice::read(buf)?),
+        let kind = u8::read(buf)?;
+        let invalid = |data: &[u8]| ParseError::MetaValue {
+            kind,
+            data: data.to_vec(),
+        };
+        Ok(match kind {
+            0x00 => {
+                let data = Slice::read(buf)?;
+                Self::SequenceNumber(match data.0.len() {
+                    0 => None,
+                    _ => Some(u16::from_be_bytes(data.exact(kind)?)),
+                })
+            }
             0x01 => Self::Text(Slice::read(buf)?.to_ascii()),
             0x02 => Self::Copyright(Slice::read(buf)?.to_ascii()),
             0x03 => Self::TrackName(Slice::read(buf)?.to_ascii()),
@@ -469,16 +1350,55 @@ impl ByteChunk for MetaMessage {
             0x05 => Self::Lyric(Slice::read(buf)?.to_ascii()),
             0x06 => Self::Marker(Slice::read(buf)?.to_ascii()),
             0x07 => Self::CuePoint(Slice::read(buf)?.to_ascii()),
-            0x20 => Self::ChannelPrefix(Slice::read(buf)?),
+            0x20 => match Slice::read(buf)?.exact(kind)? {
+                [channel] if channel < 16 => Self::ChannelPrefix(channel),
+                data => Err(invalid(&data))?,
+            },
             0x2F => Self::EndOfTrack(Slice::read(buf)?),
             0x51 => Self::Tempo(Slice::read(buf)?.to_u32()),
-            0x54 => Self::SmpteOffset(Slice::read(buf)?),
-            0x58 => Self::TimeSignature(Slice::read(buf)?),
-            0x59 => Self::KeySignature(Slice::read(buf)?),
+            0x54 => {
+                let data = Slice::read(buf)?.exact(kind)?;
+                Self::SmpteOffset(SmpteOffset::from_bytes(data).ok_or_else(|| invalid(&data))?)
+            }
+            0x58 => {
+                let data = Slice::read(buf)?.exact(kind)?;
+                Self::TimeSignature(TimeSignature::from_bytes(data).ok_or_else(|| invalid(&data))?)
+            }
+            0x59 => {
+                let data = Slice::read(buf)?.exact(kind)?;
+                Self::KeySignature(KeySignature::from_bytes(data).ok_or_else(|| invalid(&data))?)
+            }
             0x7F => Self::SequencerSpecific(Slice::read(buf)?),
-            _ => Self::Unknown(Slice::read(buf)?),
+            kind => Self::Unknown(kind, Slice::read(buf)?),
         })
     }
+
+    /// Type and data, without the 0xFF status byte
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        let (kind, data) = match self {
+            Self::SequenceNumber(number) => (
+                0x00,
+                Slice(number.map_or(vec![], |n| n.to_be_bytes().to_vec())),
+            ),
+            Self::Text(text) => (0x01, Slice::from_ascii(text)),
+            Self::Copyright(text) => (0x02, Slice::from_ascii(text)),
+            Self::TrackName(text) => (0x03, Slice::from_ascii(text)),
+            Self::InstrumentName(text) => (0x04, Slice::from_ascii(text)),
+            Self::Lyric(text) => (0x05, Slice::from_ascii(text)),
+            Self::Marker(text) => (0x06, Slice::from_ascii(text)),
+            Self::CuePoint(text) => (0x07, Slice::from_ascii(text)),
+            Self::ChannelPrefix(channel) => (0x20, Slice(vec![*channel])),
+            Self::EndOfTrack(data) => (0x2F, data.clone()),
+            Self::Tempo(tempo) => (0x51, Slice::from_u32(*tempo, 3)),
+            Self::SmpteOffset(offset) => (0x54, Slice(offset.to_bytes().to_vec())),
+            Self::TimeSignature(signature) => (0x58, Slice(signature.to_bytes().to_vec())),
+            Self::KeySignature(signature) => (0x59, Slice(signature.to_bytes().to_vec())),
+            Self::SequencerSpecific(data) => (0x7F, data.clone()),
+            Self::Unknown(kind, data) => (*kind, data.clone()),
+        };
+        kind.write(buf)?;
+        data.write(buf)
+    }
 }
 
 impl ByteChunk for U7 {
@@ -490,6 +1410,10 @@ impl ByteChunk for U7 {
             Ok(U7(u_8))
         }
     }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        (self.0 & 0x7F).write(buf)
+    }
 }
 
 impl ByteChunk for U14 {
@@ -501,6 +1425,11 @@ impl ByteChunk for U14 {
         inner += u16::from(high & 0x7F);
         Ok(Self(inner))
     }
+
+    /// Least significant 7 bits first
+    fn write<W: Write>(&self, buf: &mut W) -> Result<(), StdIoError> {
+        buf.write_all(&[(self.0 & 0x7F) as u8, ((self.0 >> 7) & 0x7F) as u8])
+    }
 }
 
 #[cfg(test)]
@@ -530,6 +1459,27 @@ mod midi_tests {
         assert_eq!(header.track_num as usize, tracks.len());
     }
 
+    #[test]
+    fn header_division() {
+        assert_eq!(Division::from(96), Division::Metrical(96));
+        // -25 fps, 40 ticks per frame
+        assert_eq!(
+            Division::from(i16::from_be_bytes([0xE7, 40])),
+            Division::Timecode {
+                fps: 25,
+                ticks_per_frame: 40
+            }
+        );
+        // -128 has no positive i8
+        assert_eq!(
+            Division::from(i16::from_be_bytes([0x80, 4])),
+            Division::Timecode {
+                fps: 128,
+                ticks_per_frame: 4
+            }
+        );
+    }
+
     #[test]
     fn struct_u14() {
         let mut bytes = get_buf([0x54u8, 0x39].as_ref());
@@ -555,7 +1505,7 @@ mod midi_tests {
         let mut bytes = get_buf([0x00u8].as_ref());
         assert_eq!(Slice::read(&mut bytes).unwrap().0, vec![]);
         let mut bytes = get_buf([0x01u8, 0x01u8].as_ref());
-        assert_eq!(Slice::read(&mut bytes).unwrap().0, vec![01u8]);
+        assert_eq!(Slice::read(&mut bytes).unwrap().0, vec![0x01u8]);
         let mut bytes = get_buf([0x02u8, 0x01u8, 0x02].as_ref());
         assert_eq!(Slice::read(&mut bytes).unwrap().0, vec![0x01u8, 0x02]);
     }
@@ -573,4 +1523,531 @@ mod midi_tests {
         let vec = vec![0x07u8, 0xA1, 0x20];
         assert_eq!(Slice(vec).to_u32(), 500000)
     }
+
+    fn meta(bytes: &[u8]) -> Result<MetaMessage, ParseError> {
+        MetaMessage::read(&mut get_buf(bytes))
+    }
+
+    #[test]
+    fn typed_meta() {
+        assert_eq!(
+            meta(&[0x58, 4, 6, 3, 36, 8]).unwrap(),
+            MetaMessage::TimeSignature(TimeSignature {
+                numerator: 6,
+                denominator_power: 3,
+                clocks_per_click: 36,
+                thirty_seconds_per_quarter: 8,
+            })
+        );
+        assert_eq!(TimeSignature::default().denominator(), 4);
+        assert_eq!(
+            meta(&[0x59, 2, 0xFD, 1]).unwrap(),
+            MetaMessage::KeySignature(KeySignature {
+                sharps: -3,
+                minor: true
+            })
+        );
+        // 29.97 drop frame, 01:02:03, frame 4.05
+        assert_eq!(
+            meta(&[0x54, 5, 0x41, 2, 3, 4, 5]).unwrap(),
+            MetaMessage::SmpteOffset(SmpteOffset {
+                fps: 29,
+                hours: 1,
+                minutes: 2,
+                seconds: 3,
+                frames: 4,
+                subframes: 5,
+            })
+        );
+        assert_eq!(
+            meta(&[0x00, 2, 0x01, 0x02]).unwrap(),
+            MetaMessage::SequenceNumber(Some(0x0102))
+        );
+        assert_eq!(meta(&[0x00, 0]).unwrap(), MetaMessage::SequenceNumber(None));
+        assert_eq!(meta(&[0x20, 1, 9]).unwrap(), MetaMessage::ChannelPrefix(9));
+    }
+
+    #[test]
+    fn malformed_meta() {
+        assert!(matches!(
+            meta(&[0x58, 3, 4, 2, 24]),
+            Err(ParseError::MetaLength { kind: 0x58, len: 3 })
+        ));
+        assert!(matches!(
+            meta(&[0x59, 3, 0, 0, 0]),
+            Err(ParseError::MetaLength { kind: 0x59, len: 3 })
+        ));
+        assert!(matches!(
+            meta(&[0x00, 1, 0]),
+            Err(ParseError::MetaLength { kind: 0x00, len: 1 })
+        ));
+        // 8 sharps, mode 2, 60 minutes, channel 16, numerator 0
+        for bytes in [
+            &[0x59, 2, 8, 0][..],
+            &[0x59, 2, 0, 2],
+            &[0x54, 5, 0, 60, 0, 0, 0],
+            &[0x20, 1, 16],
+            &[0x58, 4, 0, 2, 24, 8],
+        ] {
+            assert!(
+                matches!(meta(bytes), Err(ParseError::MetaValue { kind, .. }) if kind == bytes[0])
+            );
+        }
+    }
+
+    #[test]
+    fn typed_meta_round_trip() {
+        for bytes in [
+            &[0x58, 4, 6, 3, 36, 8][..],
+            &[0x59, 2, 0xFD, 1],
+            &[0x59, 2, 7, 0],
+            &[0x54, 5, 0x61, 2, 3, 4, 5],
+            &[0x00, 2, 0x01, 0x02],
+            &[0x00, 0],
+            &[0x20, 1, 9],
+        ] {
+            assert_eq!(to_bytes(&meta(bytes).unwrap()), bytes);
+        }
+    }
+
+    fn chunk(tag: &[u8; 4], len: u32, body: &[u8]) -> Vec<u8> {
+        let mut bytes = tag.to_vec();
+        bytes.extend(len.to_be_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn file(track_num: u16, chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = chunk(b"MThd", 6, &[0, 1]);
+        bytes.extend(track_num.to_be_bytes());
+        bytes.extend([0, 96]);
+        bytes.extend(chunks.concat());
+        bytes
+    }
+
+    const NOTE: [u8; 4] = [0x00, 0x90, 60, 100];
+    const END: [u8; 4] = [0x00, 0xFF, 0x2F, 0x00];
+
+    fn warnings(bytes: &[u8]) -> Vec<(u64, WarningKind)> {
+        let (_, warnings) = Smf::from_bytes_lenient(bytes).unwrap();
+        warnings.into_iter().map(|w| (w.offset, w.kind)).collect()
+    }
+
+    #[test]
+    fn lenient_skips_unknown_chunks() {
+        let track = chunk(b"MTrk", 8, &[NOTE, END].concat());
+        let bytes = file(1, &[chunk(b"XFIH", 3, &[1, 2, 3]), track.clone()]);
+        assert!(matches!(
+            Smf::from_bytes(&bytes).unwrap_err().kind(),
+            ParseError::UnexpectedTag(tag) if tag == b"XFIH"
+        ));
+        assert_eq!(
+            ParseError::UnexpectedTag(*b"X\0\xFFH").to_string(),
+            "unexpected tag `X\\x00\\xffH`"
+        );
+        assert_eq!(
+            warnings(&bytes),
+            vec![(
+                14,
+                WarningKind::UnknownChunk {
+                    tag: *b"XFIH",
+                    len: 3
+                }
+            )]
+        );
+        let (smf, repairs) = Smf::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(
+            repairs[0].to_string(),
+            "unknown chunk `XFIH` of 3 bytes skipped at byte 14"
+        );
+        assert_eq!(smf.tracks().len(), 1);
+        assert_eq!(smf.tracks()[0].events().len(), 2);
+        // a clean file has no warnings
+        assert!(warnings(&file(1, &[track])).is_empty());
+    }
+
+    #[test]
+    fn lenient_repairs_tracks() {
+        // unsupported status after a note
+        let damaged = chunk(
+            b"MTrk",
+            10,
+            &[&NOTE[..], &[0x00, 0xF1, 0x00], &END[..3]].concat(),
+        );
+        assert!(matches!(
+            Smf::from_bytes(&file(1, std::slice::from_ref(&damaged)))
+                .unwrap_err()
+                .kind(),
+            ParseError::NotSupportedSystemMessage(0xF1)
+        ));
+        let bytes = file(
+            4,
+            &[
+                // no end of track
+                chunk(b"MTrk", 4, &NOTE),
+                damaged,
+                // bytes after the end of track
+                chunk(b"MTrk", 6, &[&END[..], &[0, 0]].concat()),
+                // truncated in the middle of a note
+                chunk(b"MTrk", 20, &[&NOTE[..], &NOTE[..2]].concat()),
+            ],
+        );
+        assert_eq!(
+            warnings(&bytes),
+            vec![
+                (26, WarningKind::MissingEndOfTrack { track: 0 }),
+                (
+                    38,
+                    WarningKind::BadEvent {
+                        track: 1,
+                        error: ParseError::NotSupportedSystemMessage(0xF1).to_string()
+                    }
+                ),
+                (56, WarningKind::TrailingBytes { track: 2, len: 2 }),
+                (
+                    66,
+                    WarningKind::TruncatedChunk {
+                        len: 20,
+                        available: 6
+                    }
+                ),
+                (
+                    70,
+                    WarningKind::BadEvent {
+                        track: 3,
+                        error: ParseError::Truncated.to_string()
+                    }
+                ),
+            ]
+        );
+        let (smf, _) = Smf::from_bytes_lenient(&bytes).unwrap();
+        let lens = smf
+            .tracks()
+            .iter()
+            .map(|t| t.events().len())
+            .collect::<Vec<_>>();
+        assert_eq!(lens, vec![2, 2, 1, 2]);
+        assert!(smf
+            .tracks()
+            .iter()
+            .all(|t| t.events().last().unwrap().event.is_end()));
+    }
+
+    #[test]
+    fn lenient_counts_tracks() {
+        let bytes = file(3, &[chunk(b"MTrk", 4, &END)]);
+        assert_eq!(
+            warnings(&bytes),
+            vec![(
+                26,
+                WarningKind::TrackCount {
+                    header: 3,
+                    found: 1
+                }
+            )]
+        );
+        // a truncated chunk header
+        let mut bytes = file(1, &[chunk(b"MTrk", 4, &END)]);
+        bytes.extend(b"MTr");
+        assert_eq!(
+            warnings(&bytes),
+            vec![(
+                26,
+                WarningKind::TruncatedChunk {
+                    len: 8,
+                    available: 3
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn error_context() {
+        let bytes = file(
+            2,
+            &[
+                chunk(b"MTrk", 8, &[NOTE, END].concat()),
+                // `0x90` where the velocity should be
+                chunk(
+                    b"MTrk",
+                    11,
+                    &[&NOTE[..], &[0x00, 62, 0x90], &END[..]].concat(),
+                ),
+            ],
+        );
+        let error = Smf::from_bytes(&bytes).unwrap_err();
+        assert!(matches!(error.kind(), ParseError::NotData(0x90)));
+        assert_eq!(
+            error.context(),
+            Some(&ErrorContext {
+                offset: 42,
+                track: Some(1),
+                event: Some(1),
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "not data `144` should >= 8 at byte 42 in track 1 event 1"
+        );
+    }
+
+    #[test]
+    fn truncation_is_not_a_clean_end() {
+        let track = chunk(b"MTrk", 4, &END);
+        let whole = file(1, &[track]);
+        assert!(Smf::from_bytes(&whole).is_ok());
+        for len in [whole.len() - 1, whole.len() - 4, whole.len() - 6, 10] {
+            let error = Smf::from_bytes(&whole[..len]).unwrap_err();
+            assert!(error.is_truncated(), "{}", error);
+        }
+        let error = Smf::from_bytes(&whole[..whole.len() - 1]).unwrap_err();
+        assert_eq!(error.context().unwrap().offset, 14);
+        assert_eq!(error.context().unwrap().track, Some(0));
+        // the chunk is complete, the track is not
+        let error = Smf::from_bytes(&file(1, &[chunk(b"MTrk", 4, &NOTE)])).unwrap_err();
+        assert!(matches!(error.kind(), ParseError::MissingEndOfTrack));
+        let error = Smf::from_bytes(&file(1, &[chunk(b"MTrk", 2, &NOTE[..2])])).unwrap_err();
+        assert!(error.is_truncated());
+        assert_eq!(error.context().unwrap().event, Some(0));
+        // a huge length is read as far as the data goes
+        let huge = chunk(b"MTrk", u32::MAX, &END);
+        let mut bytes = get_buf(huge.as_slice());
+        assert!(TrackChunk::read(&mut bytes).unwrap_err().is_truncated());
+    }
+
+    fn to_bytes<C: ByteChunk>(chunk: &C) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn write_u28() {
+        assert_eq!(to_bytes(&U28(0)), vec![0x00]);
+        assert_eq!(to_bytes(&U28(0x7F)), vec![0x7F]);
+        assert_eq!(to_bytes(&U28(0x80)), vec![0x81, 0x00]);
+        assert_eq!(to_bytes(&U28(32768)), vec![0x82, 0x80, 0x00]);
+        assert_eq!(to_bytes(&U28(0x0FFF_FFFF)), vec![0xFF, 0xFF, 0xFF, 0x7F]);
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            16383,
+            16384,
+            2_097_151,
+            2_097_152,
+            0x0FFF_FFFF,
+        ] {
+            let bytes = to_bytes(&U28(value));
+            assert_eq!(U28::read(&mut get_buf(bytes.as_slice())).unwrap().0, value);
+        }
+    }
+
+    #[test]
+    fn write_u14() {
+        assert_eq!(to_bytes(&U14(0x1CD4)), vec![0x54, 0x39]);
+        assert_eq!(to_bytes(&U14(0x81)), vec![0x01, 0x01]);
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        let bytes = b"tab\there \\ 'q' \"q\" \x00\xff\r\n".to_vec();
+        let text = Slice(bytes.clone()).to_ascii();
+        assert_eq!(Slice::from_ascii(&text).0, bytes);
+        assert_eq!(Slice::from_u32(500000, 3).0, vec![0x07, 0xA1, 0x20]);
+    }
+
+    fn note(delta: u32, channel: u8, key: u8, vel: u8) -> TrackEvent {
+        TrackEvent::new(
+            delta,
+            Event::Midi {
+                channel,
+                midi_msg: MidiMessage::NoteOn {
+                    key: U7(key),
+                    vel: U7(vel),
+                },
+            },
+        )
+    }
+
+    fn song() -> Smf {
+        let meta = |meta_msg| TrackEvent::new(0, Event::Meta { meta_msg });
+        Smf::new(
+            Format::MultipleTrack,
+            Division::Metrical(480),
+            vec![
+                TrackChunk::new(vec![
+                    meta(MetaMessage::TrackName(r#"piano \"1\" \xe9"#.into())),
+                    meta(MetaMessage::Tempo(400_000)),
+                    meta(MetaMessage::Unknown(0x60, Slice(vec![1, 2]))),
+                ]),
+                TrackChunk::new(vec![
+                    note(0, 1, 60, 100),
+                    note(240, 1, 64, 100),
+                    note(240, 1, 60, 0),
+                    TrackEvent::new(
+                        0,
+                        Event::Sysex {
+                            sysex_msg: Slice(vec![0x7E, 0x7F, 0x09, 0x01, 0xF7]),
+                        },
+                    ),
+                    note(0, 1, 64, 0),
+                    TrackEvent::new(
+                        0x0FFF_FFFF,
+                        Event::Midi {
+                            channel: 1,
+                            midi_msg: MidiMessage::PitchBend { value: U14(0x2000) },
+                        },
+                    ),
+                ]),
+            ],
+        )
+    }
+
+    fn assert_same_events(a: &Smf, b: &Smf) {
+        assert_eq!(a.format(), b.format());
+        assert_eq!(a.division(), b.division());
+        assert_eq!(a.tracks().len(), b.tracks().len());
+        for (a, b) in a.tracks().iter().zip(b.tracks()) {
+            assert_eq!(a.events(), b.events());
+        }
+    }
+
+    #[test]
+    fn write_running_status() {
+        let smf = song();
+        let compact = smf.to_bytes(true);
+        let full = smf.to_bytes(false);
+        // three note-ons share the status of the first
+        assert_eq!(full.len() - compact.len(), 2);
+        for bytes in [compact, full] {
+            let parsed = Smf::from_bytes(&bytes).unwrap();
+            assert_eq!(parsed.header.track_num, 2);
+            // the missing end of track is added
+            let events = parsed.tracks()[0].events();
+            assert!(events.last().unwrap().event.is_end());
+            assert_eq!(&events[..3], &smf.tracks()[0].events()[..]);
+            assert_eq!(
+                &parsed.tracks()[1].events()[..6],
+                &smf.tracks()[1].events()[..]
+            );
+        }
+    }
+
+    #[test]
+    fn write_track_bytes() {
+        let track = TrackChunk::new(vec![note(0, 2, 60, 100), note(96, 2, 62, 90)]);
+        let mut bytes = Vec::new();
+        track.write_with(&mut bytes, true).unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                b'M', b'T', b'r', b'k', 0, 0, 0, 11, 0x00, 0x92, 60, 100, 0x60, 62, 90, 0x00, 0xFF,
+                0x2F, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn write_division() {
+        for division in [
+            Division::Metrical(96),
+            Division::Timecode {
+                fps: 29,
+                ticks_per_frame: 80,
+            },
+        ] {
+            assert_eq!(Division::from(i16::from(division)), division);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let tempo = [
+            &[0x00, 0xFF, 0x03, 0x04][..],
+            b"lead",
+            &[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20],
+            &[0x00, 0xFF, 0x58, 0x04, 0x04, 0x02, 0x18, 0x08],
+            &END,
+        ]
+        .concat();
+        let notes = [
+            // two notes under one status, a sysex, then a new status
+            &[0x00, 0x91, 60, 100, 0x60, 64, 100, 0x60, 60, 0][..],
+            &[0x00, 0xF0, 0x05, 0x7E, 0x7F, 0x09, 0x01, 0xF7],
+            &[0x00, 0x91, 64, 0, 0x00, 0xB1, 0x07, 90, 0x00, 0x0A, 32],
+            &END,
+        ]
+        .concat();
+        let bytes = file(
+            2,
+            &[
+                chunk(b"MTrk", tempo.len() as u32, &tempo),
+                chunk(b"MTrk", notes.len() as u32, &notes),
+            ],
+        );
+        let smf = Smf::from_bytes(&bytes).unwrap();
+        assert_eq!(smf.to_bytes(true), bytes);
+        for running_status in [true, false] {
+            let bytes = smf.to_bytes(running_status);
+            let parsed = Smf::from_bytes(&bytes).unwrap();
+            assert_same_events(&smf, &parsed);
+            // writing is stable
+            assert_eq!(parsed.to_bytes(running_status), bytes);
+        }
+    }
+
+    #[test]
+    fn sysex_packets() {
+        let body = [
+            // sysex left open, a continuation, then an escaped tune request
+            &[0x00, 0xF0, 0x03, 0x7E, 0x7F, 0x09][..],
+            &[0x10, 0xF7, 0x02, 0x01, 0xF7],
+            &[0x00, 0xF7, 0x01, 0xF6],
+            // running status survives the sysex
+            &[0x00, 0x90, 60, 100, 0x00, 0xF0, 0x01, 0xF7, 0x00, 62, 100],
+            &END,
+        ]
+        .concat();
+        let bytes = file(1, &[chunk(b"MTrk", body.len() as u32, &body)]);
+        let smf = Smf::from_bytes(&bytes).unwrap();
+        let events = smf.tracks()[0]
+            .events()
+            .iter()
+            .map(|e| e.event.clone())
+            .collect::<Vec<_>>();
+        let slice = |bytes: &[u8]| Slice(bytes.to_vec());
+        assert_eq!(
+            events[..3],
+            [
+                Event::Sysex {
+                    sysex_msg: slice(&[0x7E, 0x7F, 0x09])
+                },
+                Event::SysexContinuation {
+                    sysex_msg: slice(&[0x01, 0xF7])
+                },
+                Event::Escape {
+                    data: slice(&[0xF6])
+                },
+            ]
+        );
+        assert!(matches!(
+            events[5],
+            Event::Midi {
+                channel: 0,
+                midi_msg: MidiMessage::NoteOn { .. }
+            }
+        ));
+        // the writer repeats the status after a sysex
+        assert_same_events(&smf, &Smf::from_bytes(&smf.to_bytes(true)).unwrap());
+    }
+
+    #[test]
+    fn round_trip_in_memory() {
+        let bytes = song().to_bytes(true);
+        let parsed = Smf::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.to_bytes(true), bytes);
+        assert_same_events(&parsed, &Smf::from_bytes(&parsed.to_bytes(false)).unwrap());
+    }
 }
@@ -1,3 +1,6 @@
 pub mod control;
 pub mod formats;
 pub mod player;
+pub mod rpn;
+pub mod sysex;
+pub mod time;
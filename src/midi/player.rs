@@ -1,17 +1,287 @@
-use super::formats::{Format, Smf};
+use super::{
+    formats::{Event, Format, Smf},
+    time::{micros_to_sample, BarBeatTick, MeterMap, TempoMap},
+};
 
-struct Player {}
+/// Scheduler that merges the tracks of a song into one time-ordered event stream
+///
+/// - `Format::SingleTrack`: the only track
+/// - `Format::MultipleTrack`: all tracks play at once, tempo changes of any track apply to all of them
+/// - `Format::MultipleSong`: every track is an independent song, see [`Player::with_song`]
+#[derive(Debug, Clone)]
+pub struct Player {
+    events: Vec<TimedEvent>,
+    tempo_map: TempoMap,
+    meter_map: MeterMap,
+    sample_rate: u32,
+    /// index of the next event to be played
+    cursor: usize,
+    /// current position in samples
+    position: u64,
+}
+
+/// Event with absolute timestamps
+#[derive(Debug, Clone)]
+pub struct TimedEvent {
+    pub tick: u64,
+    pub micros: u64,
+    pub sample: u64,
+    /// index of the track chunk the event comes from
+    pub track: usize,
+    pub event: Event,
+}
 
 impl Player {
-    fn new(smf: Smf) -> Self {
+    /// Play the whole file, or its first song if it is `Format::MultipleSong`
+    pub fn new(smf: Smf, sample_rate: u32) -> Self {
+        match smf.format() {
+            Format::SingleTrack | Format::MultipleTrack => {
+                let tracks = (0..smf.tracks().len()).collect::<Vec<_>>();
+                Self::from_tracks(&smf, &tracks, sample_rate)
+            }
+            Format::MultipleSong => Self::from_tracks(&smf, &[0], sample_rate),
+        }
+    }
+
+    /// Play the `song`th song of the file.
+    ///
+    /// Files that are not `Format::MultipleSong` contain exactly one song.
+    pub fn with_song(smf: Smf, song: usize, sample_rate: u32) -> Option<Self> {
+        if song >= Self::song_count(&smf) {
+            None
+        } else {
+            match smf.format() {
+                Format::SingleTrack | Format::MultipleTrack => Some(Self::new(smf, sample_rate)),
+                Format::MultipleSong => Some(Self::from_tracks(&smf, &[song], sample_rate)),
+            }
+        }
+    }
+
+    pub fn song_count(smf: &Smf) -> usize {
         match smf.format() {
-            Format::SingleTrack => todo!(),
-            Format::MultipleTrack => todo!(),
-            Format::MultipleSong => todo!(),
+            Format::SingleTrack | Format::MultipleTrack => 1,
+            Format::MultipleSong => smf.tracks().len(),
+        }
+    }
+
+    fn from_tracks(smf: &Smf, tracks: &[usize], sample_rate: u32) -> Self {
+        let song = || tracks.iter().filter_map(|track| smf.tracks().get(*track));
+        let tempo_map = TempoMap::from_tracks(smf.division(), song());
+        let meter_map = MeterMap::from_tracks(smf.division(), song());
+        let mut events = Vec::<(u64, usize, Event)>::new();
+        for &track in tracks {
+            let mut tick = 0u64;
+            for track_event in smf
+                .tracks()
+                .get(track)
+                .map(|t| t.events().as_slice())
+                .unwrap_or(&[])
+            {
+                let (delta, event) = track_event.event();
+                tick += u64::from(delta);
+                events.push((tick, track, event.clone()));
+            }
+        }
+        // stable: simultaneous events keep track order, then file order
+        events.sort_by_key(|(tick, _, _)| *tick);
+        let events = events
+            .into_iter()
+            .map(|(tick, track, event)| {
+                let micros = tempo_map.tick_to_micros(tick);
+                TimedEvent {
+                    tick,
+                    micros,
+                    sample: micros_to_sample(micros, sample_rate),
+                    track,
+                    event,
+                }
+            })
+            .collect();
+        Self {
+            events,
+            tempo_map,
+            meter_map,
+            sample_rate,
+            cursor: 0,
+            position: 0,
         }
     }
 
-    fn play() {
-        todo!()
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn tempo_map(&self) -> &TempoMap {
+        &self.tempo_map
+    }
+
+    pub fn meter_map(&self) -> &MeterMap {
+        &self.meter_map
+    }
+
+    /// All events of the song in playing order
+    pub fn events(&self) -> &[TimedEvent] {
+        &self.events
+    }
+
+    /// Current position in samples
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Sample of the last event
+    pub fn duration(&self) -> u64 {
+        self.events.last().map(|e| e.sample).unwrap_or(0)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cursor >= self.events.len()
+    }
+
+    /// Move to `sample`, the next call of [`Player::play`] starts from the first event at or after it
+    pub fn seek(&mut self, sample: u64) {
+        self.position = sample;
+        self.cursor = self.events.partition_point(|e| e.sample < sample);
+    }
+
+    /// Move to the start of `tick`
+    pub fn seek_tick(&mut self, tick: u64) {
+        self.seek(self.tempo_map.tick_to_sample(tick, self.sample_rate));
+    }
+
+    /// Tick playing at the current position
+    pub fn position_tick(&self) -> u64 {
+        self.tempo_map
+            .sample_to_tick(self.position, self.sample_rate)
+    }
+
+    /// Bar:beat:tick playing at the current position
+    pub fn position_bbt(&self) -> BarBeatTick {
+        self.meter_map.tick_to_bbt(self.position_tick())
+    }
+
+    /// Advance by `frames` samples and return the events falling into them
+    pub fn play(&mut self, frames: usize) -> &[TimedEvent] {
+        let end = self.position + frames as u64;
+        let start = self.cursor;
+        self.cursor += self.events[start..].partition_point(|e| e.sample < end);
+        self.position = end;
+        &self.events[start..self.cursor]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::formats::MidiMessage;
+
+    fn track(events: &[u8]) -> Vec<u8> {
+        let mut bytes = b"MTrk".to_vec();
+        bytes.extend((events.len() as u32 + 4).to_be_bytes());
+        bytes.extend(events);
+        bytes.extend([0x00, 0xFF, 0x2F, 0x00]);
+        bytes
+    }
+
+    fn smf(format: u16, division: [u8; 2], tracks: &[Vec<u8>]) -> Smf {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend(6u32.to_be_bytes());
+        bytes.extend(format.to_be_bytes());
+        bytes.extend((tracks.len() as u16).to_be_bytes());
+        bytes.extend(division);
+        for t in tracks {
+            bytes.extend(t);
+        }
+        Smf::from_bytes(&bytes).unwrap()
+    }
+
+    fn note_ons(player: &Player) -> Vec<(u64, u64)> {
+        player
+            .events()
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e.event,
+                    Event::Midi {
+                        midi_msg: MidiMessage::NoteOn { .. },
+                        ..
+                    }
+                )
+            })
+            .map(|e| (e.tick, e.micros))
+            .collect()
+    }
+
+    #[test]
+    fn merges_tracks_and_follows_tempo_changes() {
+        // 96 ppq, tempo 250000 at tick 96 in the conductor track
+        let conductor = track(&[0x60, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90]);
+        let notes = track(&[
+            0x00, 0x90, 60, 100, 0x60, 0x90, 62, 100, 0x60, 0x90, 64, 100,
+        ]);
+        let player = Player::new(smf(1, [0, 96], &[conductor, notes]), 48_000);
+        assert_eq!(
+            note_ons(&player),
+            vec![(0, 0), (96, 500_000), (192, 750_000)]
+        );
+        assert!(player.events().windows(2).all(|w| w[0].tick <= w[1].tick));
+        assert_eq!(player.events()[2].sample, 24_000);
+    }
+
+    #[test]
+    fn smpte_division_ignores_tempo() {
+        // 25 fps, 40 ticks per frame: 1 ms per tick
+        let notes = track(&[
+            0x00, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90, 0x64, 0x90, 60, 100,
+        ]);
+        let player = Player::new(smf(0, [0xE7, 40], &[notes]), 44_100);
+        assert_eq!(note_ons(&player), vec![(100, 100_000)]);
+    }
+
+    #[test]
+    fn selects_song_of_multiple_song_file() {
+        let first = track(&[0x00, 0x90, 60, 100]);
+        let second = track(&[0x60, 0x90, 72, 100]);
+        let smf = smf(2, [0, 96], &[first, second]);
+        assert_eq!(Player::song_count(&smf), 2);
+        let player = Player::with_song(smf, 1, 48_000).unwrap();
+        assert_eq!(note_ons(&player), vec![(96, 500_000)]);
+        assert!(player.events().iter().all(|e| e.track == 1));
+    }
+
+    #[test]
+    fn seeks_in_musical_time() {
+        // 96 ppq, 3/4 and tempo 250000 from tick 288
+        let conductor = track(&[
+            0x00, 0xFF, 0x58, 0x04, 3, 2, 24, 8, 0x82, 0x20, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90,
+        ]);
+        let notes = track(&[0x00, 0x90, 60, 100, 0x83, 0x00, 0x90, 62, 100]);
+        let mut player = Player::new(smf(1, [0, 96], &[conductor, notes]), 1_000);
+        let bar_2 = BarBeatTick {
+            bar: 2,
+            beat: 1,
+            tick: 0,
+        };
+        assert_eq!(player.meter_map().bbt_to_tick(bar_2), 288);
+        player.seek_tick(player.meter_map().bbt_to_tick(bar_2));
+        assert_eq!(player.position(), 1_500);
+        assert_eq!(player.position_bbt(), bar_2);
+        // the tempo change and the end of the conductor track, the note is 96 ticks at 250000 later
+        assert_eq!(player.play(250).len(), 2);
+        assert_eq!(player.position_tick(), 384);
+        assert_eq!(player.position_bbt().to_string(), "2:2:0");
+    }
+
+    #[test]
+    fn play_returns_events_block_by_block() {
+        let notes = track(&[0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0]);
+        let mut player = Player::new(smf(0, [0, 96], &[notes]), 1_000);
+        assert_eq!(player.play(100).len(), 1);
+        assert!(player.play(400).is_empty());
+        // note off and end of track at 500 ms
+        assert_eq!(player.play(1).len(), 2);
+        assert!(player.is_finished());
+        player.seek(0);
+        assert_eq!(player.play(1000).len(), 3);
     }
 }
@@ -0,0 +1,194 @@
+/// RPN 0, semitones in the data MSB and cents in the LSB
+pub const PITCH_BEND_SENSITIVITY: u16 = 0x0000;
+/// RPN 1, 14 bits centred on 0x2000, ±100 cents
+pub const FINE_TUNING: u16 = 0x0001;
+/// RPN 2, semitones centred on 64 in the data MSB
+pub const COARSE_TUNING: u16 = 0x0002;
+/// RPN 127/127 deselects the parameter, so stray data entry does nothing
+pub const NULL: u16 = 0x3FFF;
+
+/// Parameter selected with controllers 101/100 or 99/98
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    Registered(u16),
+    NonRegistered(u16),
+}
+
+/// What data entry does to the selected parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataChange {
+    /// 14 bits, controller 6 sets the MSB and clears the LSB, controller 38 sets the LSB
+    Set(u16),
+    /// Controllers 96 and 97, by a step each parameter defines
+    Increment,
+    Decrement,
+}
+
+impl DataChange {
+    /// New 14 bit value of a parameter at `current`
+    pub fn apply(self, current: u16, step: u16) -> u16 {
+        match self {
+            Self::Set(value) => value,
+            Self::Increment => current.saturating_add(step).min(0x3FFF),
+            Self::Decrement => current.saturating_sub(step),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterMessage {
+    pub parameter: Parameter,
+    pub change: DataChange,
+}
+
+/// RPN and NRPN state machine of one channel
+///
+/// Parameter number and data entry controllers come one at a time, this keeps track
+/// of which parameter they address. The synth acts on the parameters it knows,
+/// the others are ignored.
+///
+/// [RPN and NRPN](http://www.somascape.org/midi/tech/spec.html#rpns)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterControl {
+    registered: bool,
+    number_msb: u8,
+    number_lsb: u8,
+    data_msb: u8,
+}
+
+impl Default for ParameterControl {
+    fn default() -> Self {
+        Self {
+            registered: true,
+            number_msb: 0x7F,
+            number_lsb: 0x7F,
+            data_msb: 0,
+        }
+    }
+}
+
+impl ParameterControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The parameter data entry goes to, `None` after RPN or NRPN 127/127
+    pub fn selected(&self) -> Option<Parameter> {
+        let number = u16::from(self.number_msb) << 7 | u16::from(self.number_lsb);
+        match (number, self.registered) {
+            (NULL, _) => None,
+            (number, true) => Some(Parameter::Registered(number)),
+            (number, false) => Some(Parameter::NonRegistered(number)),
+        }
+    }
+
+    /// Returns true for controllers 6, 38 and 96 to 101
+    pub fn is_parameter_controller(controller: u8) -> bool {
+        matches!(controller, 6 | 38 | 96..=101)
+    }
+
+    /// Feed a controller, returns the change once data arrives for a selected parameter
+    pub fn control_change(&mut self, controller: u8, value: u8) -> Option<ParameterMessage> {
+        let value = value & 0x7F;
+        let change = match controller {
+            6 => {
+                self.data_msb = value;
+                DataChange::Set(u16::from(value) << 7)
+            }
+            38 => DataChange::Set(u16::from(self.data_msb) << 7 | u16::from(value)),
+            96 => DataChange::Increment,
+            97 => DataChange::Decrement,
+            98..=101 => {
+                let registered = controller >= 100;
+                if registered != self.registered {
+                    *self = Self {
+                        registered,
+                        ..Self::default()
+                    };
+                }
+                if matches!(controller, 98 | 100) {
+                    self.number_lsb = value;
+                } else {
+                    self.number_msb = value;
+                }
+                self.data_msb = 0;
+                return None;
+            }
+            _ => return None,
+        };
+        self.selected()
+            .map(|parameter| ParameterMessage { parameter, change })
+    }
+
+    /// Deselect the parameter, on reset all controllers as recommended by RP-015
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(control: &mut ParameterControl, controllers: &[(u8, u8)]) -> Vec<ParameterMessage> {
+        controllers
+            .iter()
+            .filter_map(|(controller, value)| control.control_change(*controller, *value))
+            .collect()
+    }
+
+    #[test]
+    fn data_entry_goes_to_the_selected_parameter() {
+        let mut control = ParameterControl::new();
+        // nothing selected yet
+        assert!(feed(&mut control, &[(6, 12)]).is_empty());
+        let messages = feed(
+            &mut control,
+            &[(101, 0), (100, 0), (6, 12), (38, 50), (96, 0)],
+        );
+        let rpn = |change| ParameterMessage {
+            parameter: Parameter::Registered(PITCH_BEND_SENSITIVITY),
+            change,
+        };
+        assert_eq!(
+            messages,
+            vec![
+                rpn(DataChange::Set(12 << 7)),
+                rpn(DataChange::Set(12 << 7 | 50)),
+                rpn(DataChange::Increment),
+            ]
+        );
+        // the null parameter
+        assert!(feed(&mut control, &[(101, 127), (100, 127), (6, 1), (97, 0)]).is_empty());
+    }
+
+    #[test]
+    fn nrpn_replaces_rpn() {
+        let mut control = ParameterControl::new();
+        feed(&mut control, &[(101, 0), (100, 2)]);
+        assert_eq!(
+            control.selected(),
+            Some(Parameter::Registered(COARSE_TUNING))
+        );
+        // a half selected NRPN keeps the other byte null
+        feed(&mut control, &[(99, 1)]);
+        assert_eq!(
+            control.selected(),
+            Some(Parameter::NonRegistered(1 << 7 | 0x7F))
+        );
+        feed(&mut control, &[(98, 8)]);
+        assert_eq!(
+            control.selected(),
+            Some(Parameter::NonRegistered(1 << 7 | 8))
+        );
+        control.reset();
+        assert_eq!(control.selected(), None);
+    }
+
+    #[test]
+    fn increment_is_clamped() {
+        assert_eq!(DataChange::Increment.apply(0x3FF0, 128), 0x3FFF);
+        assert_eq!(DataChange::Decrement.apply(100, 128), 0);
+        assert_eq!(DataChange::Set(5).apply(100, 128), 5);
+    }
+}
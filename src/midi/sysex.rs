@@ -0,0 +1,321 @@
+use crate::midi::formats::Event;
+
+/// Joins sysex messages that a file splits over several events
+///
+/// A message starts with an [`Event::Sysex`] and goes on in [`Event::SysexContinuation`]
+/// packets until one ends with 0xF7. An [`Event::Escape`] holding a whole message,
+/// from 0xF0 to 0xF7, is taken as well.
+#[derive(Debug, Default)]
+pub struct SysexAssembler {
+    /// data of the unfinished message
+    buffer: Option<Vec<u8>>,
+}
+
+impl SysexAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the data between 0xF0 and 0xF7 once the last packet of a message arrives
+    pub fn push(&mut self, event: &Event) -> Option<Vec<u8>> {
+        let packet = match event {
+            Event::Sysex { sysex_msg } => {
+                self.buffer = Some(Vec::new());
+                sysex_msg.as_bytes()
+            }
+            Event::SysexContinuation { sysex_msg } => sysex_msg.as_bytes(),
+            Event::Escape { data } => {
+                return match data.as_bytes() {
+                    [0xF0, message @ .., 0xF7] => Some(message.to_vec()),
+                    _ => None,
+                };
+            }
+            _ => return None,
+        };
+        let buffer = self.buffer.as_mut()?;
+        match packet.split_last() {
+            Some((0xF7, data)) => {
+                buffer.extend_from_slice(data);
+                self.buffer.take()
+            }
+            _ => {
+                buffer.extend_from_slice(packet);
+                None
+            }
+        }
+    }
+}
+
+/// Universal and manufacturer system exclusive messages the synth acts on
+///
+/// Messages for any device ID are accepted.
+///
+/// - [Universal System Exclusive](https://www.recordingblogs.com/wiki/midi-universal-system-exclusive-message)
+/// - [GS and XG reset](http://www.somascape.org/midi/tech/spec.html#sysex)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UniversalSysex {
+    /// `7E dev 09 01`
+    GmSystemOn,
+    /// `7E dev 09 02`
+    GmSystemOff,
+    /// `7E dev 09 03`
+    Gm2SystemOn,
+    /// Roland `41 dev 42 12 40 00 7F 00 41`
+    GsReset,
+    /// Yamaha `43 1n 4C 00 00 7E 00`
+    XgReset,
+    /// `7F dev 04 01 lsb msb`, 14 bits
+    MasterVolume(u16),
+    /// `7F dev 04 03 lsb msb`, -100 to almost +100 cents
+    MasterFineTuning(f32),
+    /// `7F dev 04 04 00 msb`, -64 to +63 semitones
+    MasterCoarseTuning(i8),
+}
+
+impl UniversalSysex {
+    /// Decode the data between 0xF0 and 0xF7, `None` for any other message
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.iter().any(|byte| *byte > 0x7F) {
+            return None;
+        }
+        let u14 = |lsb: u8, msb: u8| u16::from(msb) << 7 | u16::from(lsb);
+        Some(match *data {
+            [0x7E, _, 0x09, 0x01] => Self::GmSystemOn,
+            [0x7E, _, 0x09, 0x02] => Self::GmSystemOff,
+            [0x7E, _, 0x09, 0x03] => Self::Gm2SystemOn,
+            [0x41, _, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41] => Self::GsReset,
+            [0x43, device, 0x4C, 0x00, 0x00, 0x7E, 0x00] if device >> 4 == 1 => Self::XgReset,
+            [0x7F, _, 0x04, 0x01, lsb, msb] => Self::MasterVolume(u14(lsb, msb)),
+            [0x7F, _, 0x04, 0x03, lsb, msb] => {
+                Self::MasterFineTuning((f32::from(u14(lsb, msb)) - 8192.0) * 100.0 / 8192.0)
+            }
+            [0x7F, _, 0x04, 0x04, _, msb] => Self::MasterCoarseTuning(msb as i8 - 64),
+            _ => return None,
+        })
+    }
+
+    /// Returns true for the messages that put the synth back in its initial state
+    pub fn is_reset(&self) -> bool {
+        matches!(
+            self,
+            Self::GmSystemOn | Self::Gm2SystemOn | Self::GsReset | Self::XgReset
+        )
+    }
+}
+
+/// System exclusive message the synth acts on
+#[derive(Debug, Clone, PartialEq)]
+pub enum SysexMessage {
+    Universal(UniversalSysex),
+    Tuning(TuningSysex),
+}
+
+impl SysexMessage {
+    /// Decode the data between 0xF0 and 0xF7, `None` for any other message
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        UniversalSysex::decode(data)
+            .map(Self::Universal)
+            .or_else(|| TuningSysex::decode(data).map(Self::Tuning))
+    }
+}
+
+/// MIDI Tuning Standard messages, the frequency of a key is `None` if the message
+/// leaves it as it is
+///
+/// [MIDI Tuning Standard](https://en.wikipedia.org/wiki/MIDI_tuning_standard)
+#[derive(Debug, Clone, PartialEq)]
+pub enum TuningSysex {
+    /// `7E dev 08 01 program name[16] (xx yy zz)[128] checksum`, every key
+    BulkDump {
+        program: u8,
+        name: String,
+        frequencies: Vec<Option<f32>>,
+    },
+    /// `7F dev 08 02 program count (key xx yy zz)[count]`, and with a bank
+    /// `7E dev 08 07 bank program count ...` or `7F dev 08 07 ...`
+    SingleNote {
+        bank: u8,
+        program: u8,
+        changes: Vec<(u8, Option<f32>)>,
+    },
+}
+
+impl TuningSysex {
+    /// Decode the data between 0xF0 and 0xF7, `None` for any other message
+    ///
+    /// The bulk dump checksum is not checked, devices get it wrong often enough.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.iter().any(|byte| *byte > 0x7F) {
+            return None;
+        }
+        let changes = |count: u8, notes: &[u8]| {
+            (notes.len() == usize::from(count) * 4).then(|| {
+                notes
+                    .chunks(4)
+                    .map(|note| (note[0], mts_frequency(&note[1..])))
+                    .collect()
+            })
+        };
+        Some(match *data {
+            [0x7E, _, 0x08, 0x01, program, ref rest @ ..] if rest.len() == 16 + 128 * 3 + 1 => {
+                let (name, frequencies) = rest.split_at(16);
+                Self::BulkDump {
+                    program,
+                    name: String::from_utf8_lossy(name).trim_end().to_string(),
+                    frequencies: frequencies[..128 * 3]
+                        .chunks(3)
+                        .map(mts_frequency)
+                        .collect(),
+                }
+            }
+            [0x7F, _, 0x08, 0x02, program, count, ref notes @ ..] => Self::SingleNote {
+                bank: 0,
+                program,
+                changes: changes(count, notes)?,
+            },
+            [0x7E | 0x7F, _, 0x08, 0x07, bank, program, count, ref notes @ ..] => {
+                Self::SingleNote {
+                    bank,
+                    program,
+                    changes: changes(count, notes)?,
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// `xx yy zz`, semitone `xx` plus `yyzz` / 16384, `7F 7F 7F` for no change
+fn mts_frequency(bytes: &[u8]) -> Option<f32> {
+    match *bytes {
+        [0x7F, 0x7F, 0x7F] => None,
+        [semitone, msb, lsb] => {
+            let fraction = f64::from(u16::from(msb) << 7 | u16::from(lsb)) / 16384.0;
+            let key = f64::from(semitone) + fraction;
+            Some((440.0 * 2f64.powf((key - 69.0) / 12.0)) as f32)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::formats::Slice;
+
+    fn sysex(data: &[u8]) -> Event {
+        Event::Sysex {
+            sysex_msg: Slice::from(data.to_vec()),
+        }
+    }
+
+    fn continuation(data: &[u8]) -> Event {
+        Event::SysexContinuation {
+            sysex_msg: Slice::from(data.to_vec()),
+        }
+    }
+
+    #[test]
+    fn joins_packets() {
+        let mut assembler = SysexAssembler::new();
+        assert_eq!(assembler.push(&sysex(&[0x7E, 0x7F])), None);
+        assert_eq!(assembler.push(&continuation(&[0x09])), None);
+        assert_eq!(
+            assembler.push(&continuation(&[0x01, 0xF7])),
+            Some(vec![0x7E, 0x7F, 0x09, 0x01])
+        );
+        // a continuation without a start is dropped
+        assert_eq!(assembler.push(&continuation(&[0x01, 0xF7])), None);
+        // a new message drops an unfinished one
+        assembler.push(&sysex(&[0x01]));
+        assert_eq!(assembler.push(&sysex(&[0x02, 0xF7])), Some(vec![0x02]));
+    }
+
+    #[test]
+    fn escaped_message() {
+        let mut assembler = SysexAssembler::new();
+        let escape = |bytes: &[u8]| Event::Escape {
+            data: Slice::from(bytes.to_vec()),
+        };
+        assert_eq!(
+            assembler.push(&escape(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7])),
+            Some(vec![0x7E, 0x7F, 0x09, 0x01])
+        );
+        assert_eq!(assembler.push(&escape(&[0xF8])), None);
+    }
+
+    #[test]
+    fn decodes_universal_messages() {
+        assert_eq!(
+            UniversalSysex::decode(&[0x7E, 0x7F, 0x09, 0x01]),
+            Some(UniversalSysex::GmSystemOn)
+        );
+        assert_eq!(
+            UniversalSysex::decode(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41]),
+            Some(UniversalSysex::GsReset)
+        );
+        assert_eq!(
+            UniversalSysex::decode(&[0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00]),
+            Some(UniversalSysex::XgReset)
+        );
+        assert_eq!(
+            UniversalSysex::decode(&[0x7F, 0x7F, 0x04, 0x01, 0x7F, 0x7F]),
+            Some(UniversalSysex::MasterVolume(0x3FFF))
+        );
+        assert_eq!(
+            UniversalSysex::decode(&[0x7F, 0x7F, 0x04, 0x03, 0x00, 0x60]),
+            Some(UniversalSysex::MasterFineTuning(50.0))
+        );
+        assert_eq!(
+            UniversalSysex::decode(&[0x7F, 0x7F, 0x04, 0x04, 0x00, 0x3E]),
+            Some(UniversalSysex::MasterCoarseTuning(-2))
+        );
+        // wrong sub ID, wrong length, data byte out of range
+        assert_eq!(UniversalSysex::decode(&[0x7E, 0x7F, 0x08, 0x01]), None);
+        assert_eq!(
+            UniversalSysex::decode(&[0x7E, 0x7F, 0x09, 0x01, 0x00]),
+            None
+        );
+        assert_eq!(
+            UniversalSysex::decode(&[0x7F, 0x7F, 0x04, 0x01, 0x80, 0x7F]),
+            None
+        );
+    }
+
+    #[test]
+    fn decodes_tuning_messages() {
+        // A4 a quarter tone up, middle C left alone
+        let single = [
+            0x7F, 0x7F, 0x08, 0x02, 0x00, 0x02, 69, 69, 0x40, 0x00, 60, 0x7F, 0x7F, 0x7F,
+        ];
+        let quarter_tone = 440.0 * 2f32.powf(0.5 / 12.0);
+        match TuningSysex::decode(&single) {
+            Some(TuningSysex::SingleNote { changes, .. }) => {
+                assert_eq!(changes.len(), 2);
+                assert!((changes[0].1.unwrap() - quarter_tone).abs() < 1e-3);
+                assert_eq!(changes[1], (60, None));
+            }
+            other => panic!("{:?}", other),
+        }
+        // the count does not match the notes
+        assert_eq!(TuningSysex::decode(&single[..10]), None);
+        let mut bulk = vec![0x7E, 0x00, 0x08, 0x01, 0x05];
+        bulk.extend(b"Pythagorean     ");
+        for key in 0..128 {
+            bulk.extend([key, 0x00, 0x00]);
+        }
+        bulk.push(0x00);
+        match TuningSysex::decode(&bulk) {
+            Some(TuningSysex::BulkDump {
+                program,
+                name,
+                frequencies,
+            }) => {
+                assert_eq!((program, name.as_str()), (5, "Pythagorean"));
+                assert_eq!(frequencies[69], Some(440.0));
+                assert_eq!(frequencies.len(), 128);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use super::formats::{Division, Event, MetaMessage, TimeSignature, TrackChunk};
+
+/// 120 beats per minute
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Tick to wall clock conversion that follows every `MetaMessage::Tempo`
+///
+/// With a `Division::Timecode` division ticks have a fixed length and tempo
+/// changes are ignored.
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    division: Division,
+    /// Sorted by tick, the first segment always starts at tick 0
+    segments: Vec<TempoSegment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TempoSegment {
+    tick: u64,
+    /// Absolute microseconds at `tick`
+    micros: u64,
+    /// Microseconds per quarter note
+    tempo: u32,
+}
+
+impl TempoMap {
+    pub fn new(division: Division) -> Self {
+        Self {
+            division,
+            segments: vec![TempoSegment {
+                tick: 0,
+                micros: 0,
+                tempo: DEFAULT_TEMPO,
+            }],
+        }
+    }
+
+    /// Collect the tempo changes of `tracks`, which play at the same time
+    pub fn from_tracks<'a, T: IntoIterator<Item = &'a TrackChunk>>(
+        division: Division,
+        tracks: T,
+    ) -> Self {
+        let mut map = Self::new(division);
+        for (tick, event) in tracks.into_iter().flat_map(absolute_ticks) {
+            if let Event::Meta {
+                meta_msg: MetaMessage::Tempo(tempo),
+            } = event
+            {
+                map.insert(tick, *tempo);
+            }
+        }
+        map
+    }
+
+    pub fn division(&self) -> Division {
+        self.division
+    }
+
+    /// Set `tempo` from `tick` on.
+    ///
+    /// Changes may be inserted in any order, a later change at the same tick replaces the earlier one.
+    pub fn insert(&mut self, tick: u64, tempo: u32) {
+        let tempo = tempo.max(1);
+        match self.segments.binary_search_by_key(&tick, |seg| seg.tick) {
+            Ok(index) => self.segments[index].tempo = tempo,
+            Err(index) => self.segments.insert(
+                index,
+                TempoSegment {
+                    tick,
+                    micros: 0,
+                    tempo,
+                },
+            ),
+        }
+        for index in 1..self.segments.len() {
+            let previous = self.segments[index - 1];
+            let tick = self.segments[index].tick;
+            self.segments[index].micros =
+                previous.micros + self.span(previous.tempo, tick - previous.tick);
+        }
+    }
+
+    /// Microseconds per quarter note at `tick`
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        self.segment_at(tick).tempo
+    }
+
+    /// Absolute microseconds at `tick`
+    pub fn tick_to_micros(&self, tick: u64) -> u64 {
+        let seg = self.segment_at(tick);
+        seg.micros + self.span(seg.tempo, tick - seg.tick)
+    }
+
+    /// The last tick starting at or before `micros`
+    pub fn micros_to_tick(&self, micros: u64) -> u64 {
+        let index = self.segments.partition_point(|seg| seg.micros <= micros) - 1;
+        let seg = &self.segments[index];
+        let (num, den) = self.rate(seg.tempo);
+        // largest n with n * num / den <= micros - seg.micros, rounded down like `span`
+        seg.tick + ((micros - seg.micros + 1) * den).div_ceil(num) - 1
+    }
+
+    pub fn tick_to_seconds(&self, tick: u64) -> f64 {
+        self.tick_to_micros(tick) as f64 / 1_000_000.0
+    }
+
+    pub fn seconds_to_tick(&self, seconds: f64) -> u64 {
+        self.micros_to_tick((seconds.max(0.0) * 1_000_000.0) as u64)
+    }
+
+    pub fn tick_to_sample(&self, tick: u64, sample_rate: u32) -> u64 {
+        micros_to_sample(self.tick_to_micros(tick), sample_rate)
+    }
+
+    /// The last tick starting at or before `sample`
+    pub fn sample_to_tick(&self, sample: u64, sample_rate: u32) -> u64 {
+        let sample_rate = u64::from(sample_rate.max(1));
+        // last microsecond that still falls into `sample`
+        let micros = ((sample + 1) * 1_000_000).div_ceil(sample_rate) - 1;
+        self.micros_to_tick(micros)
+    }
+
+    fn segment_at(&self, tick: u64) -> &TempoSegment {
+        let index = match self.segments.binary_search_by_key(&tick, |seg| seg.tick) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        &self.segments[index]
+    }
+
+    /// Microseconds of `ticks` at `tempo`
+    fn span(&self, tempo: u32, ticks: u64) -> u64 {
+        let (num, den) = self.rate(tempo);
+        ticks * num / den
+    }
+
+    /// Microseconds per tick at `tempo` as a fraction
+    fn rate(&self, tempo: u32) -> (u64, u64) {
+        match self.division {
+            Division::Metrical(ppq) => (u64::from(tempo.max(1)), u64::from(ppq.max(1))),
+            Division::Timecode {
+                fps,
+                ticks_per_frame,
+            } => {
+                let tpf = u64::from(ticks_per_frame.max(1));
+                match fps {
+                    // 29.97 drop frame
+                    29 => (1_000_000 * 1001, 30_000 * tpf),
+                    fps => (1_000_000, u64::from(fps.max(1)) * tpf),
+                }
+            }
+        }
+    }
+}
+
+/// Position in musical time, bar and beat count from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BarBeatTick {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl Display for BarBeatTick {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}:{}:{}", self.bar, self.beat, self.tick)
+    }
+}
+
+/// Tick to bar:beat:tick conversion that follows every `MetaMessage::TimeSignature`
+///
+/// A beat is a note of the signature's denominator, and a change that does not fall
+/// on a bar line starts a new bar. With a `Division::Timecode` division a quarter note
+/// lasts one second.
+#[derive(Debug, Clone)]
+pub struct MeterMap {
+    ticks_per_quarter: u64,
+    /// Sorted by tick, the first segment always starts at tick 0
+    segments: Vec<MeterSegment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MeterSegment {
+    tick: u64,
+    /// Bars before `tick`
+    bar: u64,
+    signature: TimeSignature,
+}
+
+impl MeterMap {
+    /// 4/4 until the first change
+    pub fn new(division: Division) -> Self {
+        let ticks_per_quarter = match division {
+            Division::Metrical(ppq) => u64::from(ppq),
+            Division::Timecode {
+                fps,
+                ticks_per_frame,
+            } => u64::from(fps) * u64::from(ticks_per_frame),
+        };
+        Self {
+            ticks_per_quarter: ticks_per_quarter.max(1),
+            segments: vec![MeterSegment {
+                tick: 0,
+                bar: 0,
+                signature: TimeSignature::default(),
+            }],
+        }
+    }
+
+    /// Collect the time signature changes of `tracks`, which play at the same time
+    pub fn from_tracks<'a, T: IntoIterator<Item = &'a TrackChunk>>(
+        division: Division,
+        tracks: T,
+    ) -> Self {
+        let mut map = Self::new(division);
+        for (tick, event) in tracks.into_iter().flat_map(absolute_ticks) {
+            if let Event::Meta {
+                meta_msg: MetaMessage::TimeSignature(signature),
+            } = event
+            {
+                map.insert(tick, *signature);
+            }
+        }
+        map
+    }
+
+    /// Set `signature` from `tick` on.
+    ///
+    /// Changes may be inserted in any order, a later change at the same tick replaces the earlier one.
+    pub fn insert(&mut self, tick: u64, signature: TimeSignature) {
+        let segment = MeterSegment {
+            tick,
+            bar: 0,
+            signature,
+        };
+        match self.segments.binary_search_by_key(&tick, |seg| seg.tick) {
+            Ok(index) => self.segments[index] = segment,
+            Err(index) => self.segments.insert(index, segment),
+        }
+        self.segments[0].bar = 0;
+        for index in 1..self.segments.len() {
+            let previous = self.segments[index - 1];
+            let ticks = self.segments[index].tick - previous.tick;
+            self.segments[index].bar = previous.bar + ticks.div_ceil(self.bar_len(&previous));
+        }
+    }
+
+    pub fn signature_at(&self, tick: u64) -> TimeSignature {
+        self.segment_at(tick).signature
+    }
+
+    pub fn tick_to_bbt(&self, tick: u64) -> BarBeatTick {
+        let seg = self.segment_at(tick);
+        let (bar_len, beat_len) = (self.bar_len(seg), self.beat_len(seg));
+        let ticks = tick - seg.tick;
+        let in_bar = ticks % bar_len;
+        BarBeatTick {
+            bar: (seg.bar + ticks / bar_len + 1) as u32,
+            beat: (in_bar / beat_len + 1) as u32,
+            tick: (in_bar % beat_len) as u32,
+        }
+    }
+
+    /// Beats and ticks past the end of a bar carry over into the following ones
+    pub fn bbt_to_tick(&self, position: BarBeatTick) -> u64 {
+        let bar = u64::from(position.bar.max(1) - 1);
+        let index = self.segments.partition_point(|seg| seg.bar <= bar) - 1;
+        let seg = &self.segments[index];
+        seg.tick
+            + (bar - seg.bar) * self.bar_len(seg)
+            + u64::from(position.beat.max(1) - 1) * self.beat_len(seg)
+            + u64::from(position.tick)
+    }
+
+    fn segment_at(&self, tick: u64) -> &MeterSegment {
+        let index = self.segments.partition_point(|seg| seg.tick <= tick) - 1;
+        &self.segments[index]
+    }
+
+    fn beat_len(&self, seg: &MeterSegment) -> u64 {
+        (self.ticks_per_quarter * 4 / u64::from(seg.signature.denominator())).max(1)
+    }
+
+    fn bar_len(&self, seg: &MeterSegment) -> u64 {
+        self.beat_len(seg) * u64::from(seg.signature.numerator.max(1))
+    }
+}
+
+/// Events of `track` with their absolute tick
+fn absolute_ticks(track: &TrackChunk) -> impl Iterator<Item = (u64, &Event)> {
+    track.events().iter().scan(0u64, |tick, track_event| {
+        let (delta, event) = track_event.event();
+        *tick += u64::from(delta);
+        Some((*tick, event))
+    })
+}
+
+/// Sample index of the absolute microseconds `micros`
+pub fn micros_to_sample(micros: u64, sample_rate: u32) -> u64 {
+    micros * u64::from(sample_rate) / 1_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(numerator: u8, denominator_power: u8) -> TimeSignature {
+        TimeSignature {
+            numerator,
+            denominator_power,
+            ..TimeSignature::default()
+        }
+    }
+
+    fn bbt(bar: u32, beat: u32, tick: u32) -> BarBeatTick {
+        BarBeatTick { bar, beat, tick }
+    }
+
+    #[test]
+    fn micros_to_tick_inverts_tick_to_micros() {
+        let mut map = TempoMap::new(Division::Metrical(96));
+        map.insert(96, 250_000);
+        map.insert(200, 333_333);
+        for tick in 0..400 {
+            assert_eq!(map.micros_to_tick(map.tick_to_micros(tick)), tick);
+        }
+        assert_eq!(map.micros_to_tick(750_000), 192);
+        // the tick still playing
+        assert_eq!(map.micros_to_tick(750_000 + 2_603), 192);
+        assert_eq!(map.seconds_to_tick(0.5), 96);
+        assert_eq!(map.tick_to_seconds(192), 0.75);
+    }
+
+    #[test]
+    fn samples_and_timecode() {
+        // 29.97 drop frame, 4 ticks per frame
+        let map = TempoMap::new(Division::Timecode {
+            fps: 29,
+            ticks_per_frame: 4,
+        });
+        for tick in 0..1000 {
+            let micros = map.tick_to_micros(tick);
+            assert_eq!(map.micros_to_tick(micros), tick);
+            assert_eq!(
+                map.sample_to_tick(map.tick_to_sample(tick, 48_000), 48_000),
+                tick
+            );
+        }
+        assert_eq!(map.tick_to_sample(120, 48_000), 48_048);
+    }
+
+    #[test]
+    fn bar_beat_tick_in_four_four() {
+        let map = MeterMap::new(Division::Metrical(96));
+        assert_eq!(map.tick_to_bbt(0), bbt(1, 1, 0));
+        assert_eq!(map.tick_to_bbt(95), bbt(1, 1, 95));
+        assert_eq!(map.tick_to_bbt(96 * 5 + 3), bbt(2, 2, 3));
+        assert_eq!(map.bbt_to_tick(bbt(2, 2, 3)), 96 * 5 + 3);
+        assert_eq!(bbt(2, 2, 3).to_string(), "2:2:3");
+    }
+
+    #[test]
+    fn meter_changes() {
+        let mut map = MeterMap::new(Division::Metrical(96));
+        // 6/8 from bar 3, 3/4 half way through bar 4
+        map.insert(96 * 8, signature(6, 3));
+        map.insert(96 * 8 + 48 * 9, signature(3, 2));
+        assert_eq!(map.tick_to_bbt(96 * 8), bbt(3, 1, 0));
+        assert_eq!(map.tick_to_bbt(96 * 8 + 48 * 7 + 1), bbt(4, 2, 1));
+        // the partial bar counts as one
+        assert_eq!(map.tick_to_bbt(96 * 8 + 48 * 9), bbt(5, 1, 0));
+        assert_eq!(map.signature_at(96 * 8 + 48 * 9).numerator, 3);
+        for tick in (0..96 * 30).step_by(7) {
+            assert_eq!(map.bbt_to_tick(map.tick_to_bbt(tick)), tick);
+        }
+        // beats past the bar carry over
+        assert_eq!(map.bbt_to_tick(bbt(3, 7, 0)), map.bbt_to_tick(bbt(4, 1, 0)));
+    }
+}
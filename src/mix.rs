@@ -1 +1,624 @@
-pub struct Mixer {}
+use std::f32::consts::FRAC_PI_2;
+
+use crate::{
+    effect::{
+        distortion::{Bitcrusher, BitcrusherParams, Distortion, DistortionParams},
+        dynamics::{Compressor, CompressorParams, Limiter, LimiterParams},
+        Effect,
+    },
+    voice::CHANNELS,
+};
+
+/// Send buses per channel strip
+pub const MAX_BUSES: usize = 8;
+
+/// Time constant of gain changes in seconds
+const SMOOTHING_TIME: f32 = 0.005;
+
+/// Level of the mix below which it counts as silent, -100 dB
+const SILENCE_FLOOR: f32 = 1e-5;
+/// Seconds the mix stays silent before the mixer is
+const SILENCE_TIME: f32 = 0.1;
+
+/// Sixteen channel strips, send buses and a compressed and limited master bus
+///
+/// ```txt
+/// voice sum of channel n -> strip n -> distortion -> bitcrusher -> gain * volume * expression -> pan ┬──────────> master -> compressor -> limiter
+///                                                                                                     └ send k -> bus k ┘
+/// ```
+pub struct Mixer {
+    strips: [ChannelStrip; CHANNELS],
+    buses: Vec<Bus>,
+    master: MasterBus,
+    smoothing: f32,
+    sample_rate: f32,
+    /// frames since the master output went below [`SILENCE_FLOOR`]
+    silent_frames: u32,
+}
+
+/// Controller 7 after a reset
+const DEFAULT_VOLUME: f32 = 100.0 / 127.0;
+
+/// Mixer channel of one MIDI channel
+pub struct ChannelStrip {
+    /// linear gain on top of the MIDI controllers
+    pub gain: f32,
+    /// controller 7 `[0, 1]`
+    pub volume: f32,
+    /// controller 11 `[0, 1]`
+    pub expression: f32,
+    /// controller 10, -1 left to 1 right
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+    /// send levels to the buses, post fader
+    pub sends: [f32; MAX_BUSES],
+    /// inserts before the fader, bypassed until a patch turns them on
+    pub distortion: Insert<Distortion>,
+    pub bitcrusher: Insert<Bitcrusher>,
+    /// smoothed left and right gains
+    current: [f32; 2],
+    current_sends: [f32; MAX_BUSES],
+}
+
+impl ChannelStrip {
+    fn new(sample_rate: f32) -> Self {
+        let mut strip = Self {
+            gain: 1.0,
+            volume: DEFAULT_VOLUME,
+            expression: 1.0,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+            sends: [0.0; MAX_BUSES],
+            distortion: Insert::bypassed(Distortion::new(DistortionParams::default(), sample_rate)),
+            bitcrusher: Insert::bypassed(Bitcrusher::new(BitcrusherParams::default(), sample_rate)),
+            current: [0.0; 2],
+            current_sends: [0.0; MAX_BUSES],
+        };
+        strip.current = strip.pan_gains();
+        strip
+    }
+
+    /// Left and right gains with a constant power pan law
+    pub fn pan_gains(&self) -> [f32; 2] {
+        let fader = self.gain * controller_gain(self.volume) * controller_gain(self.expression);
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * 0.5 * FRAC_PI_2;
+        [fader * angle.cos(), fader * angle.sin()]
+    }
+}
+
+/// Level below which a bypassed insert stops processing
+const BYPASS_FLOOR: f32 = 1e-4;
+
+/// Effect slot of a bus or a channel strip, bypassing crossfades to the dry signal so it
+/// does not click
+///
+/// The send into a bus is not part of its return, so the first insert of a bus
+/// crossfades to silence instead. The inserts of a strip have a fixed effect type.
+pub struct Insert<E: Effect + ?Sized = dyn Effect> {
+    effect: Box<E>,
+    pub bypass: bool,
+    /// smoothed share of the effect output
+    mix: f32,
+}
+
+impl Insert {
+    fn new<E: Effect + 'static>(effect: E) -> Self {
+        Self {
+            effect: Box::new(effect),
+            bypass: false,
+            mix: 1.0,
+        }
+    }
+
+    /// Swap in another effect, this allocates so call it outside the audio callback
+    pub fn set_effect<E: Effect + 'static>(&mut self, effect: E) {
+        self.effect = Box::new(effect);
+    }
+}
+
+impl<E: Effect> Insert<E> {
+    fn bypassed(effect: E) -> Self {
+        Self {
+            effect: Box::new(effect),
+            bypass: true,
+            mix: 0.0,
+        }
+    }
+}
+
+impl<E: Effect + ?Sized> Insert<E> {
+    pub fn effect_mut(&mut self) -> &mut E {
+        self.effect.as_mut()
+    }
+
+    /// `dry` is what a bypassed insert passes on
+    fn process(&mut self, frame: [f32; 2], dry: [f32; 2], smoothing: f32) -> [f32; 2] {
+        if self.bypass && self.mix < BYPASS_FLOOR {
+            self.mix = 0.0;
+            return dry;
+        }
+        if self.mix == 0.0 {
+            // coming back from a bypass, drop what the effect held before it
+            self.effect.reset();
+        }
+        let target = if self.bypass { 0.0 } else { 1.0 };
+        self.mix = target + (self.mix - target) * smoothing;
+        let wet = self.effect.process(frame);
+        [
+            dry[0] + (wet[0] - dry[0]) * self.mix,
+            dry[1] + (wet[1] - dry[1]) * self.mix,
+        ]
+    }
+}
+
+/// Effect return
+pub struct Bus {
+    inserts: Vec<Insert>,
+    /// linear gain of the return into the master bus
+    pub gain: f32,
+    pub mute: bool,
+    /// MIDI controller setting the send of a channel to this bus, like 91 for reverb
+    pub controller: Option<u8>,
+    input: [f32; 2],
+    /// frames since the input and the output went below [`SILENCE_FLOOR`]
+    silent_frames: u32,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self {
+            inserts: Vec::new(),
+            gain: 1.0,
+            mute: false,
+            controller: None,
+            input: [0.0; 2],
+            silent_frames: 0,
+        }
+    }
+}
+
+impl Bus {
+    pub fn with_effect<E: Effect + 'static>(mut self, effect: E) -> Self {
+        self.inserts.push(Insert::new(effect));
+        self
+    }
+
+    pub fn with_controller(self, controller: u8) -> Self {
+        Self {
+            controller: Some(controller),
+            ..self
+        }
+    }
+
+    /// Effects in processing order
+    pub fn inserts_mut(&mut self) -> &mut [Insert] {
+        &mut self.inserts
+    }
+
+    /// Longest time in seconds the return can stay silent while an effect holds signal
+    fn silent_gap(&self) -> f32 {
+        self.inserts
+            .iter()
+            .map(|insert| insert.effect.silent_gap())
+            .fold(0.0, f32::max)
+    }
+
+    fn process(&mut self, smoothing: f32) -> [f32; 2] {
+        let input = std::mem::take(&mut self.input);
+        let mut frame = input;
+        for (i, insert) in self.inserts.iter_mut().enumerate() {
+            let dry = if i == 0 { [0.0; 2] } else { frame };
+            frame = insert.process(frame, dry, smoothing);
+        }
+        if is_silent(input) && is_silent(frame) {
+            self.silent_frames = self.silent_frames.saturating_add(1);
+        } else {
+            self.silent_frames = 0;
+        }
+        if self.mute {
+            [0.0; 2]
+        } else {
+            [frame[0] * self.gain, frame[1] * self.gain]
+        }
+    }
+}
+
+/// Master gain, then the compressor and the limiter
+pub struct MasterBus {
+    pub gain: f32,
+    /// channel whose strip output keys the compressor instead of the mix, for ducking
+    pub sidechain: Option<u8>,
+    compressor: Compressor,
+    limiter: Limiter,
+}
+
+impl MasterBus {
+    pub fn compressor_mut(&mut self) -> &mut Compressor {
+        &mut self.compressor
+    }
+
+    pub fn limiter_mut(&mut self) -> &mut Limiter {
+        &mut self.limiter
+    }
+}
+
+impl Mixer {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            strips: std::array::from_fn(|_| ChannelStrip::new(sample_rate)),
+            buses: Vec::with_capacity(MAX_BUSES),
+            master: MasterBus {
+                gain: 1.0,
+                sidechain: None,
+                compressor: Compressor::new(CompressorParams::default(), sample_rate),
+                limiter: Limiter::new(LimiterParams::default(), sample_rate),
+            },
+            smoothing: (-1.0 / (SMOOTHING_TIME * sample_rate)).exp(),
+            sample_rate,
+            silent_frames: 0,
+        }
+    }
+
+    pub fn strip(&self, channel: u8) -> &ChannelStrip {
+        &self.strips[usize::from(channel) % CHANNELS]
+    }
+
+    pub fn strip_mut(&mut self, channel: u8) -> &mut ChannelStrip {
+        &mut self.strips[usize::from(channel) % CHANNELS]
+    }
+
+    /// Returns the index of the new bus, or gives the bus back if all `MAX_BUSES` are taken
+    pub fn add_bus(&mut self, bus: Bus) -> Result<usize, Bus> {
+        if self.buses.len() < MAX_BUSES {
+            self.buses.push(bus);
+            Ok(self.buses.len() - 1)
+        } else {
+            Err(bus)
+        }
+    }
+
+    pub fn bus_mut(&mut self, index: usize) -> Option<&mut Bus> {
+        self.buses.get_mut(index)
+    }
+
+    pub fn master_mut(&mut self) -> &mut MasterBus {
+        &mut self.master
+    }
+
+    /// Seconds per quarter note, passed on to every effect
+    pub fn set_tempo(&mut self, quarter_seconds: f32) {
+        for insert in self.buses.iter_mut().flat_map(|bus| bus.inserts.iter_mut()) {
+            insert.effect.set_tempo(quarter_seconds);
+        }
+    }
+
+    /// Returns true once the mix has stayed silent for a while, and every bus for longer
+    /// than its effects can hold signal back, so reverb, chorus and delay tails have died away
+    pub fn is_silent(&self) -> bool {
+        let frames = |seconds: f32| (seconds * self.sample_rate) as u32;
+        self.silent_frames >= frames(SILENCE_TIME)
+            && self
+                .buses
+                .iter()
+                .all(|bus| bus.silent_frames >= frames(bus.silent_gap()))
+    }
+
+    /// Apply a MIDI controller, returns false if the mixer does not use it
+    pub fn control_change(&mut self, channel: u8, controller: u8, value: u8) -> bool {
+        let bus = self
+            .buses
+            .iter()
+            .position(|bus| bus.controller == Some(controller));
+        let strip = self.strip_mut(channel);
+        let value = f32::from(value.min(127));
+        match (controller, bus) {
+            (_, Some(bus)) => strip.sends[bus] = value / 127.0,
+            (7, _) => strip.volume = value / 127.0,
+            (10, _) => strip.pan = ((value - 64.0) / 63.0).clamp(-1.0, 1.0),
+            (11, _) => strip.expression = value / 127.0,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Reset all controllers, controller 121
+    ///
+    /// Volume and pan are kept as recommended by RP-015.
+    pub fn reset_controllers(&mut self, channel: u8) {
+        self.strip_mut(channel).expression = 1.0;
+    }
+
+    /// Controller defaults of a strip, on a GM, GS or XG reset
+    pub fn reset_channel(&mut self, channel: u8) {
+        let strip = &mut self.strips[usize::from(channel) % CHANNELS];
+        for (send, bus) in strip.sends.iter_mut().zip(&self.buses) {
+            if bus.controller.is_some() {
+                *send = 0.0;
+            }
+        }
+        strip.volume = DEFAULT_VOLUME;
+        strip.expression = 1.0;
+        strip.pan = 0.0;
+    }
+
+    /// Mix one frame of per channel stereo voice sums
+    pub fn process(&mut self, inputs: &[[f32; 2]; CHANNELS]) -> [f32; 2] {
+        let any_solo = self.strips.iter().any(|s| s.solo);
+        let s = self.smoothing;
+        let mut out = [0f32; 2];
+        let mut key = None;
+        for (channel, (strip, input)) in self.strips.iter_mut().zip(inputs).enumerate() {
+            let audible = !strip.mute && (!any_solo || strip.solo);
+            let target = if audible { strip.pan_gains() } else { [0.0; 2] };
+            for (current, target) in strip.current.iter_mut().zip(target) {
+                *current = target + (*current - target) * s;
+            }
+            let input = strip.distortion.process(*input, *input, s);
+            let input = strip.bitcrusher.process(input, input, s);
+            let left = input[0] * strip.current[0];
+            let right = input[1] * strip.current[1];
+            out[0] += left;
+            out[1] += right;
+            if self.master.sidechain.map(usize::from) == Some(channel) {
+                key = Some([left, right]);
+            }
+            for (bus, (current, send)) in self
+                .buses
+                .iter_mut()
+                .zip(strip.current_sends.iter_mut().zip(strip.sends))
+            {
+                let target = if audible { send } else { 0.0 };
+                *current = target + (*current - target) * s;
+                bus.input[0] += left * *current;
+                bus.input[1] += right * *current;
+            }
+        }
+        for bus in self.buses.iter_mut() {
+            let [left, right] = bus.process(s);
+            out[0] += left;
+            out[1] += right;
+        }
+        let gain = self.master.gain;
+        let out = [out[0] * gain, out[1] * gain];
+        let out = self
+            .master
+            .compressor
+            .process_keyed(out, key.unwrap_or(out));
+        // after the limiter, so what its look-ahead holds back is out before the end
+        let out = self.master.limiter.process(out);
+        if is_silent(out) {
+            self.silent_frames = self.silent_frames.saturating_add(1);
+        } else {
+            self.silent_frames = 0;
+        }
+        out
+    }
+}
+
+fn is_silent([left, right]: [f32; 2]) -> bool {
+    left.abs().max(right.abs()) < SILENCE_FLOOR
+}
+
+/// Gain of a volume or expression controller, 40 log10(value) dB as recommended by GM
+fn controller_gain(value: f32) -> f32 {
+    value * value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    fn settle(mixer: &mut Mixer, inputs: &[[f32; 2]; CHANNELS]) -> [f32; 2] {
+        let mut out = [0.0; 2];
+        for _ in 0..200 {
+            out = mixer.process(inputs);
+        }
+        out
+    }
+
+    fn only(channel: usize, value: f32) -> [[f32; 2]; CHANNELS] {
+        let mut inputs = [[0.0; 2]; CHANNELS];
+        inputs[channel] = [value; 2];
+        inputs
+    }
+
+    #[test]
+    fn constant_power_pan() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        mixer.control_change(0, 7, 127);
+        for pan in [0, 32, 64, 96, 127] {
+            mixer.control_change(0, 10, pan);
+            let [l, r] = mixer.strip(0).pan_gains();
+            assert!((l * l + r * r - 1.0).abs() < 1e-5);
+        }
+        mixer.control_change(0, 10, 0);
+        let [l, r] = settle(&mut mixer, &only(0, 0.5));
+        assert!((l - 0.5).abs() < 1e-4 && r.abs() < 1e-4);
+        mixer.control_change(0, 10, 64);
+        let [l, r] = settle(&mut mixer, &only(0, 0.5));
+        assert!((l - r).abs() < 1e-6 && (l - 0.5 / 2f32.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn volume_and_expression_follow_gm_curve() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        mixer.control_change(2, 7, 127);
+        mixer.control_change(2, 11, 64);
+        let [l, r] = mixer.strip(2).pan_gains();
+        let db = 20.0 * (l * l + r * r).sqrt().log10();
+        assert!((db - 40.0 * (64f32 / 127.0).log10()).abs() < 1e-3);
+        assert!(!mixer.control_change(2, 1, 0));
+        mixer.reset_controllers(2);
+        assert_eq!(mixer.strip(2).expression, 1.0);
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        let inputs = {
+            let mut inputs = [[0.0; 2]; CHANNELS];
+            inputs[0] = [0.1; 2];
+            inputs[1] = [0.2; 2];
+            inputs
+        };
+        let both = settle(&mut mixer, &inputs);
+        mixer.strip_mut(1).solo = true;
+        let solo = settle(&mut mixer, &inputs);
+        let alone = {
+            let mut mixer = Mixer::new(SAMPLE_RATE);
+            settle(&mut mixer, &only(1, 0.2))
+        };
+        assert!((solo[0] - alone[0]).abs() < 1e-6);
+        assert!(both[0] > solo[0]);
+        mixer.strip_mut(1).mute = true;
+        assert!(settle(&mut mixer, &inputs)[0].abs() < 1e-6);
+    }
+
+    struct Double;
+
+    impl Effect for Double {
+        fn process(&mut self, [l, r]: [f32; 2]) -> [f32; 2] {
+            [l * 2.0, r * 2.0]
+        }
+    }
+
+    #[test]
+    fn sends_feed_buses() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        let dry = settle(&mut mixer, &only(3, 0.1));
+        let bus = mixer
+            .add_bus(Bus::default().with_effect(Double))
+            .ok()
+            .unwrap();
+        mixer.strip_mut(3).sends[bus] = 0.5;
+        let wet = settle(&mut mixer, &only(3, 0.1));
+        assert!((wet[0] - dry[0] * 2.0).abs() < 1e-5);
+        mixer.bus_mut(bus).unwrap().mute = true;
+        assert!((settle(&mut mixer, &only(3, 0.1))[0] - dry[0]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bypass_crossfades() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        let bus = mixer
+            .add_bus(Bus::default().with_effect(Double))
+            .ok()
+            .unwrap();
+        mixer.strip_mut(0).sends[bus] = 1.0;
+        let inputs = only(0, 0.1);
+        let wet = settle(&mut mixer, &inputs);
+        mixer.bus_mut(bus).unwrap().inserts_mut()[0].bypass = true;
+        let mut last = wet;
+        for _ in 0..200 {
+            let out = mixer.process(&inputs);
+            // no step larger than the smoothing allows
+            assert!((out[0] - last[0]).abs() < wet[0] * 0.2);
+            last = out;
+        }
+        // the return fades out, the channel itself is left
+        let dry = settle(&mut Mixer::new(SAMPLE_RATE), &inputs)[0];
+        assert!((last[0] - dry).abs() < 1e-4);
+        mixer.bus_mut(bus).unwrap().inserts_mut()[0].bypass = false;
+        assert!((settle(&mut mixer, &inputs)[0] - wet[0]).abs() < 1e-5);
+        // a later insert passes on what the one before returns
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        let bus = mixer
+            .add_bus(Bus::default().with_effect(Double).with_effect(Double))
+            .ok()
+            .unwrap();
+        mixer.strip_mut(0).sends[bus] = 1.0;
+        mixer.bus_mut(bus).unwrap().inserts_mut()[1].bypass = true;
+        assert!((settle(&mut mixer, &inputs)[0] - dry * 3.0).abs() < 1e-4);
+    }
+
+    /// Holds signal back for half a second
+    struct Echo;
+
+    impl Effect for Echo {
+        fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+            frame
+        }
+
+        fn silent_gap(&self) -> f32 {
+            0.5
+        }
+    }
+
+    #[test]
+    fn silence_outlasts_effect_gaps() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        settle(&mut mixer, &only(0, 0.1));
+        assert!(!mixer.is_silent());
+        let silence = [[0.0; 2]; CHANNELS];
+        let frames = (1..1000).find(|_| {
+            mixer.process(&silence);
+            mixer.is_silent()
+        });
+        // a tenth of a second after the limiter let the last frame out
+        let latency = mixer.master_mut().limiter_mut().latency() as u32;
+        assert_eq!(frames, Some(100 + latency));
+        assert!((0..latency).all(|_| mixer.process(&silence) == [0.0; 2]));
+        mixer
+            .add_bus(Bus::default().with_effect(Echo))
+            .ok()
+            .unwrap();
+        assert!(!mixer.is_silent());
+        for _ in 0..500 {
+            mixer.process(&silence);
+        }
+        assert!(mixer.is_silent());
+    }
+
+    #[test]
+    fn controller_sets_send() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        assert!(!mixer.control_change(5, 91, 127));
+        mixer.add_bus(Bus::default()).ok().unwrap();
+        let bus = mixer
+            .add_bus(Bus::default().with_controller(91))
+            .ok()
+            .unwrap();
+        assert!(mixer.control_change(5, 91, 127));
+        assert_eq!(mixer.strip(5).sends[bus], 1.0);
+        mixer.strip_mut(5).sends[0] = 0.5;
+        mixer.reset_channel(5);
+        assert_eq!(mixer.strip(5).sends[..2], [0.5, 0.0]);
+    }
+
+    #[test]
+    fn limiter_holds_ceiling() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        let mut inputs = [[0.0; 2]; CHANNELS];
+        for (i, input) in inputs.iter_mut().enumerate() {
+            *input = if i % 2 == 0 { [1.0; 2] } else { [0.8; 2] };
+        }
+        let [l, r] = settle(&mut mixer, &inputs);
+        assert!(l <= 0.99 + 1e-6 && r <= 0.99 + 1e-6);
+        assert!(l > 0.9);
+    }
+
+    #[test]
+    fn sidechain_keys_compressor() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        mixer
+            .master_mut()
+            .compressor_mut()
+            .set_params(CompressorParams {
+                threshold: -40.0,
+                ratio: 10.0,
+                ..CompressorParams::default()
+            });
+        mixer.master_mut().sidechain = Some(9);
+        // the mix is loud but the key channel is silent
+        settle(&mut mixer, &only(0, 0.5));
+        assert_eq!(mixer.master_mut().compressor_mut().reduction(), 0.0);
+        let mut inputs = only(0, 0.5);
+        inputs[9] = [0.5; 2];
+        settle(&mut mixer, &inputs);
+        assert!(mixer.master_mut().compressor_mut().reduction() < -20.0);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::osc::{OscKind, Oscillator};
+
+/// Tempo until the first tempo event, 120 quarter notes per minute
+pub const DEFAULT_QUARTER_SECONDS: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    Wave(OscKind),
+    /// A new random level every cycle
+    SampleAndHold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoRate {
+    Hz(f32),
+    /// Cycle length in quarter notes, following the tempo
+    Beats(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LfoParams {
+    pub shape: LfoShape,
+    pub rate: LfoRate,
+    /// `[0, 1)` of a cycle the LFO starts at on every note
+    pub phase: f32,
+}
+
+impl Default for LfoParams {
+    fn default() -> Self {
+        Self {
+            shape: LfoShape::Wave(OscKind::Sine),
+            rate: LfoRate::Hz(5.0),
+            phase: 0.0,
+        }
+    }
+}
+
+/// Low frequency oscillator, bipolar `[-1, 1]`
+#[derive(Debug, Clone)]
+pub struct Lfo {
+    params: LfoParams,
+    osc: Oscillator,
+    /// seconds per quarter note
+    quarter_seconds: f32,
+    /// sample and hold level and xorshift state
+    held: f32,
+    seed: u32,
+}
+
+impl Lfo {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut lfo = Self {
+            params: LfoParams::default(),
+            osc: Oscillator::new(OscKind::Sine, sample_rate),
+            quarter_seconds: DEFAULT_QUARTER_SECONDS,
+            held: 0.0,
+            seed: 0x2545_F491,
+        };
+        lfo.start(LfoParams::default());
+        lfo
+    }
+
+    pub fn params(&self) -> &LfoParams {
+        &self.params
+    }
+
+    /// Restart at the phase of `params`
+    pub fn start(&mut self, params: LfoParams) {
+        self.params = params;
+        self.osc.set_kind(match params.shape {
+            LfoShape::Wave(kind) => kind,
+            LfoShape::SampleAndHold => OscKind::Sawtooth,
+        });
+        self.osc.set_phase(params.phase);
+        self.update_frequency();
+        self.held = self.random();
+    }
+
+    /// Beat synced rates follow from the next sample on
+    pub fn set_tempo(&mut self, quarter_seconds: f32) {
+        self.quarter_seconds = quarter_seconds.max(1e-3);
+        self.update_frequency();
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.osc.frequency()
+    }
+
+    fn update_frequency(&mut self) {
+        self.osc.set_frequency(match self.params.rate {
+            LfoRate::Hz(hz) => hz,
+            LfoRate::Beats(beats) => 1.0 / (beats.max(1e-3) * self.quarter_seconds),
+        });
+    }
+
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        (x as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        match self.params.shape {
+            LfoShape::Wave(_) => self.osc.next_sample(),
+            LfoShape::SampleAndHold => {
+                let before = self.osc.phase();
+                self.osc.next_sample();
+                if self.osc.phase() < before {
+                    self.held = self.random();
+                }
+                self.held
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beat_sync_follows_tempo() {
+        let mut lfo = Lfo::new(1000.0);
+        lfo.start(LfoParams {
+            rate: LfoRate::Beats(2.0),
+            ..LfoParams::default()
+        });
+        // two quarter notes at 120 bpm
+        assert_eq!(lfo.frequency(), 1.0);
+        lfo.set_tempo(0.25);
+        assert_eq!(lfo.frequency(), 2.0);
+        lfo.start(LfoParams::default());
+        assert_eq!(lfo.frequency(), 5.0);
+    }
+
+    #[test]
+    fn starts_at_phase() {
+        let mut lfo = Lfo::new(1000.0);
+        lfo.start(LfoParams {
+            phase: 0.25,
+            ..LfoParams::default()
+        });
+        assert!((lfo.next_sample() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sample_and_hold_steps_once_a_cycle() {
+        let mut lfo = Lfo::new(1000.0);
+        lfo.start(LfoParams {
+            shape: LfoShape::SampleAndHold,
+            rate: LfoRate::Hz(10.0),
+            phase: 0.0,
+        });
+        let samples = (0..1000).map(|_| lfo.next_sample()).collect::<Vec<_>>();
+        let steps = samples.windows(2).filter(|w| w[0] != w[1]).count();
+        assert!((9..=10).contains(&steps));
+        assert!(samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+        assert!(samples[..99].iter().all(|s| *s == samples[0]));
+    }
+}
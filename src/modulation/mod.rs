@@ -0,0 +1,182 @@
+/// Low frequency oscillators, free running or synced to the song tempo
+pub mod lfo;
+
+/// LFOs of a voice
+pub const LFOS: usize = 2;
+
+/// Routes a voice holds, more are ignored
+pub const MAX_ROUTES: usize = 16;
+
+/// Signal a route reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// `[-1, 1]`
+    Lfo(usize),
+    /// `[0, 1]`, the level the voice is playing at
+    AmpEnvelope,
+    /// `[0, 1]`, the second envelope of the patch
+    ModEnvelope,
+    /// `[0, 1]`
+    Velocity,
+    /// octaves from middle C
+    Key,
+    /// `[0, 1]`
+    ChannelPressure,
+    /// `[0, 1]`, polyphonic key pressure
+    Aftertouch,
+    /// `[0, 1]`, controller 1
+    ModWheel,
+    /// `[-1, 1]`
+    PitchBend,
+}
+
+/// Parameter a route changes, each in its own unit of depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// semitones
+    Pitch,
+    /// octaves
+    Cutoff,
+    /// octaves of Q
+    Resonance,
+    /// gain multiplied by `1 + depth * source`
+    Amp,
+    /// -1 left to 1 right
+    Pan,
+    /// added to the duty cycle of square oscillators
+    PulseWidth,
+}
+
+/// One connection of the modulation matrix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Route {
+    pub source: Source,
+    pub destination: Destination,
+    pub depth: f32,
+    /// scales the source, like the mod wheel bringing in vibrato
+    pub via: Option<Source>,
+}
+
+impl Route {
+    pub fn new(source: Source, destination: Destination, depth: f32) -> Self {
+        Self {
+            source,
+            destination,
+            depth,
+            via: None,
+        }
+    }
+
+    pub fn via(self, via: Source) -> Self {
+        Self {
+            via: Some(via),
+            ..self
+        }
+    }
+}
+
+/// Controllers of a MIDI channel that routes read
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelControls {
+    pub pressure: f32,
+    pub mod_wheel: f32,
+    pub pitch_bend: f32,
+}
+
+/// Source values of a voice at one sample
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sources {
+    pub lfos: [f32; LFOS],
+    pub amp_envelope: f32,
+    pub mod_envelope: f32,
+    pub velocity: f32,
+    pub key: f32,
+    pub aftertouch: f32,
+    pub channel: ChannelControls,
+}
+
+impl Sources {
+    pub fn value(&self, source: Source) -> f32 {
+        match source {
+            Source::Lfo(index) => self.lfos.get(index).copied().unwrap_or(0.0),
+            Source::AmpEnvelope => self.amp_envelope,
+            Source::ModEnvelope => self.mod_envelope,
+            Source::Velocity => self.velocity,
+            Source::Key => self.key,
+            Source::ChannelPressure => self.channel.pressure,
+            Source::Aftertouch => self.aftertouch,
+            Source::ModWheel => self.channel.mod_wheel,
+            Source::PitchBend => self.channel.pitch_bend,
+        }
+    }
+}
+
+/// Sum of the routes into every destination
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Modulation {
+    pub pitch: f32,
+    pub cutoff: f32,
+    pub resonance: f32,
+    pub amp: f32,
+    pub pan: f32,
+    pub pulse_width: f32,
+}
+
+impl Modulation {
+    pub fn new(routes: &[Route], sources: &Sources) -> Self {
+        let mut modulation = Self::default();
+        for route in routes {
+            let mut value = sources.value(route.source) * route.depth;
+            if let Some(via) = route.via {
+                value *= sources.value(via);
+            }
+            *match route.destination {
+                Destination::Pitch => &mut modulation.pitch,
+                Destination::Cutoff => &mut modulation.cutoff,
+                Destination::Resonance => &mut modulation.resonance,
+                Destination::Amp => &mut modulation.amp,
+                Destination::Pan => &mut modulation.pan,
+                Destination::PulseWidth => &mut modulation.pulse_width,
+            } += value;
+        }
+        modulation
+    }
+
+    /// Linear gain of the amp destination, never negative
+    pub fn gain(&self) -> f32 {
+        (1.0 + self.amp).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_sum_per_destination() {
+        let sources = Sources {
+            lfos: [0.5, -1.0],
+            velocity: 1.0,
+            key: 1.0,
+            channel: ChannelControls {
+                mod_wheel: 0.5,
+                ..ChannelControls::default()
+            },
+            ..Sources::default()
+        };
+        let routes = [
+            Route::new(Source::Lfo(0), Destination::Pitch, 2.0).via(Source::ModWheel),
+            Route::new(Source::Lfo(1), Destination::Pitch, 0.25),
+            Route::new(Source::Key, Destination::Cutoff, 1.0),
+            Route::new(Source::Velocity, Destination::Amp, -2.0),
+            // no such LFO
+            Route::new(Source::Lfo(7), Destination::Pan, 1.0),
+        ];
+        let modulation = Modulation::new(&routes, &sources);
+        assert_eq!(modulation.pitch, 0.25);
+        assert_eq!(modulation.cutoff, 1.0);
+        assert_eq!(modulation.pan, 0.0);
+        assert_eq!(modulation.gain(), 0.0);
+        assert_eq!(Modulation::new(&[], &sources), Modulation::default());
+    }
+}
@@ -1,11 +1,303 @@
-struct Oscillator {
+use std::f32::consts::TAU;
+
+/// Band-limited oscillator
+///
+/// Phase is kept in `[0, 1)` and only its increment changes with the frequency,
+/// so the waveform stays continuous when the pitch is modulated.
+///
+/// Sawtooth and Square are corrected with PolyBLEP, Triangle with PolyBLAMP.
+///
+/// - [Antialiasing Oscillators in Subtractive Synthesis](https://www.researchgate.net/publication/3321436)
+/// - [PolyBLEP](http://www.martin-finke.de/blog/articles/audio-plugins-018-polyblep-oscillator/)
+#[derive(Debug, Clone)]
+pub struct Oscillator {
     kind: OscKind,
-    
+    sample_rate: f32,
+    frequency: f32,
+    /// normalized phase `[0, 1)`
+    phase: f32,
+    /// phase increment per sample, `frequency / sample_rate`
+    phase_inc: f32,
+    amplitude: f32,
+    /// duty cycle of Square `(0, 1)`
+    pulse_width: f32,
+    /// xorshift state of Noise
+    seed: u32,
 }
 
-enum OscKind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OscKind {
     Square,
     Sine,
     Sawtooth,
     Triangle,
+    Noise,
+}
+
+impl Oscillator {
+    pub fn new(kind: OscKind, sample_rate: f32) -> Self {
+        let mut osc = Self {
+            kind,
+            sample_rate,
+            frequency: 0.0,
+            phase: 0.0,
+            phase_inc: 0.0,
+            amplitude: 1.0,
+            pulse_width: 0.5,
+            seed: 0x9E37_79B9,
+        };
+        osc.set_frequency(440.0);
+        osc
+    }
+
+    pub fn kind(&self) -> OscKind {
+        self.kind
+    }
+
+    /// Switch waveform without resetting the phase
+    pub fn set_kind(&mut self, kind: OscKind) {
+        self.kind = kind;
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    /// Frequencies are clamped to `[0, nyquist)`
+    pub fn set_frequency(&mut self, frequency: f32) {
+        let nyquist = self.sample_rate * 0.5;
+        self.frequency = frequency.clamp(0.0, nyquist * 0.999);
+        self.phase_inc = self.frequency / self.sample_rate;
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// Set normalized phase, wrapped into `[0, 1)`
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = phase.rem_euclid(1.0);
+    }
+
+    pub fn amplitude(&self) -> f32 {
+        self.amplitude
+    }
+
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        self.amplitude = amplitude;
+    }
+
+    /// Duty cycle of Square, clamped to `[0.01, 0.99]`
+    pub fn set_pulse_width(&mut self, pulse_width: f32) {
+        self.pulse_width = pulse_width.clamp(0.01, 0.99);
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Returns the next sample and advances the phase
+    pub fn next_sample(&mut self) -> f32 {
+        let (p, dt) = (self.phase, self.phase_inc);
+        let value = match self.kind {
+            OscKind::Sine => (TAU * p).sin(),
+            OscKind::Sawtooth => 2.0 * p - 1.0 - poly_blep(p, dt),
+            OscKind::Square => {
+                let pw = self.pulse_width;
+                let naive = if p < pw { 1.0 } else { -1.0 };
+                naive + poly_blep(p, dt) - poly_blep((p + 1.0 - pw) % 1.0, dt)
+            }
+            OscKind::Triangle => {
+                let naive = 1.0 - 4.0 * (p - 0.5).abs();
+                naive + 4.0 * dt * (poly_blamp(p, dt) - poly_blamp((p + 0.5) % 1.0, dt))
+            }
+            OscKind::Noise => {
+                // xorshift32
+                let mut x = self.seed;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.seed = x;
+                (x as f32 / u32::MAX as f32) * 2.0 - 1.0
+            }
+        };
+        self.phase += dt;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        value * self.amplitude
+    }
+
+    /// Fill `buffer` with consecutive samples
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        for s in buffer.iter_mut() {
+            *s = self.next_sample();
+        }
+    }
+}
+
+/// Polynomial band-limited step residual, `t` is phase and `dt` phase increment
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if dt <= 0.0 {
+        0.0
+    } else if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Polynomial band-limited ramp residual (integrated [`poly_blep`])
+fn poly_blamp(t: f32, dt: f32) -> f32 {
+    if dt <= 0.0 {
+        0.0
+    } else if t < dt {
+        let x = t / dt - 1.0;
+        -x * x * x / 3.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt + 1.0;
+        x * x * x / 3.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+    const N: usize = 2048;
+
+    /// Power of every DFT bin below nyquist
+    fn power_spectrum(samples: &[f32]) -> Vec<f64> {
+        let n = samples.len();
+        (0..n / 2)
+            .map(|k| {
+                let (mut re, mut im) = (0f64, 0f64);
+                for (i, s) in samples.iter().enumerate() {
+                    let w = std::f64::consts::TAU * (k * i % n) as f64 / n as f64;
+                    re += *s as f64 * w.cos();
+                    im -= *s as f64 * w.sin();
+                }
+                re * re + im * im
+            })
+            .collect()
+    }
+
+    /// Ratio in dB between power off the harmonic bins and power on them.
+    ///
+    /// `bin` is the fundamental, so every harmonic and every alias falls exactly on a bin.
+    fn alias_ratio_db(samples: &[f32], bin: usize) -> f64 {
+        let spectrum = power_spectrum(samples);
+        let (mut harmonic, mut alias) = (0f64, 0f64);
+        for (k, p) in spectrum.iter().enumerate().skip(1) {
+            if k % bin == 0 {
+                harmonic += p;
+            } else {
+                alias += p;
+            }
+        }
+        10.0 * (alias / harmonic).log10()
+    }
+
+    fn render(kind: OscKind, bin: usize) -> Vec<f32> {
+        let mut osc = Oscillator::new(kind, SAMPLE_RATE);
+        osc.set_frequency(bin as f32 * SAMPLE_RATE / N as f32);
+        // let the transient of the first period pass
+        let mut warmup = vec![0f32; N];
+        osc.render(&mut warmup);
+        let mut buffer = vec![0f32; N];
+        osc.render(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn sine_has_no_harmonics() {
+        let ratio = alias_ratio_db(&render(OscKind::Sine, 100), 100);
+        assert!(ratio < -60.0, "{}", ratio);
+    }
+
+    #[test]
+    fn sawtooth_aliasing_bound() {
+        // ~3 kHz
+        let bin = 140;
+        let blep = alias_ratio_db(&render(OscKind::Sawtooth, bin), bin);
+        let naive: Vec<f32> = (0..N)
+            .map(|i| 2.0 * ((i * bin) % N) as f32 / N as f32 - 1.0)
+            .collect();
+        let naive = alias_ratio_db(&naive, bin);
+        assert!(blep < -25.0, "{}", blep);
+        assert!(blep < naive - 10.0, "blep {} naive {}", blep, naive);
+    }
+
+    #[test]
+    fn square_aliasing_bound() {
+        let bin = 140;
+        let blep = alias_ratio_db(&render(OscKind::Square, bin), bin);
+        let naive: Vec<f32> = (0..N)
+            .map(|i| if (i * bin) % N < N / 2 { 1.0 } else { -1.0 })
+            .collect();
+        let naive = alias_ratio_db(&naive, bin);
+        assert!(blep < -25.0, "{}", blep);
+        assert!(blep < naive - 10.0, "blep {} naive {}", blep, naive);
+    }
+
+    #[test]
+    fn triangle_aliasing_bound() {
+        let bin = 140;
+        let blamp = alias_ratio_db(&render(OscKind::Triangle, bin), bin);
+        assert!(blamp < -45.0, "{}", blamp);
+    }
+
+    #[test]
+    fn phase_continuity_across_frequency_change() {
+        for kind in [OscKind::Sine, OscKind::Triangle] {
+            let mut osc = Oscillator::new(kind, SAMPLE_RATE);
+            osc.set_frequency(220.0);
+            let mut previous = osc.next_sample();
+            for i in 0..4096 {
+                if i % 512 == 0 {
+                    osc.set_frequency(220.0 + (i / 512) as f32 * 137.0);
+                }
+                let max_step = TAU * osc.frequency() / SAMPLE_RATE * 1.1 + 1e-3;
+                let sample = osc.next_sample();
+                assert!(
+                    (sample - previous).abs() <= max_step,
+                    "{:?} jump at {}",
+                    kind,
+                    i
+                );
+                previous = sample;
+            }
+        }
+    }
+
+    #[test]
+    fn phase_wraps_and_amplitude_scales() {
+        let mut osc = Oscillator::new(OscKind::Sawtooth, SAMPLE_RATE);
+        osc.set_frequency(1000.0);
+        osc.set_amplitude(0.25);
+        osc.set_phase(1.25);
+        assert!((osc.phase() - 0.25).abs() < 1e-6);
+        let mut buffer = [0f32; 1000];
+        osc.render(&mut buffer);
+        assert!(buffer.iter().all(|s| s.abs() <= 0.25 * 1.01));
+        assert!(osc.phase() >= 0.0 && osc.phase() < 1.0);
+    }
+
+    #[test]
+    fn noise_is_bounded_and_not_constant() {
+        let mut osc = Oscillator::new(OscKind::Noise, SAMPLE_RATE);
+        let mut buffer = [0f32; 1024];
+        osc.render(&mut buffer);
+        assert!(buffer.iter().all(|s| (-1.0..=1.0).contains(s)));
+        let mean = buffer.iter().sum::<f32>() / buffer.len() as f32;
+        assert!(mean.abs() < 0.1);
+        assert!(buffer.windows(2).any(|w| w[0] != w[1]));
+    }
 }
@@ -0,0 +1,880 @@
+use std::{
+    fmt::{Debug, Write as FmtWrite},
+    fs,
+    io::Error as StdIoError,
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use thiserror::Error;
+
+use super::Patch;
+use crate::{
+    effect::{
+        distortion::{BitcrusherParams, DistortionParams, Shape},
+        oversample::Oversampling,
+    },
+    envelope::{Curve, EnvelopeParams},
+    filter::{FilterKind, FilterParams},
+    modulation::{
+        lfo::{LfoParams, LfoRate, LfoShape},
+        Destination, Route, Source, MAX_ROUTES,
+    },
+    osc::OscKind,
+};
+
+#[derive(Error, Debug)]
+pub enum PatchError {
+    #[error("patch file error: {0}")]
+    IOError(#[from] StdIoError),
+    #[error("line {line}: {kind}")]
+    Line { line: usize, kind: LineError },
+}
+
+/// What is wrong with a line of a patch file
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LineError {
+    #[error("expected `key = value` or `[section]`")]
+    Syntax,
+    #[error("unknown section `[{0}]`, expected one of {SECTIONS}")]
+    UnknownSection(String),
+    #[error("section `[{0}]` appears twice")]
+    DuplicateSection(String),
+    #[error("unknown key `{key}` in {section}")]
+    UnknownKey { section: String, key: String },
+    #[error("`{0}` is set twice")]
+    DuplicateKey(String),
+    #[error("`{key}` should be {expected}, found `{found}`")]
+    Type {
+        key: String,
+        expected: &'static str,
+        found: String,
+    },
+    #[error("`{key}` = {value} is out of range, expected {min} to {max}")]
+    Range {
+        key: String,
+        value: f32,
+        min: f32,
+        max: f32,
+    },
+    #[error("`{key}` = \"{found}\" is not one of {expected}")]
+    Choice {
+        key: String,
+        expected: &'static str,
+        found: String,
+    },
+    #[error("`{0}` and `{1}` cannot both be set")]
+    Conflict(String, String),
+    #[error("more than {MAX_ROUTES} `[[route]]`s")]
+    TooManyRoutes,
+}
+
+const SECTIONS: &str = "[osc1], [osc2], [envelope], [filter], [lfo1], [lfo2], [mod_envelope], \
+    [distortion], [bitcrusher], [[route]]";
+const OSC_KINDS: &str = "sine, square, sawtooth, triangle, noise";
+const LFO_SHAPES: &str = "sine, square, sawtooth, triangle, noise, sample_and_hold";
+const SOURCES: &str = "lfo1, lfo2, amp_envelope, mod_envelope, velocity, key, \
+    channel_pressure, aftertouch, mod_wheel, pitch_bend";
+const DESTINATIONS: &str = "pitch, cutoff, resonance, amp, pan, pulse_width";
+const CURVES: &str = "linear, exponential";
+const FILTER_KINDS: &str = "low_pass, high_pass, band_pass, notch, peak, low_shelf, high_shelf";
+const SHAPES: &str = "soft_clip, hard_clip, foldback, tube";
+const OVERSAMPLINGS: &str = "off, 2x, 4x, 8x";
+
+/// Longest envelope stage in seconds
+const MAX_STAGE: f32 = 60.0;
+
+/// Keys of an `[lfo1]` or `[lfo2]` section that exclude each other
+const LFO_CONFLICTS: [(&str, &str); 1] = [("rate", "beats")];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Text(String),
+    Number(f32),
+    Bool(bool),
+}
+
+impl Value {
+    fn parse(text: &str) -> Option<Self> {
+        if let Some(quoted) = text.strip_prefix('"') {
+            return unquote(quoted).map(Self::Text);
+        }
+        match text {
+            "true" => Some(Self::Bool(true)),
+            "false" => Some(Self::Bool(false)),
+            _ => text.replace('_', "").parse().ok().map(Self::Number),
+        }
+    }
+}
+
+/// Basic TOML string after the opening quote, `None` unless it is closed at the very end
+fn unquote(text: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return chars.next().is_none().then_some(out),
+            '\\' => out.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                '"' => '"',
+                '\\' => '\\',
+                _ => return None,
+            }),
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+fn quote(text: &str) -> String {
+    let mut out = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Strip a `#` comment outside of strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// A `key = value` line being applied to the patch
+struct Entry<'a> {
+    key: &'a str,
+    value: Value,
+}
+
+impl Entry<'_> {
+    fn type_error(&self, expected: &'static str) -> LineError {
+        LineError::Type {
+            key: self.key.to_string(),
+            expected,
+            found: match &self.value {
+                Value::Text(text) => quote(text),
+                Value::Number(number) => number.to_string(),
+                Value::Bool(value) => value.to_string(),
+            },
+        }
+    }
+
+    fn number(&self, range: RangeInclusive<f32>) -> Result<f32, LineError> {
+        match self.value {
+            Value::Number(value) if range.contains(&value) => Ok(value),
+            Value::Number(value) => Err(LineError::Range {
+                key: self.key.to_string(),
+                value,
+                min: *range.start(),
+                max: *range.end(),
+            }),
+            _ => Err(self.type_error("a number")),
+        }
+    }
+
+    fn bool(&self) -> Result<bool, LineError> {
+        match self.value {
+            Value::Bool(value) => Ok(value),
+            _ => Err(self.type_error("true or false")),
+        }
+    }
+
+    fn text(&self) -> Result<&str, LineError> {
+        match &self.value {
+            Value::Text(text) => Ok(text),
+            _ => Err(self.type_error("a quoted string")),
+        }
+    }
+
+    /// One of `choices` by name, `expected` lists them for the error
+    fn choice<T: Copy>(
+        &self,
+        choices: &[(&str, T)],
+        expected: &'static str,
+    ) -> Result<T, LineError> {
+        let text = self.text()?;
+        choices
+            .iter()
+            .find(|(name, _)| *name == text)
+            .map(|(_, choice)| *choice)
+            .ok_or_else(|| LineError::Choice {
+                key: self.key.to_string(),
+                expected,
+                found: text.to_string(),
+            })
+    }
+}
+
+const OSC_KIND_NAMES: [(&str, OscKind); 5] = [
+    ("sine", OscKind::Sine),
+    ("square", OscKind::Square),
+    ("sawtooth", OscKind::Sawtooth),
+    ("triangle", OscKind::Triangle),
+    ("noise", OscKind::Noise),
+];
+
+const CURVE_NAMES: [(&str, Curve); 2] = [
+    ("linear", Curve::Linear),
+    ("exponential", Curve::Exponential),
+];
+
+const LFO_SHAPE_NAMES: [(&str, LfoShape); 6] = [
+    ("sine", LfoShape::Wave(OscKind::Sine)),
+    ("square", LfoShape::Wave(OscKind::Square)),
+    ("sawtooth", LfoShape::Wave(OscKind::Sawtooth)),
+    ("triangle", LfoShape::Wave(OscKind::Triangle)),
+    ("noise", LfoShape::Wave(OscKind::Noise)),
+    ("sample_and_hold", LfoShape::SampleAndHold),
+];
+
+const SOURCE_NAMES: [(&str, Source); 10] = [
+    ("lfo1", Source::Lfo(0)),
+    ("lfo2", Source::Lfo(1)),
+    ("amp_envelope", Source::AmpEnvelope),
+    ("mod_envelope", Source::ModEnvelope),
+    ("velocity", Source::Velocity),
+    ("key", Source::Key),
+    ("channel_pressure", Source::ChannelPressure),
+    ("aftertouch", Source::Aftertouch),
+    ("mod_wheel", Source::ModWheel),
+    ("pitch_bend", Source::PitchBend),
+];
+
+const DESTINATION_NAMES: [(&str, Destination); 6] = [
+    ("pitch", Destination::Pitch),
+    ("cutoff", Destination::Cutoff),
+    ("resonance", Destination::Resonance),
+    ("amp", Destination::Amp),
+    ("pan", Destination::Pan),
+    ("pulse_width", Destination::PulseWidth),
+];
+
+const FILTER_KIND_NAMES: [(&str, FilterKind); 7] = [
+    ("low_pass", FilterKind::LowPass),
+    ("high_pass", FilterKind::HighPass),
+    ("band_pass", FilterKind::BandPass),
+    ("notch", FilterKind::Notch),
+    ("peak", FilterKind::Peak),
+    ("low_shelf", FilterKind::LowShelf),
+    ("high_shelf", FilterKind::HighShelf),
+];
+
+const SHAPE_NAMES: [(&str, Shape); 4] = [
+    ("soft_clip", Shape::SoftClip),
+    ("hard_clip", Shape::HardClip),
+    ("foldback", Shape::Foldback),
+    ("tube", Shape::Tube),
+];
+
+const OVERSAMPLING_NAMES: [(&str, Oversampling); 4] = [
+    ("off", Oversampling::Off),
+    ("2x", Oversampling::X2),
+    ("4x", Oversampling::X4),
+    ("8x", Oversampling::X8),
+];
+
+/// `key = value` line, `Debug` keeps numbers exact and always writes a decimal point
+fn entry<V: Debug>(text: &mut String, key: &str, value: V) {
+    writeln!(text, "{} = {:?}", key, value).expect("writing to a String does not fail");
+}
+
+fn text_entry(text: &mut String, key: &str, value: &str) {
+    writeln!(text, "{} = {}", key, quote(value)).expect("writing to a String does not fail");
+}
+
+fn envelope_entries(text: &mut String, env: &EnvelopeParams) {
+    entry(text, "delay", env.delay);
+    entry(text, "attack", env.attack);
+    entry(text, "hold", env.hold);
+    entry(text, "decay", env.decay);
+    entry(text, "sustain", env.sustain);
+    entry(text, "release", env.release);
+    text_entry(text, "curve", name_of(&CURVE_NAMES, env.curve));
+    entry(text, "velocity_sensitivity", env.velocity_sensitivity);
+}
+
+fn name_of<T: PartialEq>(names: &[(&'static str, T)], value: T) -> &'static str {
+    names
+        .iter()
+        .find(|(_, v)| *v == value)
+        .map_or("", |(name, _)| name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Top,
+    Osc(usize),
+    Envelope,
+    Filter,
+    Lfo(usize),
+    ModEnvelope,
+    Distortion,
+    Bitcrusher,
+    /// index into `Patch::routes`
+    Route(usize),
+}
+
+impl Section {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "osc1" => Self::Osc(0),
+            "osc2" => Self::Osc(1),
+            "envelope" => Self::Envelope,
+            "filter" => Self::Filter,
+            "lfo1" => Self::Lfo(0),
+            "lfo2" => Self::Lfo(1),
+            "mod_envelope" => Self::ModEnvelope,
+            "distortion" => Self::Distortion,
+            "bitcrusher" => Self::Bitcrusher,
+            _ => return None,
+        })
+    }
+}
+
+fn apply_envelope(env: &mut EnvelopeParams, entry: &Entry) -> Result<bool, LineError> {
+    match entry.key {
+        "delay" => env.delay = entry.number(0.0..=MAX_STAGE)?,
+        "attack" => env.attack = entry.number(0.0..=MAX_STAGE)?,
+        "hold" => env.hold = entry.number(0.0..=MAX_STAGE)?,
+        "decay" => env.decay = entry.number(0.0..=MAX_STAGE)?,
+        "sustain" => env.sustain = entry.number(0.0..=1.0)?,
+        "release" => env.release = entry.number(0.0..=MAX_STAGE)?,
+        "curve" => env.curve = entry.choice(&CURVE_NAMES, CURVES)?,
+        "velocity_sensitivity" => env.velocity_sensitivity = entry.number(0.0..=1.0)?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Apply one entry to `patch`
+fn apply(patch: &mut Patch, section: Section, entry: &Entry) -> Result<(), LineError> {
+    let unknown = || LineError::UnknownKey {
+        section: match section {
+            Section::Top => "the top level".to_string(),
+            Section::Osc(index) => format!("[osc{}]", index + 1),
+            Section::Envelope => "[envelope]".to_string(),
+            Section::Filter => "[filter]".to_string(),
+            Section::Lfo(index) => format!("[lfo{}]", index + 1),
+            Section::ModEnvelope => "[mod_envelope]".to_string(),
+            Section::Distortion => "[distortion]".to_string(),
+            Section::Bitcrusher => "[bitcrusher]".to_string(),
+            Section::Route(_) => "[[route]]".to_string(),
+        },
+        key: entry.key.to_string(),
+    };
+    match section {
+        Section::Top => match entry.key {
+            "name" => patch.name = entry.text()?.to_string(),
+            "gain" => patch.gain = entry.number(0.0..=4.0)?,
+            "key_tracking" => patch.key_tracking = entry.number(0.0..=1.0)?,
+            "pitch" => patch.pitch = Some(entry.number(1.0..=20_000.0)?),
+            "one_shot" => patch.one_shot = entry.bool()?,
+            _ => return Err(unknown()),
+        },
+        Section::Osc(index) => {
+            let osc = &mut patch.oscillators[index];
+            match entry.key {
+                "kind" => osc.kind = entry.choice(&OSC_KIND_NAMES, OSC_KINDS)?,
+                "level" => osc.level = entry.number(0.0..=1.0)?,
+                "transpose" => osc.transpose = entry.number(-48.0..=48.0)?,
+                "pulse_width" => osc.pulse_width = entry.number(0.01..=0.99)?,
+                _ => return Err(unknown()),
+            }
+        }
+        Section::Envelope => {
+            if !apply_envelope(&mut patch.envelope, entry)? {
+                return Err(unknown());
+            }
+        }
+        Section::ModEnvelope => {
+            if !apply_envelope(&mut patch.mod_envelope, entry)? {
+                return Err(unknown());
+            }
+        }
+        Section::Filter => {
+            let filter = patch.filter.get_or_insert_with(FilterParams::default);
+            match entry.key {
+                "kind" => filter.kind = entry.choice(&FILTER_KIND_NAMES, FILTER_KINDS)?,
+                "cutoff" => filter.cutoff = entry.number(10.0..=20_000.0)?,
+                "q" => filter.q = entry.number(0.1..=40.0)?,
+                "gain_db" => filter.gain_db = entry.number(-48.0..=48.0)?,
+                _ => return Err(unknown()),
+            }
+        }
+        Section::Lfo(index) => {
+            let lfo = &mut patch.lfos[index];
+            match entry.key {
+                "shape" => lfo.shape = entry.choice(&LFO_SHAPE_NAMES, LFO_SHAPES)?,
+                "rate" => lfo.rate = LfoRate::Hz(entry.number(0.01..=100.0)?),
+                "beats" => lfo.rate = LfoRate::Beats(entry.number(1.0 / 64.0..=64.0)?),
+                "phase" => lfo.phase = entry.number(0.0..=1.0)?,
+                _ => return Err(unknown()),
+            }
+        }
+        Section::Distortion => {
+            let distortion = patch.distortion.get_or_insert_with(Default::default);
+            match entry.key {
+                "shape" => distortion.shape = entry.choice(&SHAPE_NAMES, SHAPES)?,
+                "drive" => distortion.drive = entry.number(-24.0..=48.0)?,
+                "output" => distortion.output = entry.number(-48.0..=24.0)?,
+                "mix" => distortion.mix = entry.number(0.0..=1.0)?,
+                "oversampling" => {
+                    distortion.oversampling = entry.choice(&OVERSAMPLING_NAMES, OVERSAMPLINGS)?
+                }
+                _ => return Err(unknown()),
+            }
+        }
+        Section::Bitcrusher => {
+            let bitcrusher = patch.bitcrusher.get_or_insert_with(Default::default);
+            match entry.key {
+                "bits" => bitcrusher.bits = entry.number(1.0..=24.0)?,
+                "rate" => bitcrusher.rate = entry.number(1.0..=192_000.0)?,
+                "mix" => bitcrusher.mix = entry.number(0.0..=1.0)?,
+                "oversampling" => {
+                    bitcrusher.oversampling = entry.choice(&OVERSAMPLING_NAMES, OVERSAMPLINGS)?
+                }
+                _ => return Err(unknown()),
+            }
+        }
+        Section::Route(index) => {
+            let route = &mut patch.routes[index];
+            match entry.key {
+                "source" => route.source = entry.choice(&SOURCE_NAMES, SOURCES)?,
+                "destination" => {
+                    route.destination = entry.choice(&DESTINATION_NAMES, DESTINATIONS)?
+                }
+                "depth" => route.depth = entry.number(-48.0..=48.0)?,
+                "via" => route.via = Some(entry.choice(&SOURCE_NAMES, SOURCES)?),
+                _ => return Err(unknown()),
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Patch {
+    /// Parse a patch file
+    ///
+    /// The format is a subset of [TOML](https://toml.io/en/v1.0.0): `key = value` lines
+    /// grouped in `[section]`s, `#` comments, quoted strings, numbers and booleans.
+    /// Missing keys keep the values of `Patch::default()`, a patch without `[filter]`
+    /// is not filtered. Every `[[route]]` adds a route of the modulation matrix, one
+    /// without a depth does nothing.
+    ///
+    /// ```toml
+    /// name = "Warm Pad"
+    /// gain = 1.0
+    /// key_tracking = 0.5          # [0, 1]
+    /// # pitch = 60.0              # fixed Hz, for drums
+    /// one_shot = false
+    ///
+    /// [osc1]                      # and [osc2]
+    /// kind = "sawtooth"           # sine, square, sawtooth, triangle, noise
+    /// level = 0.5                 # [0, 1]
+    /// transpose = -0.15           # semitones [-48, 48]
+    /// pulse_width = 0.5           # [0.01, 0.99]
+    ///
+    /// [envelope]                  # stages in seconds [0, 60]
+    /// delay = 0.0
+    /// attack = 0.6
+    /// hold = 0.0
+    /// decay = 1.0
+    /// sustain = 0.8               # [0, 1]
+    /// release = 1.2
+    /// curve = "exponential"       # linear, exponential
+    /// velocity_sensitivity = 1.0  # [0, 1]
+    ///
+    /// [filter]
+    /// kind = "low_pass"           # low_pass, high_pass, band_pass, notch, peak, low_shelf, high_shelf
+    /// cutoff = 1500.0             # Hz [10, 20000]
+    /// q = 1.0                     # [0.1, 40]
+    /// gain_db = 0.0               # [-48, 48]
+    ///
+    /// [lfo1]                      # and [lfo2]
+    /// shape = "triangle"          # sine, square, sawtooth, triangle, noise, sample_and_hold
+    /// rate = 5.0                  # Hz [0.01, 100]
+    /// # beats = 1.0               # quarter notes per cycle [1/64, 64], instead of rate
+    /// phase = 0.0                 # [0, 1]
+    ///
+    /// [mod_envelope]              # same keys as [envelope]
+    /// attack = 0.5
+    ///
+    /// [distortion]                # channel insert, off without the section
+    /// shape = "tube"              # soft_clip, hard_clip, foldback, tube
+    /// drive = 12.0                # dB [-24, 48]
+    /// output = -6.0               # dB [-48, 24]
+    /// mix = 1.0                   # [0, 1]
+    /// oversampling = "4x"         # off, 2x, 4x, 8x
+    ///
+    /// [bitcrusher]                # channel insert after the distortion
+    /// bits = 8.0                  # [1, 24]
+    /// rate = 11025.0              # Hz [1, 192000]
+    /// mix = 1.0                   # [0, 1]
+    /// oversampling = "2x"         # off, 2x, 4x, 8x
+    ///
+    /// [[route]]                   # up to 16
+    /// source = "lfo1"             # lfo1, lfo2, amp_envelope, mod_envelope, velocity, key,
+    ///                             # channel_pressure, aftertouch, mod_wheel, pitch_bend
+    /// destination = "pitch"       # pitch, cutoff, resonance, amp, pan, pulse_width
+    /// depth = 0.5                 # [-48, 48] semitones, octaves, gain, pan or duty cycle
+    /// via = "mod_wheel"           # optional source scaling the route
+    /// ```
+    pub fn from_text(text: &str) -> Result<Self, PatchError> {
+        let mut patch = Patch::default();
+        let mut section = Section::Top;
+        let mut sections = vec![section];
+        let mut keys = Vec::<(Section, &str)>::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |kind| PatchError::Line {
+                line: index + 1,
+                kind,
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("[[").and_then(|l| l.strip_suffix("]]")) {
+                if name.trim() != "route" {
+                    return Err(error(LineError::UnknownSection(format!(
+                        "[{}]",
+                        name.trim()
+                    ))));
+                }
+                if patch.routes.len() == MAX_ROUTES {
+                    return Err(error(LineError::TooManyRoutes));
+                }
+                section = Section::Route(patch.routes.len());
+                patch
+                    .routes
+                    .push(Route::new(Source::Lfo(0), Destination::Pitch, 0.0));
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                section = Section::parse(name)
+                    .ok_or_else(|| error(LineError::UnknownSection(name.to_string())))?;
+                if sections.contains(&section) {
+                    return Err(error(LineError::DuplicateSection(name.to_string())));
+                }
+                sections.push(section);
+                match section {
+                    Section::Filter => patch.filter = Some(FilterParams::default()),
+                    Section::Distortion => patch.distortion = Some(DistortionParams::default()),
+                    Section::Bitcrusher => patch.bitcrusher = Some(BitcrusherParams::default()),
+                    _ => {}
+                }
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(LineError::Syntax))?;
+            let key = key.trim();
+            let value = Value::parse(value.trim()).ok_or_else(|| error(LineError::Syntax))?;
+            if keys.contains(&(section, key)) {
+                return Err(error(LineError::DuplicateKey(key.to_string())));
+            }
+            let conflicts = match section {
+                Section::Lfo(_) => &LFO_CONFLICTS[..],
+                _ => &[],
+            };
+            for (first, second) in conflicts {
+                for (this, other) in [(first, second), (second, first)] {
+                    if key == *this && keys.contains(&(section, *other)) {
+                        return Err(error(LineError::Conflict(
+                            other.to_string(),
+                            this.to_string(),
+                        )));
+                    }
+                }
+            }
+            keys.push((section, key));
+            apply(&mut patch, section, &Entry { key, value }).map_err(error)?;
+        }
+        Ok(patch)
+    }
+
+    /// Format as a patch file that [`Patch::from_text`] reads back unchanged
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let out = &mut text;
+        text_entry(out, "name", &self.name);
+        entry(out, "gain", self.gain);
+        entry(out, "key_tracking", self.key_tracking);
+        if let Some(pitch) = self.pitch {
+            entry(out, "pitch", pitch);
+        }
+        entry(out, "one_shot", self.one_shot);
+        for (index, osc) in self.oscillators.iter().enumerate() {
+            out.push_str(&format!("\n[osc{}]\n", index + 1));
+            text_entry(out, "kind", name_of(&OSC_KIND_NAMES, osc.kind));
+            entry(out, "level", osc.level);
+            entry(out, "transpose", osc.transpose);
+            entry(out, "pulse_width", osc.pulse_width);
+        }
+        out.push_str("\n[envelope]\n");
+        envelope_entries(out, &self.envelope);
+        if let Some(filter) = self.filter {
+            out.push_str("\n[filter]\n");
+            text_entry(out, "kind", name_of(&FILTER_KIND_NAMES, filter.kind));
+            entry(out, "cutoff", filter.cutoff);
+            entry(out, "q", filter.q);
+            entry(out, "gain_db", filter.gain_db);
+        }
+        for (index, lfo) in self.lfos.iter().enumerate() {
+            if *lfo == LfoParams::default() {
+                continue;
+            }
+            out.push_str(&format!("\n[lfo{}]\n", index + 1));
+            text_entry(out, "shape", name_of(&LFO_SHAPE_NAMES, lfo.shape));
+            match lfo.rate {
+                LfoRate::Hz(hz) => entry(out, "rate", hz),
+                LfoRate::Beats(beats) => entry(out, "beats", beats),
+            }
+            entry(out, "phase", lfo.phase);
+        }
+        if self.mod_envelope != EnvelopeParams::default() {
+            out.push_str("\n[mod_envelope]\n");
+            envelope_entries(out, &self.mod_envelope);
+        }
+        if let Some(distortion) = self.distortion {
+            out.push_str("\n[distortion]\n");
+            text_entry(out, "shape", name_of(&SHAPE_NAMES, distortion.shape));
+            entry(out, "drive", distortion.drive);
+            entry(out, "output", distortion.output);
+            entry(out, "mix", distortion.mix);
+            text_entry(
+                out,
+                "oversampling",
+                name_of(&OVERSAMPLING_NAMES, distortion.oversampling),
+            );
+        }
+        if let Some(bitcrusher) = self.bitcrusher {
+            out.push_str("\n[bitcrusher]\n");
+            entry(out, "bits", bitcrusher.bits);
+            entry(out, "rate", bitcrusher.rate);
+            entry(out, "mix", bitcrusher.mix);
+            text_entry(
+                out,
+                "oversampling",
+                name_of(&OVERSAMPLING_NAMES, bitcrusher.oversampling),
+            );
+        }
+        for route in &self.routes {
+            out.push_str("\n[[route]]\n");
+            text_entry(out, "source", name_of(&SOURCE_NAMES, route.source));
+            text_entry(
+                out,
+                "destination",
+                name_of(&DESTINATION_NAMES, route.destination),
+            );
+            entry(out, "depth", route.depth);
+            if let Some(via) = route.via {
+                text_entry(out, "via", name_of(&SOURCE_NAMES, via));
+            }
+        }
+        text
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, PatchError> {
+        Self::from_text(&fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), StdIoError> {
+        fs::write(path, self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preset::gm;
+
+    fn line_error(text: &str) -> (usize, LineError) {
+        match Patch::from_text(text) {
+            Err(PatchError::Line { line, kind }) => (line, kind),
+            other => panic!("expected a line error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for program in [0, 30, 38, 88, 119] {
+            let patch = gm::program(program);
+            assert_eq!(Patch::from_text(&patch.to_text()).unwrap(), patch);
+        }
+        let mut patch = gm::program(81);
+        patch.lfos[1] = LfoParams {
+            shape: LfoShape::SampleAndHold,
+            rate: LfoRate::Beats(0.25),
+            phase: 0.5,
+        };
+        patch.mod_envelope.attack = 2.0;
+        patch.bitcrusher = Some(BitcrusherParams {
+            bits: 4.5,
+            oversampling: Oversampling::Off,
+            ..BitcrusherParams::default()
+        });
+        patch.routes.extend([
+            Route::new(Source::Lfo(1), Destination::Cutoff, -1.5),
+            Route::new(Source::ModEnvelope, Destination::PulseWidth, 0.25).via(Source::Velocity),
+        ]);
+        assert_eq!(Patch::from_text(&patch.to_text()).unwrap(), patch);
+        let mut drum = gm::drum(42).unwrap();
+        drum.name = "odd \"name\" \\ # not a comment".to_string();
+        assert_eq!(Patch::from_text(&drum.to_text()).unwrap(), drum);
+    }
+
+    #[test]
+    fn missing_keys_keep_defaults() {
+        let text = r#"
+            # only the first oscillator
+            name = "Lead"   # trailing comment
+            [osc1]
+            kind = "square"
+            pulse_width = 0.25
+        "#;
+        let patch = Patch::from_text(text).unwrap();
+        assert_eq!(patch.name, "Lead");
+        assert_eq!(patch.oscillators[0].kind, OscKind::Square);
+        assert_eq!(patch.oscillators[0].level, 1.0);
+        assert_eq!(patch.envelope, EnvelopeParams::default());
+        assert_eq!(patch.filter, None);
+        // an empty filter section turns the default filter on
+        let patch = Patch::from_text("[filter]").unwrap();
+        assert_eq!(patch.filter, Some(FilterParams::default()));
+        assert_eq!(patch.distortion, None);
+        // so do the inserts
+        let patch = Patch::from_text("[distortion]\nshape = \"foldback\"\n[bitcrusher]").unwrap();
+        assert_eq!(patch.distortion.unwrap().shape, Shape::Foldback);
+        assert_eq!(patch.bitcrusher, Some(BitcrusherParams::default()));
+    }
+
+    #[test]
+    fn modulation_sections() {
+        let text = r#"
+            [lfo1]
+            shape = "sample_and_hold"
+            beats = 0.5
+            [[route]]
+            source = "mod_wheel"
+            destination = "cutoff"
+            depth = 2.0
+            [[route]]
+            source = "lfo1"
+            destination = "pan"
+            depth = 1
+            via = "channel_pressure"
+        "#;
+        let patch = Patch::from_text(text).unwrap();
+        assert_eq!(patch.lfos[0].shape, LfoShape::SampleAndHold);
+        assert_eq!(patch.lfos[0].rate, LfoRate::Beats(0.5));
+        assert_eq!(patch.lfos[1], LfoParams::default());
+        assert_eq!(
+            patch.routes,
+            vec![
+                Route::new(Source::ModWheel, Destination::Cutoff, 2.0),
+                Route::new(Source::Lfo(0), Destination::Pan, 1.0).via(Source::ChannelPressure),
+            ]
+        );
+        assert_eq!(
+            line_error("[lfo2]\nrate = 2\nbeats = 1"),
+            (
+                3,
+                LineError::Conflict("rate".to_string(), "beats".to_string())
+            )
+        );
+        assert_eq!(
+            line_error("[lfo1]\nbeats = 1\n\nrate = 2"),
+            (
+                4,
+                LineError::Conflict("beats".to_string(), "rate".to_string())
+            )
+        );
+        // each lfo has its own rate, other sections have no `beats`
+        assert!(Patch::from_text("[lfo1]\nrate = 2\n[lfo2]\nbeats = 1").is_ok());
+        assert!(matches!(
+            line_error("[bitcrusher]\nrate = 8000\nbeats = 1"),
+            (3, LineError::UnknownKey { .. })
+        ));
+        assert_eq!(
+            line_error("[[route]]\nsource = \"lfo3\"").1.to_string(),
+            format!("`source` = \"lfo3\" is not one of {}", SOURCES)
+        );
+        assert_eq!(
+            line_error("[[lfo]]").1,
+            LineError::UnknownSection("[lfo]".to_string())
+        );
+        let routes = "[[route]]\n".repeat(MAX_ROUTES + 1);
+        assert_eq!(
+            line_error(&routes),
+            (MAX_ROUTES + 1, LineError::TooManyRoutes)
+        );
+    }
+
+    #[test]
+    fn friendly_errors() {
+        assert_eq!(
+            line_error("[envelope]\nattack = -1"),
+            (
+                2,
+                LineError::Range {
+                    key: "attack".to_string(),
+                    value: -1.0,
+                    min: 0.0,
+                    max: 60.0
+                }
+            )
+        );
+        let (line, error) = line_error("\n\n[osc2]\nkind = \"saw\"");
+        assert_eq!(line, 4);
+        assert_eq!(
+            error.to_string(),
+            "`kind` = \"saw\" is not one of sine, square, sawtooth, triangle, noise"
+        );
+        assert_eq!(
+            line_error("[lfo]").1,
+            LineError::UnknownSection("lfo".to_string())
+        );
+        assert!(matches!(
+            line_error("[filter]\nresonance = 2").1,
+            LineError::UnknownKey { .. }
+        ));
+        assert_eq!(
+            line_error("gain = 1\ngain = 2"),
+            (2, LineError::DuplicateKey("gain".to_string()))
+        );
+        assert!(matches!(
+            line_error("one_shot = 1").1,
+            LineError::Type { .. }
+        ));
+        assert_eq!(line_error("name = \"open").1, LineError::Syntax);
+        assert_eq!(line_error("just words").1, LineError::Syntax);
+        assert_eq!(
+            line_error("[osc1]\n[osc1]"),
+            (2, LineError::DuplicateSection("osc1".to_string()))
+        );
+        let error = Patch::from_text("[filter]\nq = 100").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: `q` = 100 is out of range, expected 0.1 to 40"
+        );
+    }
+}
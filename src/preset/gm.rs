@@ -0,0 +1,849 @@
+use std::ops::RangeInclusive;
+
+use super::{OscParams, Patch};
+use crate::{
+    envelope::{Curve, EnvelopeParams},
+    filter::{FilterKind, FilterParams},
+    modulation::{Destination, Route, Source},
+    osc::OscKind::{self, *},
+};
+
+/// Channel 10, the GM percussion channel
+pub const DRUM_CHANNEL: u8 = 9;
+
+/// Keys of the GM percussion map
+pub const DRUM_KEYS: RangeInclusive<u8> = 35..=81;
+
+/// Kit programs on the drum channel, like the GS standard and TR-808 kits
+pub const STANDARD_KIT: u8 = 0;
+pub const ANALOG_KIT: u8 = 25;
+
+/// Bank MSB 8, a detuned variation of every program like the GS variation tones
+pub const VARIATION_BANK: u16 = 8 << 7;
+
+/// Bank MSBs that turn a channel into a drum channel, GM2 and XG rhythm banks
+pub const DRUM_BANK_MSBS: [u8; 2] = [120, 127];
+
+/// Bank MSB that turns the drum channel into a melodic one, the GM2 melody bank
+pub const MELODY_BANK_MSB: u8 = 121;
+
+/// Semitones of vibrato with the mod wheel all the way up
+pub const VIBRATO_DEPTH: f32 = 0.5;
+
+pub const PROGRAM_NAMES: [&str; 128] = [
+    // piano
+    "Acoustic Grand Piano",
+    "Bright Acoustic Piano",
+    "Electric Grand Piano",
+    "Honky-tonk Piano",
+    "Electric Piano 1",
+    "Electric Piano 2",
+    "Harpsichord",
+    "Clavinet",
+    // chromatic percussion
+    "Celesta",
+    "Glockenspiel",
+    "Music Box",
+    "Vibraphone",
+    "Marimba",
+    "Xylophone",
+    "Tubular Bells",
+    "Dulcimer",
+    // organ
+    "Drawbar Organ",
+    "Percussive Organ",
+    "Rock Organ",
+    "Church Organ",
+    "Reed Organ",
+    "Accordion",
+    "Harmonica",
+    "Tango Accordion",
+    // guitar
+    "Acoustic Guitar (nylon)",
+    "Acoustic Guitar (steel)",
+    "Electric Guitar (jazz)",
+    "Electric Guitar (clean)",
+    "Electric Guitar (muted)",
+    "Overdriven Guitar",
+    "Distortion Guitar",
+    "Guitar Harmonics",
+    // bass
+    "Acoustic Bass",
+    "Electric Bass (finger)",
+    "Electric Bass (pick)",
+    "Fretless Bass",
+    "Slap Bass 1",
+    "Slap Bass 2",
+    "Synth Bass 1",
+    "Synth Bass 2",
+    // strings
+    "Violin",
+    "Viola",
+    "Cello",
+    "Contrabass",
+    "Tremolo Strings",
+    "Pizzicato Strings",
+    "Orchestral Harp",
+    "Timpani",
+    // ensemble
+    "String Ensemble 1",
+    "String Ensemble 2",
+    "Synth Strings 1",
+    "Synth Strings 2",
+    "Choir Aahs",
+    "Voice Oohs",
+    "Synth Voice",
+    "Orchestra Hit",
+    // brass
+    "Trumpet",
+    "Trombone",
+    "Tuba",
+    "Muted Trumpet",
+    "French Horn",
+    "Brass Section",
+    "Synth Brass 1",
+    "Synth Brass 2",
+    // reed
+    "Soprano Sax",
+    "Alto Sax",
+    "Tenor Sax",
+    "Baritone Sax",
+    "Oboe",
+    "English Horn",
+    "Bassoon",
+    "Clarinet",
+    // pipe
+    "Piccolo",
+    "Flute",
+    "Recorder",
+    "Pan Flute",
+    "Blown Bottle",
+    "Shakuhachi",
+    "Whistle",
+    "Ocarina",
+    // synth lead
+    "Lead 1 (square)",
+    "Lead 2 (sawtooth)",
+    "Lead 3 (calliope)",
+    "Lead 4 (chiff)",
+    "Lead 5 (charang)",
+    "Lead 6 (voice)",
+    "Lead 7 (fifths)",
+    "Lead 8 (bass + lead)",
+    // synth pad
+    "Pad 1 (new age)",
+    "Pad 2 (warm)",
+    "Pad 3 (polysynth)",
+    "Pad 4 (choir)",
+    "Pad 5 (bowed)",
+    "Pad 6 (metallic)",
+    "Pad 7 (halo)",
+    "Pad 8 (sweep)",
+    // synth effects
+    "FX 1 (rain)",
+    "FX 2 (soundtrack)",
+    "FX 3 (crystal)",
+    "FX 4 (atmosphere)",
+    "FX 5 (brightness)",
+    "FX 6 (goblins)",
+    "FX 7 (echoes)",
+    "FX 8 (sci-fi)",
+    // ethnic
+    "Sitar",
+    "Banjo",
+    "Shamisen",
+    "Koto",
+    "Kalimba",
+    "Bag pipe",
+    "Fiddle",
+    "Shanai",
+    // percussive
+    "Tinkle Bell",
+    "Agogo",
+    "Steel Drums",
+    "Woodblock",
+    "Taiko Drum",
+    "Melodic Tom",
+    "Synth Drum",
+    "Reverse Cymbal",
+    // sound effects
+    "Guitar Fret Noise",
+    "Breath Noise",
+    "Seashore",
+    "Bird Tweet",
+    "Telephone Ring",
+    "Helicopter",
+    "Applause",
+    "Gunshot",
+];
+
+fn osc(kind: OscKind, level: f32, transpose: f32) -> OscParams {
+    OscParams {
+        kind,
+        level,
+        transpose,
+        pulse_width: 0.5,
+    }
+}
+
+fn pulse(level: f32, transpose: f32, pulse_width: f32) -> OscParams {
+    OscParams {
+        pulse_width,
+        ..osc(Square, level, transpose)
+    }
+}
+
+fn off() -> OscParams {
+    OscParams::default()
+}
+
+/// Attack, decay, sustain level and release
+fn env(attack: f32, decay: f32, sustain: f32, release: f32) -> EnvelopeParams {
+    EnvelopeParams {
+        attack,
+        decay,
+        sustain,
+        release,
+        ..EnvelopeParams::default()
+    }
+}
+
+fn filter(kind: FilterKind, cutoff: f32, q: f32) -> Option<FilterParams> {
+    Some(FilterParams {
+        kind,
+        cutoff,
+        q,
+        gain_db: 0.0,
+    })
+}
+
+fn low_pass(cutoff: f32, q: f32) -> Option<FilterParams> {
+    filter(FilterKind::LowPass, cutoff, q)
+}
+
+/// Cutoffs of keyed patches are set for middle C and follow the key halfway,
+/// the mod wheel brings in vibrato from the first LFO
+fn patch(
+    oscillators: [OscParams; 2],
+    envelope: EnvelopeParams,
+    filter: Option<FilterParams>,
+) -> Patch {
+    Patch {
+        oscillators,
+        envelope,
+        filter,
+        key_tracking: 0.5,
+        routes: vec![
+            Route::new(Source::Lfo(0), Destination::Pitch, VIBRATO_DEPTH).via(Source::ModWheel),
+        ],
+        ..Patch::default()
+    }
+}
+
+/// Decaying sound such as a piano or a plucked string
+fn plucked(oscillators: [OscParams; 2], decay: f32, cutoff: f32) -> Patch {
+    patch(
+        oscillators,
+        env(0.002, decay, 0.0, 0.3),
+        low_pass(cutoff, 0.7),
+    )
+}
+
+/// Held sound such as an organ, bowed or blown instrument
+fn sustained(oscillators: [OscParams; 2], attack: f32, cutoff: f32) -> Patch {
+    patch(
+        oscillators,
+        env(attack, 0.2, 0.8, attack.max(0.1)),
+        low_pass(cutoff, 0.7),
+    )
+}
+
+/// Patch approximating the GM `program`, 0 is the acoustic grand piano
+pub fn program(program: u8) -> Patch {
+    let program = program & 0x7F;
+    let mut patch = match program {
+        0 => plucked(
+            [osc(Sawtooth, 0.6, 0.0), osc(Triangle, 0.6, 0.0)],
+            1.8,
+            2500.0,
+        ),
+        1 => plucked(
+            [osc(Sawtooth, 0.7, 0.0), osc(Triangle, 0.5, 12.0)],
+            1.6,
+            4000.0,
+        ),
+        2 => plucked(
+            [osc(Sawtooth, 0.6, 0.0), osc(Square, 0.3, 0.0)],
+            1.6,
+            3000.0,
+        ),
+        3 => plucked(
+            [osc(Sawtooth, 0.6, -0.15), osc(Sawtooth, 0.6, 0.15)],
+            1.5,
+            2800.0,
+        ),
+        4 => plucked([osc(Sine, 0.9, 0.0), osc(Triangle, 0.3, 12.0)], 2.0, 5000.0),
+        5 => plucked([osc(Sine, 0.8, 0.0), osc(Sine, 0.3, 19.0)], 1.5, 6000.0),
+        6 => plucked(
+            [pulse(0.6, 0.0, 0.3), osc(Sawtooth, 0.3, 12.0)],
+            1.0,
+            6000.0,
+        ),
+        7 => plucked([pulse(0.7, 0.0, 0.2), off()], 0.8, 3500.0),
+        // bells and mallets
+        8 => plucked([osc(Sine, 0.8, 12.0), osc(Sine, 0.3, 24.0)], 1.2, 8000.0),
+        9 => plucked([osc(Sine, 0.7, 24.0), osc(Sine, 0.3, 31.0)], 1.5, 10_000.0),
+        10 => plucked(
+            [osc(Sine, 0.7, 12.0), osc(Triangle, 0.3, 24.0)],
+            0.9,
+            8000.0,
+        ),
+        11 => plucked([osc(Sine, 0.9, 0.0), osc(Sine, 0.2, 24.0)], 2.5, 6000.0),
+        12 => plucked([osc(Sine, 0.9, 0.0), osc(Triangle, 0.3, 12.0)], 0.4, 3000.0),
+        13 => plucked(
+            [osc(Triangle, 0.8, 12.0), osc(Sine, 0.3, 24.0)],
+            0.25,
+            6000.0,
+        ),
+        14 => plucked([osc(Sine, 0.7, 0.0), osc(Sine, 0.4, 14.0)], 3.5, 6000.0),
+        15 => plucked(
+            [osc(Sawtooth, 0.5, -0.1), osc(Sawtooth, 0.5, 0.1)],
+            1.0,
+            4000.0,
+        ),
+        // organs
+        16 => sustained([osc(Sine, 0.7, 0.0), osc(Sine, 0.5, 12.0)], 0.01, 8000.0),
+        17 => patch(
+            [osc(Sine, 0.7, 0.0), osc(Sine, 0.5, 19.0)],
+            env(0.005, 0.3, 0.6, 0.05),
+            None,
+        ),
+        18 => sustained([pulse(0.5, 0.0, 0.5), osc(Sine, 0.5, 12.0)], 0.01, 4000.0),
+        19 => sustained(
+            [osc(Sine, 0.6, -12.0), osc(Triangle, 0.6, 0.0)],
+            0.08,
+            5000.0,
+        ),
+        20 => sustained([pulse(0.5, 0.0, 0.4), off()], 0.05, 2000.0),
+        21 | 23 => sustained(
+            [osc(Sawtooth, 0.5, -0.1), osc(Sawtooth, 0.5, 0.1)],
+            0.05,
+            2500.0,
+        ),
+        22 => sustained([pulse(0.7, 0.0, 0.25), off()], 0.04, 2500.0),
+        // guitars
+        24 => plucked(
+            [osc(Triangle, 0.8, 0.0), osc(Sawtooth, 0.2, 0.0)],
+            1.2,
+            2500.0,
+        ),
+        25 => plucked(
+            [osc(Sawtooth, 0.7, 0.0), osc(Sawtooth, 0.2, 12.0)],
+            1.4,
+            3500.0,
+        ),
+        26 => plucked([osc(Triangle, 0.7, 0.0), osc(Sine, 0.4, 0.0)], 1.5, 1800.0),
+        27 => plucked([osc(Sawtooth, 0.6, 0.0), pulse(0.3, 0.0, 0.3)], 1.5, 3000.0),
+        28 => plucked([osc(Sawtooth, 0.7, 0.0), off()], 0.2, 1500.0),
+        29 => sustained(
+            [pulse(0.6, 0.0, 0.5), osc(Sawtooth, 0.4, 0.0)],
+            0.005,
+            3000.0,
+        ),
+        30 => sustained(
+            [osc(Sawtooth, 0.6, -0.1), pulse(0.6, 0.1, 0.5)],
+            0.005,
+            3500.0,
+        ),
+        31 => plucked([osc(Sine, 0.8, 12.0), osc(Sine, 0.2, 24.0)], 1.5, 8000.0),
+        // basses
+        32 => plucked([osc(Triangle, 0.9, 0.0), osc(Sine, 0.4, 0.0)], 1.0, 1200.0),
+        33 | 35 => plucked([osc(Sawtooth, 0.6, 0.0), osc(Sine, 0.6, 0.0)], 1.2, 900.0),
+        34 => plucked(
+            [osc(Sawtooth, 0.7, 0.0), osc(Square, 0.3, 0.0)],
+            0.9,
+            1500.0,
+        ),
+        36 | 37 => plucked(
+            [osc(Sawtooth, 0.6, 0.0), pulse(0.4, 12.0, 0.3)],
+            0.6,
+            2500.0,
+        ),
+        38 => patch(
+            [osc(Sawtooth, 0.8, 0.0), off()],
+            env(0.002, 0.4, 0.3, 0.1),
+            low_pass(700.0, 3.0),
+        ),
+        39 => patch(
+            [pulse(0.7, 0.0, 0.3), osc(Sawtooth, 0.3, -12.0)],
+            env(0.002, 0.3, 0.4, 0.1),
+            low_pass(900.0, 2.5),
+        ),
+        // strings
+        40..=44 => sustained(
+            [osc(Sawtooth, 0.5, -0.08), osc(Sawtooth, 0.5, 0.08)],
+            0.12,
+            3000.0,
+        ),
+        45 => plucked(
+            [osc(Sawtooth, 0.6, 0.0), osc(Triangle, 0.4, 0.0)],
+            0.3,
+            2000.0,
+        ),
+        46 => plucked([osc(Triangle, 0.8, 0.0), osc(Sine, 0.3, 12.0)], 2.0, 4000.0),
+        47 => plucked([osc(Sine, 0.9, 0.0), osc(Triangle, 0.3, 0.0)], 1.2, 800.0),
+        // ensembles and voices
+        48 | 49 => sustained(
+            [osc(Sawtooth, 0.5, -0.12), osc(Sawtooth, 0.5, 0.12)],
+            0.25,
+            2500.0,
+        ),
+        50 | 51 => sustained(
+            [osc(Sawtooth, 0.5, -0.2), pulse(0.5, 0.2, 0.4)],
+            0.3,
+            3500.0,
+        ),
+        52..=54 => sustained(
+            [osc(Triangle, 0.6, -0.1), osc(Sine, 0.6, 0.1)],
+            0.25,
+            1500.0,
+        ),
+        55 => plucked(
+            [osc(Sawtooth, 0.6, 0.0), osc(Square, 0.5, 7.0)],
+            0.35,
+            5000.0,
+        ),
+        // brass
+        56 | 59 => sustained([osc(Sawtooth, 0.8, 0.0), off()], 0.04, 2200.0),
+        57 | 58 => sustained(
+            [osc(Sawtooth, 0.7, 0.0), osc(Sine, 0.4, -12.0)],
+            0.06,
+            1400.0,
+        ),
+        60 => sustained([osc(Sawtooth, 0.5, 0.0), osc(Sine, 0.6, 0.0)], 0.08, 1200.0),
+        61 => sustained(
+            [osc(Sawtooth, 0.6, -0.1), osc(Sawtooth, 0.6, 0.1)],
+            0.05,
+            2500.0,
+        ),
+        62 | 63 => patch(
+            [osc(Sawtooth, 0.6, -0.1), osc(Sawtooth, 0.6, 0.1)],
+            env(0.02, 0.4, 0.6, 0.2),
+            low_pass(1800.0, 2.0),
+        ),
+        // reeds
+        64..=67 => sustained(
+            [pulse(0.5, 0.0, 0.35), osc(Sawtooth, 0.4, 0.0)],
+            0.03,
+            2200.0,
+        ),
+        68 | 69 => sustained([pulse(0.7, 0.0, 0.2), off()], 0.04, 2500.0),
+        70 => sustained([pulse(0.6, 0.0, 0.3), off()], 0.04, 1200.0),
+        71 => sustained([pulse(0.7, 0.0, 0.5), off()], 0.04, 2000.0),
+        // pipes
+        72..=79 => {
+            let mut patch = sustained([osc(Sine, 0.9, 0.0), osc(Noise, 0.04, 0.0)], 0.06, 5000.0);
+            if program == 72 {
+                patch.oscillators[0].transpose = 12.0;
+            }
+            patch
+        }
+        // synth leads
+        80 => sustained([pulse(0.6, 0.0, 0.5), pulse(0.3, 0.1, 0.5)], 0.005, 6000.0),
+        81 => sustained(
+            [osc(Sawtooth, 0.6, 0.0), osc(Sawtooth, 0.3, 0.1)],
+            0.005,
+            5000.0,
+        ),
+        82 => sustained(
+            [osc(Triangle, 0.7, 0.0), osc(Sine, 0.4, 12.0)],
+            0.02,
+            5000.0,
+        ),
+        83 => plucked([pulse(0.6, 0.0, 0.3), osc(Noise, 0.1, 0.0)], 0.8, 4000.0),
+        84 => sustained(
+            [osc(Sawtooth, 0.5, 0.0), pulse(0.5, 0.0, 0.3)],
+            0.005,
+            3000.0,
+        ),
+        85 => sustained([osc(Sawtooth, 0.5, 0.0), osc(Sine, 0.5, 0.0)], 0.1, 1500.0),
+        86 => sustained(
+            [osc(Sawtooth, 0.5, 0.0), osc(Sawtooth, 0.5, 7.0)],
+            0.005,
+            4000.0,
+        ),
+        87 => sustained(
+            [osc(Sawtooth, 0.6, 0.0), osc(Sawtooth, 0.5, -12.0)],
+            0.005,
+            3000.0,
+        ),
+        // pads
+        88..=95 => {
+            let mut patch = patch(
+                [osc(Sawtooth, 0.5, -0.15), osc(Triangle, 0.5, 0.15)],
+                env(0.6, 1.0, 0.8, 1.2),
+                low_pass(1500.0, 1.0),
+            );
+            match program {
+                89 => patch.oscillators[1].kind = Sawtooth,
+                91 => patch.oscillators[0].kind = Triangle,
+                93 => patch.oscillators[1] = pulse(0.4, 12.1, 0.2),
+                94 => patch.oscillators = [osc(Sine, 0.6, -0.1), osc(Sine, 0.4, 12.1)],
+                _ => {}
+            }
+            patch
+        }
+        // synth effects
+        96 => plucked([osc(Sine, 0.6, 12.0), osc(Noise, 0.2, 0.0)], 0.6, 6000.0),
+        97 => sustained([osc(Sawtooth, 0.5, -0.2), osc(Sine, 0.5, 7.0)], 0.5, 2000.0),
+        98 => plucked(
+            [osc(Sine, 0.7, 24.0), osc(Triangle, 0.4, 12.0)],
+            2.0,
+            10_000.0,
+        ),
+        99 => sustained(
+            [osc(Triangle, 0.5, 0.0), osc(Sawtooth, 0.3, 0.1)],
+            0.3,
+            2500.0,
+        ),
+        100 => sustained([osc(Sine, 0.6, 12.0), osc(Sawtooth, 0.3, 0.0)], 0.2, 8000.0),
+        101 => sustained(
+            [osc(Sawtooth, 0.5, -0.3), osc(Square, 0.5, 6.0)],
+            0.8,
+            1500.0,
+        ),
+        102 => plucked([osc(Sine, 0.6, 0.0), osc(Triangle, 0.4, 12.0)], 2.5, 3000.0),
+        103 => sustained([osc(Square, 0.5, 0.0), osc(Sine, 0.5, 18.0)], 0.3, 4000.0),
+        // ethnic
+        104 => plucked(
+            [osc(Sawtooth, 0.6, 0.0), osc(Sawtooth, 0.3, 12.05)],
+            2.0,
+            5000.0,
+        ),
+        105 => plucked([pulse(0.6, 0.0, 0.3), osc(Sawtooth, 0.3, 0.0)], 0.5, 5000.0),
+        106 => plucked([pulse(0.6, 0.0, 0.2), off()], 0.6, 4000.0),
+        107 => plucked(
+            [osc(Triangle, 0.7, 0.0), osc(Sawtooth, 0.3, 0.0)],
+            1.2,
+            4000.0,
+        ),
+        108 => plucked([osc(Sine, 0.8, 0.0), osc(Sine, 0.3, 24.0)], 0.6, 5000.0),
+        109 => sustained(
+            [osc(Sawtooth, 0.5, 0.0), osc(Sawtooth, 0.4, -24.0)],
+            0.03,
+            3000.0,
+        ),
+        110 => sustained([osc(Sawtooth, 0.8, 0.0), off()], 0.08, 3500.0),
+        111 => sustained([pulse(0.7, 0.0, 0.25), off()], 0.03, 3000.0),
+        // percussive
+        112 => plucked([osc(Sine, 0.7, 24.0), osc(Sine, 0.3, 36.0)], 1.0, 10_000.0),
+        113 => plucked([osc(Square, 0.6, 12.0), osc(Sine, 0.3, 19.0)], 0.3, 6000.0),
+        114 => plucked([osc(Sine, 0.7, 0.0), osc(Sine, 0.4, 12.3)], 0.8, 6000.0),
+        115 => plucked([osc(Triangle, 0.8, 12.0), off()], 0.08, 4000.0),
+        116 => plucked([osc(Sine, 0.9, -12.0), osc(Noise, 0.2, 0.0)], 0.6, 600.0),
+        117 | 118 => plucked([osc(Sine, 0.9, 0.0), osc(Triangle, 0.3, 0.0)], 0.4, 2000.0),
+        119 => patch(
+            [osc(Noise, 0.7, 0.0), off()],
+            env(1.5, 0.01, 0.0, 0.02),
+            filter(FilterKind::HighPass, 4000.0, 0.7),
+        ),
+        // sound effects
+        120 => plucked(
+            [osc(Noise, 0.5, 0.0), osc(Sawtooth, 0.3, 0.0)],
+            0.15,
+            3000.0,
+        ),
+        121 => patch(
+            [osc(Noise, 0.8, 0.0), off()],
+            env(0.15, 0.2, 0.6, 0.3),
+            filter(FilterKind::BandPass, 1500.0, 1.0),
+        ),
+        122 => patch(
+            [osc(Noise, 0.8, 0.0), off()],
+            env(2.0, 1.0, 0.5, 2.0),
+            low_pass(1200.0, 0.7),
+        ),
+        123 => sustained([osc(Sine, 0.7, 36.0), osc(Sine, 0.3, 43.0)], 0.02, 10_000.0),
+        124 => sustained([pulse(0.6, 24.0, 0.5), off()], 0.005, 4000.0),
+        125 => patch(
+            [osc(Noise, 0.8, 0.0), pulse(0.3, -24.0, 0.1)],
+            env(0.3, 0.2, 0.8, 0.5),
+            low_pass(800.0, 2.0),
+        ),
+        126 => patch(
+            [osc(Noise, 0.8, 0.0), off()],
+            env(0.5, 0.5, 0.7, 1.0),
+            filter(FilterKind::BandPass, 2500.0, 0.5),
+        ),
+        _ => patch(
+            [osc(Noise, 1.0, 0.0), off()],
+            env(0.001, 0.4, 0.0, 0.2),
+            low_pass(2500.0, 0.7),
+        ),
+    };
+    patch.name = PROGRAM_NAMES[usize::from(program)].to_string();
+    patch
+}
+
+/// `program` with a detuned second oscillator and a brighter filter
+pub fn variation(program: u8) -> Patch {
+    let mut patch = self::program(program);
+    let [first, second] = &mut patch.oscillators;
+    if second.level == 0.0 {
+        *second = *first;
+    }
+    first.transpose -= 0.1;
+    second.transpose += 0.1;
+    if let Some(filter) = patch.filter.as_mut() {
+        filter.cutoff *= 1.5;
+    }
+    patch.name.push_str(" Var.");
+    patch
+}
+
+/// Name of a key of the GM percussion map
+pub fn drum_name(key: u8) -> Option<&'static str> {
+    Some(match key {
+        35 => "Acoustic Bass Drum",
+        36 => "Bass Drum 1",
+        37 => "Side Stick",
+        38 => "Acoustic Snare",
+        39 => "Hand Clap",
+        40 => "Electric Snare",
+        41 => "Low Floor Tom",
+        42 => "Closed Hi-Hat",
+        43 => "High Floor Tom",
+        44 => "Pedal Hi-Hat",
+        45 => "Low Tom",
+        46 => "Open Hi-Hat",
+        47 => "Low-Mid Tom",
+        48 => "Hi-Mid Tom",
+        49 => "Crash Cymbal 1",
+        50 => "High Tom",
+        51 => "Ride Cymbal 1",
+        52 => "Chinese Cymbal",
+        53 => "Ride Bell",
+        54 => "Tambourine",
+        55 => "Splash Cymbal",
+        56 => "Cowbell",
+        57 => "Crash Cymbal 2",
+        58 => "Vibraslap",
+        59 => "Ride Cymbal 2",
+        60 => "Hi Bongo",
+        61 => "Low Bongo",
+        62 => "Mute Hi Conga",
+        63 => "Open Hi Conga",
+        64 => "Low Conga",
+        65 => "High Timbale",
+        66 => "Low Timbale",
+        67 => "High Agogo",
+        68 => "Low Agogo",
+        69 => "Cabasa",
+        70 => "Maracas",
+        71 => "Short Whistle",
+        72 => "Long Whistle",
+        73 => "Short Guiro",
+        74 => "Long Guiro",
+        75 => "Claves",
+        76 => "Hi Wood Block",
+        77 => "Low Wood Block",
+        78 => "Mute Cuica",
+        79 => "Open Cuica",
+        80 => "Mute Triangle",
+        81 => "Open Triangle",
+        _ => return None,
+    })
+}
+
+/// Tone at `hz` mixed with `noise`, decaying in `decay` seconds
+fn hit(tone: OscKind, hz: f32, noise: f32, decay: f32, filter: Option<FilterParams>) -> Patch {
+    Patch {
+        oscillators: [osc(tone, 1.0 - noise, 0.0), osc(Noise, noise, 0.0)],
+        envelope: EnvelopeParams {
+            attack: 0.001,
+            decay,
+            sustain: 0.0,
+            release: decay,
+            curve: Curve::Exponential,
+            ..EnvelopeParams::default()
+        },
+        filter,
+        pitch: Some(hz),
+        one_shot: true,
+        ..Patch::default()
+    }
+}
+
+fn cymbal(cutoff: f32, decay: f32) -> Patch {
+    hit(
+        Square,
+        cutoff * 0.07,
+        0.85,
+        decay,
+        filter(FilterKind::HighPass, cutoff, 0.7),
+    )
+}
+
+/// Sound of `key` in the standard kit
+pub fn drum(key: u8) -> Option<Patch> {
+    let band = |cutoff, q| filter(FilterKind::BandPass, cutoff, q);
+    let mut patch = match key {
+        35 => hit(Sine, 50.0, 0.05, 0.3, low_pass(400.0, 0.7)),
+        36 => hit(Sine, 60.0, 0.08, 0.25, low_pass(600.0, 0.7)),
+        37 => hit(Square, 420.0, 0.3, 0.05, band(1500.0, 1.0)),
+        38 => hit(Triangle, 190.0, 0.6, 0.18, low_pass(7000.0, 0.7)),
+        39 => hit(Sine, 1000.0, 0.9, 0.15, band(1200.0, 1.5)),
+        40 => hit(
+            Triangle,
+            220.0,
+            0.7,
+            0.15,
+            filter(FilterKind::HighPass, 400.0, 0.7),
+        ),
+        41 | 43 | 45 | 47 | 48 | 50 => {
+            let hz = match key {
+                41 => 80.0,
+                43 => 95.0,
+                45 => 110.0,
+                47 => 130.0,
+                48 => 155.0,
+                _ => 185.0,
+            };
+            hit(Sine, hz, 0.1, 0.35, low_pass(1200.0, 0.7))
+        }
+        42 => cymbal(7000.0, 0.06),
+        44 => cymbal(6500.0, 0.09),
+        46 => cymbal(6000.0, 0.45),
+        49 | 57 => cymbal(4500.0, 1.6),
+        51 | 59 => cymbal(8000.0, 1.0),
+        52 => cymbal(3500.0, 1.2),
+        53 => hit(Square, 620.0, 0.2, 0.8, band(3000.0, 2.0)),
+        54 => cymbal(6000.0, 0.2),
+        55 => cymbal(5000.0, 0.6),
+        56 => hit(Square, 560.0, 0.0, 0.25, band(850.0, 3.0)),
+        58 => hit(Square, 300.0, 0.5, 0.6, band(2500.0, 4.0)),
+        60..=68 => {
+            let hz = [
+                480.0, 360.0, 330.0, 300.0, 220.0, 400.0, 300.0, 900.0, 700.0,
+            ];
+            let tone = if key >= 65 { Square } else { Sine };
+            hit(
+                tone,
+                hz[usize::from(key - 60)],
+                0.1,
+                0.2,
+                low_pass(3000.0, 0.7),
+            )
+        }
+        69 | 70 => cymbal(9000.0, 0.06),
+        71 => hit(Sine, 2200.0, 0.0, 0.15, None),
+        72 => hit(Sine, 2200.0, 0.0, 0.6, None),
+        73 | 74 => hit(
+            Sawtooth,
+            60.0,
+            0.6,
+            if key == 73 { 0.1 } else { 0.35 },
+            band(3500.0, 2.0),
+        ),
+        75 => hit(Sine, 2500.0, 0.0, 0.05, None),
+        76 | 77 => hit(
+            Triangle,
+            if key == 76 { 1100.0 } else { 800.0 },
+            0.0,
+            0.07,
+            None,
+        ),
+        78 | 79 => hit(
+            Sine,
+            if key == 78 { 600.0 } else { 450.0 },
+            0.1,
+            if key == 78 { 0.12 } else { 0.35 },
+            None,
+        ),
+        80 => hit(Sine, 4200.0, 0.0, 0.25, None),
+        81 => hit(Sine, 4200.0, 0.0, 1.3, None),
+        _ => return None,
+    };
+    patch.name = drum_name(key)?.to_string();
+    Some(patch)
+}
+
+/// Sound of `key` in the analog kit, keys it leaves out come from the standard kit
+pub fn analog_drum(key: u8) -> Option<Patch> {
+    let (name, mut patch) = match key {
+        35 | 36 => ("808 Bass Drum", hit(Sine, 48.0, 0.0, 0.7, None)),
+        37 => ("808 Rim Shot", hit(Triangle, 1700.0, 0.1, 0.03, None)),
+        38 | 40 => (
+            "808 Snare",
+            hit(
+                Triangle,
+                240.0,
+                0.5,
+                0.2,
+                filter(FilterKind::HighPass, 200.0, 0.7),
+            ),
+        ),
+        39 => (
+            "808 Clap",
+            hit(
+                Sine,
+                1000.0,
+                1.0,
+                0.2,
+                filter(FilterKind::BandPass, 1000.0, 2.0),
+            ),
+        ),
+        42 => ("808 Closed Hi-Hat", cymbal(8000.0, 0.04)),
+        44 => ("808 Pedal Hi-Hat", cymbal(8000.0, 0.07)),
+        46 => ("808 Open Hi-Hat", cymbal(8000.0, 0.35)),
+        56 => (
+            "808 Cowbell",
+            hit(
+                Square,
+                540.0,
+                0.0,
+                0.3,
+                filter(FilterKind::BandPass, 800.0, 2.0),
+            ),
+        ),
+        75 => ("808 Claves", hit(Sine, 2500.0, 0.0, 0.03, None)),
+        _ => return None,
+    };
+    patch.name = name.to_string();
+    Some(patch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_program_is_audible() {
+        for program in 0..128 {
+            let patch = self::program(program);
+            assert_eq!(patch.name, PROGRAM_NAMES[usize::from(program)]);
+            assert!(patch.oscillators.iter().any(|osc| osc.level > 0.0));
+            assert!(patch.pitch.is_none() && !patch.one_shot);
+            assert!(patch
+                .routes
+                .iter()
+                .any(|route| route.via == Some(Source::ModWheel)));
+            if let Some(filter) = patch.filter {
+                assert!(filter.cutoff >= 100.0, "{}", patch.name);
+            }
+        }
+        // program numbers wrap like 7 bit data
+        assert_eq!(program(128 + 40), program(40));
+    }
+
+    #[test]
+    fn percussion_map_is_complete() {
+        for key in DRUM_KEYS {
+            let patch = drum(key).unwrap();
+            assert_eq!(Some(patch.name.as_str()), drum_name(key));
+            assert!(patch.pitch.is_some());
+            assert_eq!(patch.envelope.sustain, 0.0);
+        }
+        assert!(drum(34).is_none() && drum(82).is_none());
+    }
+}
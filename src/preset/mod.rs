@@ -0,0 +1,214 @@
+/// Patch files, a subset of TOML
+pub mod file;
+/// General MIDI sound set built from the synth's oscillators, envelopes and filters
+///
+/// The patches only hint at the instruments, but enough that a GM file is recognizable.
+///
+/// - [General MIDI Level 1 Sound Set](https://www.midi.org/specifications-old/item/gm-level-1-sound-set)
+/// - [GM percussion key map](https://www.recordingblogs.com/wiki/general-midi-percussion-key-map)
+pub mod gm;
+
+use std::collections::HashMap;
+
+use crate::{
+    effect::distortion::{BitcrusherParams, DistortionParams},
+    envelope::EnvelopeParams,
+    filter::FilterParams,
+    modulation::{lfo::LfoParams, Route, LFOS},
+    osc::OscKind,
+};
+
+/// Oscillators mixed in a voice
+pub const OSCILLATORS: usize = 2;
+
+/// Sound of a voice: oscillators into an optional filter, shaped by the envelope
+///
+/// The routes of the modulation matrix move the parameters while the note plays.
+/// The distortion and the bitcrusher are the inserts of the channel strip playing
+/// the patch, see [`crate::mix::ChannelStrip`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch {
+    pub name: String,
+    pub oscillators: [OscParams; OSCILLATORS],
+    pub envelope: EnvelopeParams,
+    pub filter: Option<FilterParams>,
+    /// `[0, 1]`, 1 moves the cutoff with the pitch so every key gets the same tone
+    pub key_tracking: f32,
+    /// Hz the voice plays at whatever the key, for drums
+    pub pitch: Option<f32>,
+    /// Play the whole envelope and ignore note-offs
+    pub one_shot: bool,
+    /// linear output level
+    pub gain: f32,
+    pub lfos: [LfoParams; LFOS],
+    /// envelope that only modulates, see [`crate::modulation::Source::ModEnvelope`]
+    pub mod_envelope: EnvelopeParams,
+    pub routes: Vec<Route>,
+    /// channel inserts, off if `None`
+    pub distortion: Option<DistortionParams>,
+    pub bitcrusher: Option<BitcrusherParams>,
+}
+
+impl Default for Patch {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            oscillators: [
+                OscParams {
+                    level: 1.0,
+                    ..OscParams::default()
+                },
+                OscParams::default(),
+            ],
+            envelope: EnvelopeParams::default(),
+            filter: None,
+            key_tracking: 0.0,
+            pitch: None,
+            one_shot: false,
+            gain: 1.0,
+            lfos: [LfoParams::default(); LFOS],
+            mod_envelope: EnvelopeParams::default(),
+            routes: Vec::new(),
+            distortion: None,
+            bitcrusher: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OscParams {
+    pub kind: OscKind,
+    /// linear level in the mix, 0 turns the oscillator off
+    pub level: f32,
+    /// semitones from the key, fractions detune
+    pub transpose: f32,
+    /// duty cycle of `OscKind::Square`
+    pub pulse_width: f32,
+}
+
+impl Default for OscParams {
+    fn default() -> Self {
+        Self {
+            kind: OscKind::Triangle,
+            level: 0.0,
+            transpose: 0.0,
+            pulse_width: 0.5,
+        }
+    }
+}
+
+/// Bank selected by controllers 0 and 32
+pub fn bank(msb: u8, lsb: u8) -> u16 {
+    u16::from(msb & 0x7F) << 7 | u16::from(lsb & 0x7F)
+}
+
+/// Melodic banks of 128 programs and drum kits of up to 128 keys
+///
+/// A program missing from its bank falls back to bank 0, a key missing from its kit
+/// to kit 0, the way GS and XG modules substitute their capital tones.
+#[derive(Debug, Clone, Default)]
+pub struct Presets {
+    /// `(bank, program)`
+    melodic: HashMap<(u16, u8), Patch>,
+    /// `(kit program, key)`
+    drums: HashMap<(u8, u8), Patch>,
+}
+
+impl Presets {
+    /// Empty, every note plays `Patch::default()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The General MIDI sound set, see [`gm`]
+    pub fn general_midi() -> Self {
+        let mut presets = Self::new();
+        for program in 0..128 {
+            presets.insert(0, program, gm::program(program));
+            presets.insert(gm::VARIATION_BANK, program, gm::variation(program));
+        }
+        for key in gm::DRUM_KEYS {
+            if let Some(patch) = gm::drum(key) {
+                presets.insert_drum(gm::STANDARD_KIT, key, patch);
+            }
+            if let Some(patch) = gm::analog_drum(key) {
+                presets.insert_drum(gm::ANALOG_KIT, key, patch);
+            }
+        }
+        presets
+    }
+
+    pub fn insert(&mut self, bank: u16, program: u8, patch: Patch) {
+        self.melodic.insert((bank, program & 0x7F), patch);
+    }
+
+    pub fn insert_drum(&mut self, kit: u8, key: u8, patch: Patch) {
+        self.drums.insert((kit & 0x7F, key & 0x7F), patch);
+    }
+
+    pub fn patch(&self, bank: u16, program: u8) -> Option<&Patch> {
+        let program = program & 0x7F;
+        self.melodic
+            .get(&(bank, program))
+            .or_else(|| self.melodic.get(&(0, program)))
+    }
+
+    pub fn drum(&self, kit: u8, key: u8) -> Option<&Patch> {
+        let key = key & 0x7F;
+        self.drums
+            .get(&(kit & 0x7F, key))
+            .or_else(|| self.drums.get(&(0, key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_fallback() {
+        let mut presets = Presets::general_midi();
+        assert_eq!(presets.patch(0, 0).unwrap().name, "Acoustic Grand Piano");
+        assert_eq!(
+            presets.patch(gm::VARIATION_BANK, 0).unwrap().name,
+            "Acoustic Grand Piano Var."
+        );
+        assert_eq!(presets.patch(bank(5, 3), 40).unwrap().name, "Violin");
+        presets.insert(
+            bank(5, 3),
+            40,
+            Patch {
+                name: "Fiddle".into(),
+                ..Patch::default()
+            },
+        );
+        assert_eq!(presets.patch(bank(5, 3), 40).unwrap().name, "Fiddle");
+        assert!(Presets::new().patch(0, 0).is_none());
+    }
+
+    #[test]
+    fn drum_kits() {
+        let presets = Presets::general_midi();
+        assert_eq!(presets.drum(0, 38).unwrap().name, "Acoustic Snare");
+        assert_eq!(
+            presets.drum(gm::ANALOG_KIT, 36).unwrap().name,
+            "808 Bass Drum"
+        );
+        // missing from the analog kit, missing from every kit
+        assert_eq!(
+            presets.drum(gm::ANALOG_KIT, 81).unwrap().name,
+            "Open Triangle"
+        );
+        assert!(presets.drum(0, 20).is_none());
+        for key in gm::DRUM_KEYS {
+            assert!(presets.drum(0, key).unwrap().one_shot);
+        }
+    }
+
+    #[test]
+    fn bank_number() {
+        assert_eq!(bank(0, 0), 0);
+        assert_eq!(bank(8, 0), gm::VARIATION_BANK);
+        assert_eq!(bank(0x7F, 0x7F), 0x3FFF);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Error as StdIoError, Seek, Write},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{
+    audio::AudioRenderer,
+    dither::Dither,
+    midi::{
+        formats::{ParseError, Smf},
+        player::Player,
+    },
+    synth::Synth,
+    wav::{WavSpec, WavWriter},
+};
+
+/// Frames rendered per block
+const BLOCK_FRAMES: usize = 512;
+
+/// Longest tail of releases and effects rendered after the last event, in seconds
+const MAX_TAIL_SECONDS: u32 = 10;
+
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("midi file error: {0}")]
+    Parse(#[from] ParseError),
+    #[error("wav output error: {0}")]
+    Io(#[from] StdIoError),
+}
+
+/// Render `smf` faster than real time into a WAV stream, after `configure` set up the synth.
+///
+/// The output only depends on the file, the setup, `spec` and `dither`, so renders are
+/// bit-exact between runs.
+pub fn render_smf<W: Write + Seek, F: FnOnce(&mut Synth)>(
+    smf: Smf,
+    writer: W,
+    spec: WavSpec,
+    dither: Dither,
+    configure: F,
+) -> Result<W, RenderError> {
+    let player = Player::new(smf, spec.sample_rate);
+    let mut renderer = AudioRenderer::with_player(player, spec.channels);
+    configure(renderer.synth_mut());
+    let mut wav = WavWriter::new(writer, spec)?;
+    wav.set_dither(dither);
+    let mut buffer = vec![0f32; BLOCK_FRAMES * usize::from(spec.channels)];
+    let max_tail = u64::from(spec.sample_rate * MAX_TAIL_SECONDS);
+    let mut tail = 0u64;
+    while !renderer.is_finished() && tail < max_tail {
+        if !renderer.is_playing() {
+            tail += BLOCK_FRAMES as u64;
+        }
+        renderer.render_audio(&mut buffer);
+        wav.write_samples(&buffer)?;
+    }
+    Ok(wav.finalize()?)
+}
+
+/// Render `smf` into the WAV file at `output`
+pub fn render_file<P: AsRef<Path>, F: FnOnce(&mut Synth)>(
+    smf: Smf,
+    output: P,
+    spec: WavSpec,
+    dither: Dither,
+    configure: F,
+) -> Result<(), RenderError> {
+    let file = BufWriter::new(File::create(output)?);
+    let mut writer = render_smf(smf, file, spec, dither, configure)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        effect::dynamics::CompressorParams,
+        preset::{gm, Patch},
+        tuning::Tuning,
+        wav::WavSampleFormat,
+    };
+    use std::io::Cursor;
+
+    /// One quarter note of A4 at 96 ppq and 120 bpm, after the `setup` events
+    fn smf(setup: &[u8]) -> Smf {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend([0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        let mut events = setup.to_vec();
+        events.extend([
+            0x00, 0x90, 69, 100, 0x60, 0x80, 69, 0, 0x00, 0xFF, 0x2F, 0x00,
+        ]);
+        bytes.extend(b"MTrk");
+        bytes.extend((events.len() as u32).to_be_bytes());
+        bytes.extend(events);
+        Smf::from_bytes(&bytes).unwrap()
+    }
+
+    fn render(smf: Smf, sample_format: WavSampleFormat, channels: u16) -> Vec<u8> {
+        render_with(smf, sample_format, channels, |_| {})
+    }
+
+    fn render_with<F: FnOnce(&mut Synth)>(
+        smf: Smf,
+        sample_format: WavSampleFormat,
+        channels: u16,
+        configure: F,
+    ) -> Vec<u8> {
+        let spec = WavSpec {
+            sample_rate: 8_000,
+            channels,
+            sample_format,
+        };
+        render_smf(
+            smf,
+            Cursor::new(Vec::new()),
+            spec,
+            Dither::Triangular,
+            configure,
+        )
+        .unwrap()
+        .into_inner()
+    }
+
+    /// Samples of a 16 bit render
+    fn samples(bytes: &[u8]) -> Vec<i16> {
+        bytes[46..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn renders_whole_song() {
+        let bytes = render(smf(&[]), WavSampleFormat::Int16, 2);
+        let data_len = u32::from_le_bytes([bytes[42], bytes[43], bytes[44], bytes[45]]);
+        // half a second at 8 kHz plus the release, rounded up to whole blocks
+        let frames = data_len as usize / 4;
+        assert_eq!(frames % BLOCK_FRAMES, 0);
+        assert!((4000..8000).contains(&frames), "{}", frames);
+        let samples = samples(&bytes);
+        assert!(samples[..8000].iter().any(|s| *s != 0));
+        assert_eq!(samples.last(), Some(&0));
+    }
+
+    #[test]
+    fn renders_effect_tails() {
+        let dry = samples(&render(smf(&[]), WavSampleFormat::Int16, 1));
+        // reverb send all the way up
+        let wet = samples(&render(
+            smf(&[0x00, 0xB0, 91, 127]),
+            WavSampleFormat::Int16,
+            1,
+        ));
+        // the tail rings on for more than a second after the dry render ends
+        assert!(wet.len() > dry.len() + 8000, "{} {}", dry.len(), wet.len());
+        let tail = &wet[dry.len() + 7000..dry.len() + 8000];
+        assert!(tail.iter().any(|s| s.abs() > 1), "{:?}", tail);
+        // and the render stops once it died away, well before the cap
+        assert!(wet.len() < 8000 * 4, "{}", wet.len());
+        assert!(wet[wet.len() - 100..].iter().all(|s| s.abs() <= 1));
+    }
+
+    #[test]
+    fn configures_the_synth() {
+        let peak = |samples: Vec<i16>| samples.iter().map(|s| s.unsigned_abs()).max();
+        let plain = peak(samples(&render(smf(&[]), WavSampleFormat::Int16, 1)));
+        let compressed = peak(samples(&render_with(
+            smf(&[]),
+            WavSampleFormat::Int16,
+            1,
+            |synth| {
+                synth
+                    .mixer()
+                    .master_mut()
+                    .compressor_mut()
+                    .set_params(CompressorParams {
+                        threshold: -40.0,
+                        ratio: 20.0,
+                        attack: 0.0,
+                        ..CompressorParams::default()
+                    })
+            },
+        )));
+        assert!(
+            compressed.unwrap() * 4 < plain.unwrap(),
+            "{:?} {:?}",
+            compressed,
+            plain
+        );
+    }
+
+    #[test]
+    fn renders_in_the_given_tuning() {
+        let mut tuning = Tuning::default();
+        tuning.set_frequency(69, None);
+        let bytes = render_with(smf(&[]), WavSampleFormat::Int16, 1, |synth| {
+            synth.set_tuning(tuning)
+        });
+        // the only key played does not sound
+        assert!(samples(&bytes).iter().all(|s| s.abs() <= 1));
+    }
+
+    #[test]
+    fn renders_loaded_patches() {
+        let path = std::env::temp_dir().join(format!("simple_synth_{}.patch", std::process::id()));
+        gm::program(80).save(&path).unwrap();
+        let patch = Patch::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let piano = samples(&render(smf(&[]), WavSampleFormat::Int16, 1));
+        // a square lead on the piano program
+        let lead = samples(&render_with(smf(&[]), WavSampleFormat::Int16, 1, |synth| {
+            synth.load_patch(0, 0, patch)
+        }));
+        let difference = piano
+            .iter()
+            .zip(&lead)
+            .filter(|(piano, lead)| (i32::from(**piano) - i32::from(**lead)).abs() > 100)
+            .count();
+        assert!(difference > 1000, "{}", difference);
+    }
+
+    #[test]
+    fn renders_are_bit_exact() {
+        for format in [
+            WavSampleFormat::Int16,
+            WavSampleFormat::Int24,
+            WavSampleFormat::Float32,
+        ] {
+            assert_eq!(render(smf(&[]), format, 1), render(smf(&[]), format, 1));
+        }
+    }
+}
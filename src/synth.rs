@@ -0,0 +1,843 @@
+use crate::{
+    effect::{
+        chorus::{Chorus, ChorusParams},
+        delay::{Delay, DelayParams},
+        phaser::{Phaser, PhaserParams},
+        reverb::{Reverb, ReverbParams},
+    },
+    midi::{
+        formats::{Event, MetaMessage, MidiMessage},
+        rpn::{self, Parameter, ParameterControl, ParameterMessage},
+        sysex::{SysexAssembler, SysexMessage, TuningSysex, UniversalSysex},
+    },
+    mix::{Bus, Mixer},
+    modulation::ChannelControls,
+    preset::{self, gm, Patch, Presets},
+    tuning::Tuning,
+    voice::{VoiceManager, CHANNELS, DEFAULT_BEND_RANGE},
+};
+
+/// Default number of simultaneous voices
+const POLYPHONY: usize = 64;
+
+/// Controller 91 sets the send of a channel to the reverb bus
+pub const REVERB_CONTROLLER: u8 = 91;
+/// Controller 93 sets the send of a channel to the chorus bus
+pub const CHORUS_CONTROLLER: u8 = 93;
+/// Controller 92, tremolo depth in GM2, sets the send of a channel to the flanger bus
+pub const FLANGER_CONTROLLER: u8 = 92;
+/// Controller 95, phaser depth in GM2, sets the send of a channel to the phaser bus
+pub const PHASER_CONTROLLER: u8 = 95;
+/// Controller 94 sets the send of a channel to the delay bus, as on Roland GS modules
+pub const DELAY_CONTROLLER: u8 = 94;
+/// The reverb, chorus, flanger, phaser and delay are the first buses of the mixer
+const REVERB_BUS: usize = 0;
+const CHORUS_BUS: usize = 1;
+const FLANGER_BUS: usize = 2;
+const PHASER_BUS: usize = 3;
+const DELAY_BUS: usize = 4;
+
+/// Program state of a MIDI channel
+#[derive(Debug, Clone, Copy)]
+struct ChannelProgram {
+    /// controllers 0 and 32, they take effect with the next program change
+    bank_msb: u8,
+    bank_lsb: u8,
+    /// bank and program the channel plays, the kit on a drum channel
+    bank: u16,
+    program: u8,
+    drums: bool,
+}
+
+impl ChannelProgram {
+    fn new(channel: u8) -> Self {
+        Self {
+            bank_msb: 0,
+            bank_lsb: 0,
+            bank: 0,
+            program: 0,
+            drums: channel == gm::DRUM_CHANNEL,
+        }
+    }
+
+    /// Rhythm bank MSBs make any channel a drum channel, the melody bank MSB
+    /// makes the GM drum channel melodic
+    fn program_change(&mut self, channel: u8, program: u8) {
+        self.bank = preset::bank(self.bank_msb, self.bank_lsb);
+        self.program = program;
+        self.drums = match self.bank_msb {
+            msb if gm::DRUM_BANK_MSBS.contains(&msb) => true,
+            gm::MELODY_BANK_MSB => false,
+            _ => channel == gm::DRUM_CHANNEL,
+        };
+    }
+}
+
+/// Registered parameters of a MIDI channel as 14 bit data
+#[derive(Debug, Clone, Copy)]
+struct RegisteredParameters {
+    bend_sensitivity: u16,
+    fine_tuning: u16,
+    coarse_tuning: u16,
+}
+
+impl Default for RegisteredParameters {
+    fn default() -> Self {
+        Self {
+            bend_sensitivity: (DEFAULT_BEND_RANGE as u16) << 7,
+            fine_tuning: 0x2000,
+            coarse_tuning: 64 << 7,
+        }
+    }
+}
+
+impl RegisteredParameters {
+    /// Semitones and cents
+    fn bend_range(&self) -> f32 {
+        f32::from(self.bend_sensitivity >> 7) + f32::from(self.bend_sensitivity & 0x7F) / 100.0
+    }
+
+    /// Cents, fine tuning is ±100 cents and coarse tuning ±64 semitones
+    fn tuning(&self) -> f32 {
+        let fine = (f32::from(self.fine_tuning) - 8192.0) * 100.0 / 8192.0;
+        let coarse = f32::from(self.coarse_tuning >> 7) - 64.0;
+        coarse * 100.0 + fine
+    }
+}
+
+/// Sound engine fed with MIDI events and pulled one stereo frame at a time
+///
+/// Notes play the patch their channel's bank and program select, channel 10 plays
+/// the drum kit, see [`preset::gm`]. The mixer has reverb, chorus, flanger, phaser and
+/// delay buses, see [`REVERB_CONTROLLER`], [`CHORUS_CONTROLLER`], [`FLANGER_CONTROLLER`],
+/// [`PHASER_CONTROLLER`] and [`DELAY_CONTROLLER`]. The flanger and phaser return only
+/// their wet signal, a full send mixes it with the channel one to one for the deepest
+/// notches. The delay follows the tempo of the song.
+pub struct Synth {
+    voices: VoiceManager,
+    mixer: Mixer,
+    sample_rate: f32,
+    presets: Presets,
+    programs: [ChannelProgram; CHANNELS],
+    parameters: [ParameterControl; CHANNELS],
+    registered: [RegisteredParameters; CHANNELS],
+    sysex: SysexAssembler,
+    /// master tuning set by sysex, cents and semitones
+    fine_tuning: f32,
+    coarse_tuning: i8,
+}
+
+impl Synth {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut mixer = Mixer::new(sample_rate);
+        let reverb = Bus::default()
+            .with_effect(Reverb::new(ReverbParams::default(), sample_rate))
+            .with_controller(REVERB_CONTROLLER);
+        let chorus = Bus::default()
+            .with_effect(Chorus::new(ChorusParams::default(), sample_rate))
+            .with_controller(CHORUS_CONTROLLER);
+        let flanger = Bus::default()
+            .with_effect(Chorus::new(
+                ChorusParams {
+                    mix: 1.0,
+                    ..ChorusParams::flanger()
+                },
+                sample_rate,
+            ))
+            .with_controller(FLANGER_CONTROLLER);
+        let phaser = Bus::default()
+            .with_effect(Phaser::new(
+                PhaserParams {
+                    mix: 1.0,
+                    ..PhaserParams::default()
+                },
+                sample_rate,
+            ))
+            .with_controller(PHASER_CONTROLLER);
+        let delay = Bus::default()
+            .with_effect(Delay::new(
+                DelayParams {
+                    mix: 1.0,
+                    ..DelayParams::default()
+                },
+                sample_rate,
+            ))
+            .with_controller(DELAY_CONTROLLER);
+        let buses = [reverb, chorus, flanger, phaser, delay].map(|bus| mixer.add_bus(bus).ok());
+        debug_assert_eq!(
+            buses,
+            [REVERB_BUS, CHORUS_BUS, FLANGER_BUS, PHASER_BUS, DELAY_BUS].map(Some)
+        );
+        Self {
+            voices: VoiceManager::new(POLYPHONY, sample_rate),
+            mixer,
+            sample_rate,
+            presets: Presets::general_midi(),
+            programs: programs(),
+            parameters: [ParameterControl::new(); CHANNELS],
+            registered: [RegisteredParameters::default(); CHANNELS],
+            sysex: SysexAssembler::new(),
+            fine_tuning: 0.0,
+            coarse_tuning: 0,
+        }
+    }
+
+    pub fn voices(&mut self) -> &mut VoiceManager {
+        &mut self.voices
+    }
+
+    pub fn mixer(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    /// Replace the reverb, this allocates its delay lines so call it before playback
+    pub fn set_reverb(&mut self, params: ReverbParams) {
+        let reverb = Reverb::new(params, self.sample_rate);
+        if let Some(bus) = self.mixer.bus_mut(REVERB_BUS) {
+            bus.inserts_mut()[0].set_effect(reverb);
+        }
+    }
+
+    /// Replace the chorus, this allocates its delay lines so call it before playback
+    pub fn set_chorus(&mut self, params: ChorusParams) {
+        let chorus = Chorus::new(params, self.sample_rate);
+        if let Some(bus) = self.mixer.bus_mut(CHORUS_BUS) {
+            bus.inserts_mut()[0].set_effect(chorus);
+        }
+    }
+
+    /// General MIDI by default
+    pub fn presets_mut(&mut self) -> &mut Presets {
+        &mut self.presets
+    }
+
+    /// Replace a melodic preset, channels already on it switch their inserts right away
+    pub fn load_patch(&mut self, bank: u16, program: u8, patch: Patch) {
+        self.presets.insert(bank, program, patch);
+        for channel in 0..CHANNELS as u8 {
+            self.update_inserts(channel);
+        }
+    }
+
+    /// Turn the inserts of a channel strip on or off for the patch the channel plays,
+    /// drum kits play without them
+    fn update_inserts(&mut self, channel: u8) {
+        let program = &self.programs[usize::from(channel) % CHANNELS];
+        let patch = if program.drums {
+            None
+        } else {
+            self.presets.patch(program.bank, program.program)
+        };
+        let strip = self.mixer.strip_mut(channel);
+        match patch.and_then(|patch| patch.distortion) {
+            Some(params) => {
+                strip.distortion.effect_mut().set_params(params);
+                strip.distortion.bypass = false;
+            }
+            None => strip.distortion.bypass = true,
+        }
+        match patch.and_then(|patch| patch.bitcrusher) {
+            Some(params) => {
+                strip.bitcrusher.effect_mut().set_params(params);
+                strip.bitcrusher.bypass = false;
+            }
+            None => strip.bitcrusher.bypass = true,
+        }
+    }
+
+    /// Start a note with the patch of its channel, drum keys outside the kit stay silent
+    fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        let program = &self.programs[usize::from(channel) % CHANNELS];
+        let patch = if program.drums {
+            self.presets.drum(program.program, key)
+        } else {
+            self.presets.patch(program.bank, program.program)
+        };
+        match patch {
+            Some(patch) => self.voices.note_on_with(channel, key, velocity, patch),
+            None if !program.drums => self.voices.note_on(channel, key, velocity),
+            None => {}
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        if let Event::Midi { channel, midi_msg } = event {
+            let channel = *channel;
+            match midi_msg {
+                MidiMessage::NoteOn { key, vel } => self.note_on(channel, key.value(), vel.value()),
+                MidiMessage::NoteOff { key, .. } => self.voices.note_off(channel, key.value()),
+                MidiMessage::ControlChange { controller, value } => {
+                    let program = &mut self.programs[usize::from(channel) % CHANNELS];
+                    match (controller.value(), value.value()) {
+                        (0, value) => program.bank_msb = value,
+                        (1, value) => {
+                            self.voices.controls_mut(channel).mod_wheel = f32::from(value) / 127.0
+                        }
+                        (32, value) => program.bank_lsb = value,
+                        (64, value) => self.voices.set_sustain(channel, value >= 64),
+                        (controller, value)
+                            if ParameterControl::is_parameter_controller(controller) =>
+                        {
+                            if let Some(message) = self.parameters[usize::from(channel) % CHANNELS]
+                                .control_change(controller, value)
+                            {
+                                self.handle_parameter(channel, message);
+                            }
+                        }
+                        (120, _) => self.voices.all_sound_off(channel),
+                        (121, _) => {
+                            self.voices.set_sustain(channel, false);
+                            *self.voices.controls_mut(channel) = ChannelControls::default();
+                            self.parameters[usize::from(channel) % CHANNELS].reset();
+                            self.mixer.reset_controllers(channel);
+                        }
+                        (123, _) => self.voices.all_notes_off(channel),
+                        (controller, value) => {
+                            self.mixer.control_change(channel, controller, value);
+                        }
+                    }
+                }
+                MidiMessage::PatchChange { program } => {
+                    self.programs[usize::from(channel) % CHANNELS]
+                        .program_change(channel, program.value());
+                    self.update_inserts(channel);
+                }
+                MidiMessage::ChannelPressure { vel } => {
+                    self.voices.controls_mut(channel).pressure = f32::from(vel.value()) / 127.0
+                }
+                MidiMessage::Aftertouch { key, vel } => {
+                    self.voices
+                        .set_aftertouch(channel, key.value(), f32::from(vel.value()) / 127.0)
+                }
+                MidiMessage::PitchBend { value } => {
+                    self.voices.controls_mut(channel).pitch_bend =
+                        (f32::from(value.value()) - 8192.0) / 8192.0
+                }
+            }
+        } else if let Event::Meta {
+            meta_msg: MetaMessage::Tempo(micros),
+        } = event
+        {
+            self.voices.set_tempo(*micros as f32 / 1e6);
+            self.mixer.set_tempo(*micros as f32 / 1e6);
+        } else if let Some(message) = self
+            .sysex
+            .push(event)
+            .and_then(|data| SysexMessage::decode(&data))
+        {
+            self.handle_sysex_message(message);
+        }
+    }
+
+    /// Act on a message decoded ahead of time, e.g. on a live input thread
+    pub fn handle_sysex_message(&mut self, message: SysexMessage) {
+        match message {
+            SysexMessage::Universal(message) => self.handle_sysex(message),
+            SysexMessage::Tuning(message) => self.handle_tuning(message),
+        }
+    }
+
+    /// Scale the keys play, 12-TET with A4 at 440 Hz by default, see [`Tuning`]
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.voices.set_tuning_table(tuning);
+    }
+
+    /// Retune keys, every tuning program and bank retunes the one table the synth plays
+    pub fn handle_tuning(&mut self, message: TuningSysex) {
+        match message {
+            TuningSysex::BulkDump { frequencies, .. } => {
+                for (key, frequency) in frequencies.into_iter().enumerate() {
+                    if frequency.is_some() {
+                        self.voices.retune_key(key as u8, frequency);
+                    }
+                }
+            }
+            TuningSysex::SingleNote { changes, .. } => {
+                for (key, frequency) in changes {
+                    if frequency.is_some() {
+                        self.voices.retune_key(key, frequency);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Act on RPN data entry, other parameters are ignored
+    fn handle_parameter(&mut self, channel: u8, message: ParameterMessage) {
+        let registered = &mut self.registered[usize::from(channel) % CHANNELS];
+        let (value, step) = match message.parameter {
+            Parameter::Registered(rpn::PITCH_BEND_SENSITIVITY) => {
+                (&mut registered.bend_sensitivity, 1)
+            }
+            Parameter::Registered(rpn::FINE_TUNING) => (&mut registered.fine_tuning, 1),
+            Parameter::Registered(rpn::COARSE_TUNING) => (&mut registered.coarse_tuning, 1 << 7),
+            _ => return,
+        };
+        *value = message.change.apply(*value, step);
+        let registered = *registered;
+        self.voices
+            .set_pitch_bend_range(channel, registered.bend_range());
+        self.voices.set_channel_tuning(channel, registered.tuning());
+    }
+
+    /// Act on a decoded message, resets silence every channel and restore the defaults
+    pub fn handle_sysex(&mut self, message: UniversalSysex) {
+        match message {
+            UniversalSysex::MasterVolume(value) => {
+                self.mixer.master_mut().gain = f32::from(value) / 16383.0;
+            }
+            UniversalSysex::MasterFineTuning(cents) => self.fine_tuning = cents,
+            UniversalSysex::MasterCoarseTuning(semitones) => self.coarse_tuning = semitones,
+            UniversalSysex::GmSystemOff => {}
+            reset => {
+                debug_assert!(reset.is_reset());
+                for channel in 0..CHANNELS as u8 {
+                    self.voices.all_sound_off(channel);
+                    self.voices.set_sustain(channel, false);
+                    *self.voices.controls_mut(channel) = ChannelControls::default();
+                    self.voices
+                        .set_pitch_bend_range(channel, DEFAULT_BEND_RANGE);
+                    self.voices.set_channel_tuning(channel, 0.0);
+                    self.mixer.reset_channel(channel);
+                }
+                self.programs = programs();
+                for channel in 0..CHANNELS as u8 {
+                    self.update_inserts(channel);
+                }
+                self.mixer.master_mut().gain = 1.0;
+                self.parameters = [ParameterControl::new(); CHANNELS];
+                self.registered = [RegisteredParameters::default(); CHANNELS];
+                self.fine_tuning = 0.0;
+                self.coarse_tuning = 0;
+            }
+        }
+        self.voices
+            .set_master_tuning(f32::from(self.coarse_tuning) * 100.0 + self.fine_tuning);
+    }
+
+    /// Returns true if nothing is sounding, no voices and no effect tails
+    pub fn is_idle(&self) -> bool {
+        self.voices.voices().is_empty() && self.mixer.is_silent()
+    }
+
+    /// Returns the next left and right sample
+    pub fn render_frame(&mut self) -> [f32; 2] {
+        let mut channels = [[0f32; 2]; CHANNELS];
+        self.voices.render_frame(&mut channels);
+        self.mixer.process(&channels)
+    }
+}
+
+fn programs() -> [ChannelProgram; CHANNELS] {
+    std::array::from_fn(|channel| ChannelProgram::new(channel as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        effect::{
+            distortion::{BitcrusherParams, DistortionParams},
+            oversample::Oversampling,
+        },
+        midi::formats::{Slice, U14, U7},
+        preset::{OscParams, Patch},
+    };
+
+    fn note_on(channel: u8, key: u8) -> Event {
+        Event::Midi {
+            channel,
+            midi_msg: MidiMessage::NoteOn {
+                key: U7::new(key),
+                vel: U7::new(100),
+            },
+        }
+    }
+
+    fn midi(channel: u8, midi_msg: MidiMessage) -> Event {
+        Event::Midi { channel, midi_msg }
+    }
+
+    fn control_change(channel: u8, controller: u8, value: u8) -> Event {
+        midi(
+            channel,
+            MidiMessage::ControlChange {
+                controller: U7::new(controller),
+                value: U7::new(value),
+            },
+        )
+    }
+
+    fn program_change(channel: u8, program: u8) -> Event {
+        midi(
+            channel,
+            MidiMessage::PatchChange {
+                program: U7::new(program),
+            },
+        )
+    }
+
+    /// Frequency of the last note started
+    fn last_frequency(synth: &mut Synth) -> Option<f32> {
+        synth
+            .voices()
+            .voices()
+            .last()
+            .map(|voice| voice.frequency())
+    }
+
+    fn sysex(data: &[u8]) -> Event {
+        Event::Sysex {
+            sysex_msg: Slice::from(data.to_vec()),
+        }
+    }
+
+    #[test]
+    fn master_volume_and_tuning() {
+        let mut synth = Synth::new(1000.0);
+        synth.handle_event(&sysex(&[0x7F, 0x7F, 0x04, 0x01, 0x00, 0x40, 0xF7]));
+        assert_eq!(synth.mixer().master_mut().gain, 8192.0 / 16383.0);
+        // a split message takes effect once complete
+        synth.handle_event(&sysex(&[0x7F, 0x7F, 0x04]));
+        synth.handle_event(&Event::SysexContinuation {
+            sysex_msg: Slice::from(vec![0x04, 0x00, 0x4C, 0xF7]),
+        });
+        synth.handle_event(&sysex(&[0x7F, 0x7F, 0x04, 0x03, 0x00, 0x60, 0xF7]));
+        assert_eq!(synth.voices().master_tuning(), 1250.0);
+        synth.handle_event(&note_on(0, 57));
+        assert!(
+            (synth.voices().voices()[0].frequency() - 440.0 * 2f32.powf(1.0 / 24.0)).abs() < 1e-2
+        );
+    }
+
+    #[test]
+    fn reverb_send_leaves_a_tail() {
+        let tail = |send| {
+            let mut synth = Synth::new(8000.0);
+            synth.handle_event(&control_change(0, REVERB_CONTROLLER, send));
+            synth.handle_event(&note_on(0, 60));
+            for _ in 0..2000 {
+                synth.render_frame();
+            }
+            synth.voices().all_sound_off(0);
+            assert!(synth.voices().voices().is_empty());
+            // after what the limiter still holds back
+            (0..2000)
+                .map(|_| synth.render_frame())
+                .skip(100)
+                .map(|[l, r]| l * l + r * r)
+                .sum::<f32>()
+        };
+        assert_eq!(tail(0), 0.0);
+        assert!(tail(127) > 1e-3);
+        let mut synth = Synth::new(1000.0);
+        synth.handle_event(&control_change(2, REVERB_CONTROLLER, 40));
+        assert_eq!(synth.mixer().strip(2).sends[REVERB_BUS], 40.0 / 127.0);
+        synth.handle_event(&sysex(&[0x7E, 0x7F, 0x09, 0x01, 0xF7]));
+        assert_eq!(synth.mixer().strip(2).sends[REVERB_BUS], 0.0);
+        synth.handle_event(&control_change(2, CHORUS_CONTROLLER, 127));
+        assert_eq!(synth.mixer().strip(2).sends[CHORUS_BUS], 1.0);
+    }
+
+    #[test]
+    fn delay_follows_tempo_changes() {
+        // frames until the echo of a note comes back
+        let echo = |micros| {
+            let render = |send| {
+                let mut synth = Synth::new(8000.0);
+                synth.handle_event(&Event::Meta {
+                    meta_msg: MetaMessage::Tempo(micros),
+                });
+                synth.handle_event(&control_change(0, DELAY_CONTROLLER, send));
+                // the delay glides to the new tempo
+                for _ in 0..4000 {
+                    synth.render_frame();
+                }
+                synth.handle_event(&note_on(0, 60));
+                (0..4000).map(|_| synth.render_frame()).collect::<Vec<_>>()
+            };
+            let dry = render(0);
+            render(127)
+                .iter()
+                .zip(&dry)
+                .position(|(wet, dry)| (wet[0] - dry[0]).abs() > 1e-4)
+        };
+        // a dotted eighth, at 120 and at 200 quarter notes a minute
+        let slow = echo(500_000).unwrap();
+        let fast = echo(300_000).unwrap();
+        // the note fades in over a few frames
+        assert!((3000..3100).contains(&slow), "{}", slow);
+        assert!((1195..1205).contains(&(slow - fast)), "{} {}", slow, fast);
+        let mut synth = Synth::new(1000.0);
+        synth.handle_event(&control_change(5, DELAY_CONTROLLER, 127));
+        assert_eq!(synth.mixer().strip(5).sends[DELAY_BUS], 1.0);
+    }
+
+    #[test]
+    fn patches_turn_on_channel_inserts() {
+        let render = |patch: Patch| {
+            let mut synth = Synth::new(8000.0);
+            let inserts = [patch.distortion.is_some(), patch.bitcrusher.is_some()];
+            synth.presets_mut().insert(0, 30, patch);
+            synth.handle_event(&program_change(0, 30));
+            let strip = synth.mixer().strip_mut(0);
+            assert_eq!(
+                [!strip.distortion.bypass, !strip.bitcrusher.bypass],
+                inserts
+            );
+            synth.handle_event(&note_on(0, 60));
+            let out = (0..2000).map(|_| synth.render_frame()).collect::<Vec<_>>();
+            // another program takes the inserts out
+            synth.handle_event(&program_change(0, 0));
+            let strip = synth.mixer().strip_mut(0);
+            assert!(strip.distortion.bypass && strip.bitcrusher.bypass);
+            out
+        };
+        let difference = |a: &[[f32; 2]], b: &[[f32; 2]]| {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| (a[0] - b[0]).powi(2))
+                .sum::<f32>()
+        };
+        let clean = gm::program(30);
+        let driven = Patch {
+            distortion: Some(DistortionParams {
+                drive: 24.0,
+                output: -9.0,
+                ..DistortionParams::default()
+            }),
+            ..clean.clone()
+        };
+        let crushed = Patch {
+            bitcrusher: Some(BitcrusherParams {
+                bits: 2.0,
+                oversampling: Oversampling::Off,
+                ..BitcrusherParams::default()
+            }),
+            ..clean.clone()
+        };
+        let [driven, clean, crushed] = [driven, clean, crushed].map(render);
+        assert!(difference(&driven, &clean) > 1e-2);
+        assert!(difference(&crushed, &clean) > 1e-2);
+    }
+
+    #[test]
+    fn loaded_patches_switch_inserts_right_away() {
+        let driven = Patch {
+            distortion: Some(DistortionParams::default()),
+            ..gm::program(30)
+        };
+        let mut synth = Synth::new(8000.0);
+        synth.handle_event(&program_change(3, 30));
+        synth.handle_event(&program_change(4, 29));
+        synth.load_patch(0, 30, driven.clone());
+        synth.load_patch(0, 29, driven);
+        assert!(!synth.mixer().strip_mut(3).distortion.bypass);
+        // channels on other programs keep theirs
+        synth.load_patch(0, 30, gm::program(0));
+        assert!(synth.mixer().strip_mut(3).distortion.bypass);
+        assert!(!synth.mixer().strip_mut(4).distortion.bypass);
+    }
+
+    #[test]
+    fn flanger_and_phaser_sends_change_the_sound() {
+        let render = |controller, send| {
+            let mut synth = Synth::new(8000.0);
+            synth.handle_event(&control_change(0, controller, send));
+            synth.handle_event(&note_on(0, 60));
+            (0..4000).map(|_| synth.render_frame()).collect::<Vec<_>>()
+        };
+        let dry = render(FLANGER_CONTROLLER, 0);
+        for (controller, bus) in [
+            (FLANGER_CONTROLLER, FLANGER_BUS),
+            (PHASER_CONTROLLER, PHASER_BUS),
+        ] {
+            assert_eq!(render(controller, 0), dry);
+            let wet = render(controller, 127);
+            let difference = dry
+                .iter()
+                .zip(&wet)
+                .map(|(a, b)| (a[0] - b[0]).powi(2))
+                .sum::<f32>();
+            assert!(difference > 1e-2, "{} {}", controller, difference);
+            let mut synth = Synth::new(1000.0);
+            synth.handle_event(&control_change(5, controller, 127));
+            assert_eq!(synth.mixer().strip(5).sends[bus], 1.0);
+        }
+    }
+
+    #[test]
+    fn controllers_feed_modulation() {
+        let mut synth = Synth::new(1000.0);
+        synth.handle_event(&control_change(4, 1, 127));
+        synth.handle_event(&midi(
+            4,
+            MidiMessage::PitchBend {
+                value: U14::new(0x3000),
+            },
+        ));
+        synth.handle_event(&midi(4, MidiMessage::ChannelPressure { vel: U7::new(0) }));
+        assert_eq!(
+            *synth.voices().controls(4),
+            ChannelControls {
+                pressure: 0.0,
+                mod_wheel: 1.0,
+                pitch_bend: 0.5,
+            }
+        );
+        synth.handle_event(&control_change(4, 121, 0));
+        assert_eq!(*synth.voices().controls(4), ChannelControls::default());
+    }
+
+    #[test]
+    fn rpns_set_bend_range_and_tuning() {
+        let mut synth = Synth::new(48_000.0);
+        let bend = |value| {
+            midi(
+                0,
+                MidiMessage::PitchBend {
+                    value: U14::new(value),
+                },
+            )
+        };
+        let current = |synth: &mut Synth| synth.voices().voices()[0].current_frequency();
+        synth.handle_event(&note_on(0, 69));
+        // a full bend down moves the default range of two semitones
+        synth.handle_event(&bend(0));
+        synth.render_frame();
+        assert!((current(&mut synth) - 440.0 * 2f32.powf(-2.0 / 12.0)).abs() < 1e-2);
+        // RPN 0, 12 semitones
+        for (controller, value) in [(101, 0), (100, 0), (6, 12), (38, 0)] {
+            synth.handle_event(&control_change(0, controller, value));
+        }
+        synth.render_frame();
+        assert!((current(&mut synth) - 220.0).abs() < 1e-2);
+        assert_eq!(synth.voices().pitch_bend_range(0), 12.0);
+        // RPN 2 coarse tuning a semitone up, then incremented by one more
+        for (controller, value) in [(100, 2), (6, 65), (96, 0)] {
+            synth.handle_event(&control_change(0, controller, value));
+        }
+        assert_eq!(synth.voices().channel_tuning(0), 200.0);
+        // reset all controllers keeps the parameters but deselects them
+        synth.handle_event(&control_change(0, 121, 0));
+        synth.handle_event(&control_change(0, 6, 64));
+        assert_eq!(synth.voices().channel_tuning(0), 200.0);
+        synth.handle_event(&sysex(&[0x7E, 0x7F, 0x09, 0x01, 0xF7]));
+        assert_eq!(synth.voices().pitch_bend_range(0), DEFAULT_BEND_RANGE);
+        assert_eq!(synth.voices().channel_tuning(0), 0.0);
+    }
+
+    #[test]
+    fn tuning_standard_retunes_keys() {
+        let mut synth = Synth::new(48_000.0);
+        synth.set_tuning(Tuning::equal(442.0));
+        synth.handle_event(&note_on(0, 69));
+        assert_eq!(last_frequency(&mut synth), Some(442.0));
+        // A4 to exactly 440 Hz while it sounds, Bb4 left alone
+        synth.handle_event(&sysex(&[
+            0x7F, 0x7F, 0x08, 0x02, 0x00, 0x02, 69, 69, 0x00, 0x00, 70, 0x7F, 0x7F, 0x7F, 0xF7,
+        ]));
+        assert_eq!(synth.voices().voices()[0].current_frequency(), 440.0);
+        let bb = synth.voices().tuning_table().frequency(70).unwrap();
+        assert!((bb - 442.0 * 2f32.powf(1.0 / 12.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn idle_waits_for_the_reverb_tail() {
+        let frames_to_idle = |send| {
+            let mut synth = Synth::new(8000.0);
+            synth.handle_event(&control_change(0, REVERB_CONTROLLER, send));
+            synth.handle_event(&note_on(0, 60));
+            for _ in 0..2000 {
+                synth.render_frame();
+            }
+            synth.voices().all_sound_off(0);
+            (0..80_000)
+                .take_while(|_| {
+                    synth.render_frame();
+                    !synth.is_idle()
+                })
+                .count()
+        };
+        let dry = frames_to_idle(0);
+        let wet = frames_to_idle(127);
+        assert!(dry < 2000, "{}", dry);
+        assert!((dry + 8000..80_000).contains(&wet), "{} {}", dry, wet);
+    }
+
+    #[test]
+    fn reset_restores_defaults() {
+        let mut synth = Synth::new(1000.0);
+        synth.handle_event(&note_on(3, 60));
+        synth.mixer().control_change(3, 7, 20);
+        synth.mixer().master_mut().gain = 0.5;
+        synth.handle_event(&sysex(&[0x7F, 0x7F, 0x04, 0x04, 0x00, 0x3E, 0xF7]));
+        synth.handle_event(&sysex(&[
+            0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41, 0xF7,
+        ]));
+        assert!(synth.voices().voices().is_empty());
+        assert_eq!(synth.mixer().strip(3).volume, 100.0 / 127.0);
+        assert_eq!(synth.mixer().master_mut().gain, 1.0);
+        assert_eq!(synth.voices().master_tuning(), 0.0);
+    }
+
+    #[test]
+    fn bank_and_program_select_patches() {
+        let mut synth = Synth::new(48_000.0);
+        let octave_up = Patch {
+            oscillators: [
+                OscParams {
+                    level: 1.0,
+                    transpose: 12.0,
+                    ..OscParams::default()
+                },
+                OscParams::default(),
+            ],
+            ..Patch::default()
+        };
+        synth.presets_mut().insert(preset::bank(1, 0), 5, octave_up);
+        // bank select waits for the program change
+        synth.handle_event(&control_change(0, 0, 1));
+        synth.handle_event(&note_on(0, 69));
+        assert_eq!(last_frequency(&mut synth), Some(440.0));
+        synth.handle_event(&program_change(0, 5));
+        synth.handle_event(&note_on(0, 69));
+        assert_eq!(last_frequency(&mut synth), Some(880.0));
+        // other programs of the bank fall back to GM
+        synth.handle_event(&program_change(0, 6));
+        synth.handle_event(&note_on(0, 69));
+        assert_eq!(last_frequency(&mut synth), Some(440.0));
+    }
+
+    #[test]
+    fn drum_channel_plays_the_kit() {
+        let mut synth = Synth::new(48_000.0);
+        synth.handle_event(&note_on(9, 36));
+        assert_eq!(last_frequency(&mut synth), Some(60.0));
+        // outside the kit
+        synth.handle_event(&note_on(9, 20));
+        assert_eq!(synth.voices().voices().len(), 1);
+        // drums ring on after the note-off
+        synth.handle_event(&midi(
+            9,
+            MidiMessage::NoteOff {
+                key: U7::new(36),
+                vel: U7::new(0),
+            },
+        ));
+        assert!(!synth.voices().voices()[0].is_released());
+        // the analog kit, then the GM2 melody bank
+        synth.handle_event(&program_change(9, gm::ANALOG_KIT));
+        synth.handle_event(&note_on(9, 36));
+        assert_eq!(last_frequency(&mut synth), Some(48.0));
+        synth.handle_event(&control_change(9, 0, gm::MELODY_BANK_MSB));
+        synth.handle_event(&program_change(9, 0));
+        synth.handle_event(&note_on(9, 69));
+        assert_eq!(last_frequency(&mut synth), Some(440.0));
+        // a rhythm bank on another channel
+        synth.handle_event(&control_change(2, 0, 120));
+        synth.handle_event(&program_change(2, 0));
+        synth.handle_event(&note_on(2, 36));
+        assert_eq!(last_frequency(&mut synth), Some(60.0));
+    }
+}
@@ -0,0 +1,103 @@
+/// Scala scale and keyboard mapping files
+///
+/// [Scala scale file format](https://www.huygens-fokker.org/scala/scl_format.html)
+pub mod scala;
+
+use std::path::Path;
+
+use scala::{KeyboardMapping, ScalaError, Scale};
+
+/// Concert pitch of A4
+pub const DEFAULT_REFERENCE: f32 = 440.0;
+
+/// Key of A4
+const REFERENCE_KEY: u8 = 69;
+
+/// Frequency of every MIDI key, `None` for keys that do not sound
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    frequencies: [Option<f32>; 128],
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::equal(DEFAULT_REFERENCE)
+    }
+}
+
+impl Tuning {
+    /// 12-TET with A4 at `reference` Hz
+    pub fn equal(reference: f32) -> Self {
+        Self {
+            frequencies: std::array::from_fn(|key| {
+                Some(reference * 2f32.powf((key as f32 - f32::from(REFERENCE_KEY)) / 12.0))
+            }),
+        }
+    }
+
+    /// `scale` laid out on the keyboard by `mapping`
+    pub fn from_scale(scale: &Scale, mapping: &KeyboardMapping) -> Result<Self, ScalaError> {
+        let reference = mapping
+            .cents(scale, mapping.reference_key)
+            .ok_or(ScalaError::UnmappedReference(mapping.reference_key))?;
+        Ok(Self {
+            frequencies: std::array::from_fn(|key| {
+                let cents = mapping.cents(scale, key as u8)?;
+                Some(mapping.reference_frequency * 2f32.powf((cents - reference) / 1200.0))
+            }),
+        })
+    }
+
+    /// Load a `.scl` file, laid out by a `.kbm` file or linearly with A4 at 440 Hz
+    pub fn open<P: AsRef<Path>, Q: AsRef<Path>>(
+        scale: P,
+        mapping: Option<Q>,
+    ) -> Result<Self, ScalaError> {
+        let mapping = match mapping {
+            Some(path) => KeyboardMapping::open(path)?,
+            None => KeyboardMapping::default(),
+        };
+        Self::from_scale(&Scale::open(scale)?, &mapping)
+    }
+
+    pub fn frequency(&self, key: u8) -> Option<f32> {
+        self.frequencies[usize::from(key & 0x7F)]
+    }
+
+    /// Retune one key, `None` silences it
+    pub fn set_frequency(&mut self, key: u8, frequency: Option<f32>) {
+        self.frequencies[usize::from(key & 0x7F)] = frequency;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_temperament() {
+        let tuning = Tuning::default();
+        assert_eq!(tuning.frequency(69), Some(440.0));
+        assert!((tuning.frequency(60).unwrap() - 261.6256).abs() < 1e-3);
+        assert!((tuning.frequency(127).unwrap() - 12543.854).abs() < 1e-2);
+        let baroque = Tuning::equal(415.0);
+        assert_eq!(baroque.frequency(57), Some(207.5));
+    }
+
+    #[test]
+    fn opens_scale_and_mapping_files() {
+        let dir = std::env::temp_dir().join(format!("simple_synth_tuning_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let scl = dir.join("tritave.scl");
+        let kbm = dir.join("low.kbm");
+        std::fs::write(&scl, "tritave\n1\n3/1\n").unwrap();
+        std::fs::write(&kbm, "0\n0\n127\n60\n60\n100.0\n0\n").unwrap();
+        let linear = Tuning::open(&scl, None::<&Path>).unwrap();
+        let mapped = Tuning::open(&scl, Some(&kbm)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // every key a tritave up, A4 stays at 440 Hz without a mapping
+        assert_eq!(linear.frequency(69), Some(440.0));
+        assert!((linear.frequency(70).unwrap() - 1320.0).abs() < 1e-2);
+        assert!((mapped.frequency(61).unwrap() - 300.0).abs() < 1e-3);
+    }
+}
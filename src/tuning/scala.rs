@@ -0,0 +1,393 @@
+use std::{fs, io::Error as StdIoError, num::NonZeroUsize, path::Path, str::FromStr};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ScalaError {
+    #[error("scala file error: {0}")]
+    IOError(#[from] StdIoError),
+    #[error("line {line}: expected {expected}, found `{found}`")]
+    Syntax {
+        line: usize,
+        expected: &'static str,
+        found: String,
+    },
+    #[error("the file ends before {0}")]
+    Truncated(&'static str),
+    #[error("map size {0} is more than the 128 keys")]
+    MapSize(usize),
+    #[error("reference key {0} is not mapped to a scale degree")]
+    UnmappedReference(u8),
+}
+
+/// Lines that are not `!` comments, numbered from 1
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+/// First word of the next non-empty line, parsed
+fn field<'a, T: FromStr>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    expected: &'static str,
+) -> Result<T, ScalaError> {
+    let (line, text) = lines
+        .find(|(_, text)| !text.is_empty())
+        .ok_or(ScalaError::Truncated(expected))?;
+    let word = text.split_whitespace().next().unwrap_or_default();
+    word.parse().map_err(|_| ScalaError::Syntax {
+        line,
+        expected,
+        found: word.to_string(),
+    })
+}
+
+/// Interval of a scale line, cents if it has a period, otherwise a ratio like `3/2` or `2`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pitch(f32);
+
+impl FromStr for Pitch {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, ()> {
+        if text.contains('.') {
+            return text.parse().map(Self).map_err(|_| ());
+        }
+        let (numerator, denominator) = text.split_once('/').unwrap_or((text, "1"));
+        let numerator = numerator.parse::<u64>().map_err(|_| ())?;
+        let denominator = denominator.parse::<u64>().map_err(|_| ())?;
+        if numerator == 0 || denominator == 0 {
+            return Err(());
+        }
+        Ok(Self(
+            (1200.0 * (numerator as f64 / denominator as f64).log2()) as f32,
+        ))
+    }
+}
+
+/// MIDI key number
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Key(u8);
+
+impl FromStr for Key {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, ()> {
+        match text.parse() {
+            Ok(key @ 0..=127) => Ok(Self(key)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Scale degree of a mapping entry, `x` leaves the key unmapped
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MapEntry(Option<u32>);
+
+impl FromStr for MapEntry {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, ()> {
+        match text {
+            "x" | "X" => Ok(Self(None)),
+            _ => text
+                .parse()
+                .map(|degree| Self(Some(degree)))
+                .map_err(|_| ()),
+        }
+    }
+}
+
+/// Scala `.scl` scale, the intervals above the unison
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    description: String,
+    /// cents of degrees 1 to n, the last one is the period
+    pitches: Vec<f32>,
+}
+
+impl Scale {
+    /// Equal divisions of the octave
+    pub fn equal(divisions: u32) -> Self {
+        let divisions = divisions.max(1);
+        Self {
+            description: format!("{} equal divisions of the octave", divisions),
+            pitches: (1..=divisions)
+                .map(|step| 1200.0 * step as f32 / divisions as f32)
+                .collect(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScalaError> {
+        let mut lines = lines(text);
+        let description = lines
+            .next()
+            .map(|(_, line)| line.to_string())
+            .ok_or(ScalaError::Truncated("the description"))?;
+        let count: NonZeroUsize = field(&mut lines, "the number of notes")?;
+        let pitches = (0..count.get())
+            .map(|_| {
+                field::<Pitch>(&mut lines, "cents like 100.0 or a ratio like 3/2").map(|p| p.0)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            description,
+            pitches,
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ScalaError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Notes per period
+    pub fn len(&self) -> usize {
+        self.pitches.len()
+    }
+
+    /// Returns true for a scale of only the unison
+    pub fn is_empty(&self) -> bool {
+        self.pitches.is_empty()
+    }
+
+    /// Interval the scale repeats at, usually an octave
+    pub fn period(&self) -> f32 {
+        self.pitches.last().copied().unwrap_or(1200.0)
+    }
+
+    /// Cents of any degree, negative ones below the unison
+    pub fn cents(&self, degree: i32) -> f32 {
+        let size = self.pitches.len().max(1) as i32;
+        let (period, step) = (degree.div_euclid(size), degree.rem_euclid(size));
+        let within = match step {
+            0 => 0.0,
+            step => self.pitches[step as usize - 1],
+        };
+        period as f32 * self.period() + within
+    }
+}
+
+/// Scala `.kbm` keyboard mapping, which key plays which scale degree
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    /// scale degrees of one repetition of the pattern, empty maps every key to the next degree
+    pub map: Vec<Option<u32>>,
+    /// keys outside are not mapped
+    pub first_key: u8,
+    pub last_key: u8,
+    /// key playing degree 0
+    pub middle_key: u8,
+    pub reference_key: u8,
+    /// Hz of `reference_key`
+    pub reference_frequency: f32,
+    /// degree the pattern moves up by on each repetition, 0 for the period of the scale
+    pub octave_degree: u32,
+}
+
+impl Default for KeyboardMapping {
+    /// Linear from middle C with A4 at 440 Hz
+    fn default() -> Self {
+        Self::linear(60, 69, super::DEFAULT_REFERENCE)
+    }
+}
+
+impl KeyboardMapping {
+    /// Every key the next degree, `middle_key` the unison
+    pub fn linear(middle_key: u8, reference_key: u8, reference_frequency: f32) -> Self {
+        Self {
+            map: Vec::new(),
+            first_key: 0,
+            last_key: 127,
+            middle_key,
+            reference_key,
+            reference_frequency,
+            octave_degree: 0,
+        }
+    }
+
+    /// Keys are 0 to 127, missing map entries at the end of the file are unmapped
+    pub fn parse(text: &str) -> Result<Self, ScalaError> {
+        let mut lines = lines(text);
+        let size: usize = field(&mut lines, "the map size")?;
+        if size > 128 {
+            return Err(ScalaError::MapSize(size));
+        }
+        let mut key = |expected| field::<Key>(&mut lines, expected).map(|key| key.0);
+        let first_key = key("the first key")?;
+        let last_key = key("the last key")?;
+        let middle_key = key("the middle key")?;
+        let reference_key = key("the reference key")?;
+        let reference_frequency = field(&mut lines, "the reference frequency")?;
+        let octave_degree = field(&mut lines, "the octave degree")?;
+        let mut map = Vec::with_capacity(size);
+        for _ in 0..size {
+            map.push(match field::<MapEntry>(&mut lines, "a scale degree or x") {
+                Ok(entry) => entry.0,
+                Err(ScalaError::Truncated(_)) => None,
+                Err(err) => return Err(err),
+            });
+        }
+        Ok(Self {
+            map,
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_frequency,
+            octave_degree,
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ScalaError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Cents of `key` above the unison of `scale`, `None` if it is not mapped
+    pub fn cents(&self, scale: &Scale, key: u8) -> Option<f32> {
+        if !(self.first_key..=self.last_key).contains(&key) {
+            return None;
+        }
+        let offset = i32::from(key) - i32::from(self.middle_key);
+        if self.map.is_empty() {
+            return Some(scale.cents(offset));
+        }
+        let size = self.map.len() as i32;
+        let degree = self.map[offset.rem_euclid(size) as usize]?;
+        let octave = match self.octave_degree {
+            0 => scale.period(),
+            degree => scale.cents(degree as i32),
+        };
+        Some(offset.div_euclid(size) as f32 * octave + scale.cents(degree as i32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuning::Tuning;
+
+    const MEANTONE: &str = "! meanquar.scl
+!
+1/4-comma meantone scale. Pietro Aaron's temperament (1523)
+ 12
+!
+ 76.04900
+ 193.15686
+ 310.26471
+ 5/4
+ 503.42157
+ 579.47057
+ 696.57843
+ 25/16
+ 889.73529
+ 1006.84314
+ 1082.89214
+ 2/1
+";
+
+    #[test]
+    fn parses_scale() {
+        let scale = Scale::parse(MEANTONE).unwrap();
+        assert_eq!(
+            scale.description(),
+            "1/4-comma meantone scale. Pietro Aaron's temperament (1523)"
+        );
+        assert_eq!(scale.len(), 12);
+        assert!((scale.cents(4) - 386.3137).abs() < 1e-3);
+        assert_eq!(scale.cents(12), 1200.0);
+        assert!((scale.cents(-8) - (386.3137 - 1200.0)).abs() < 1e-3);
+        // an empty description is not a comment
+        let scale = Scale::parse("\n1\n3/1 tritave").unwrap();
+        assert_eq!(scale.description(), "");
+        assert!((scale.period() - 1901.955).abs() < 1e-3);
+    }
+
+    #[test]
+    fn scale_errors() {
+        assert!(matches!(
+            Scale::parse("bad\n2\n100.0\n0/1"),
+            Err(ScalaError::Syntax { line: 4, .. })
+        ));
+        assert!(matches!(
+            Scale::parse("short\n3\n100.0\n"),
+            Err(ScalaError::Truncated(_))
+        ));
+    }
+
+    #[test]
+    fn white_keys_only() {
+        // a diatonic scale on the white keys, black keys unmapped
+        let scale = Scale::parse("major\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1").unwrap();
+        let mapping = KeyboardMapping::parse(
+            "! white.kbm
+12
+0
+127
+60
+69
+440.0
+7
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+",
+        )
+        .unwrap();
+        let tuning = Tuning::from_scale(&scale, &mapping).unwrap();
+        assert_eq!(tuning.frequency(69), Some(440.0));
+        assert_eq!(tuning.frequency(61), None);
+        // just intonation from C at 440 * 3/5
+        assert!((tuning.frequency(60).unwrap() - 264.0).abs() < 1e-2);
+        assert!((tuning.frequency(67).unwrap() - 396.0).abs() < 1e-2);
+        assert!((tuning.frequency(72).unwrap() - 528.0).abs() < 1e-2);
+        assert!((tuning.frequency(48).unwrap() - 132.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn map_size_is_limited() {
+        assert!(matches!(
+            KeyboardMapping::parse("4294967295\n0\n127\n60\n69\n440.0\n0\n"),
+            Err(ScalaError::MapSize(4294967295))
+        ));
+        // a full keyboard of missing entries is unmapped
+        let mapping = KeyboardMapping::parse("128\n0\n127\n60\n69\n440.0\n0\n").unwrap();
+        assert_eq!(mapping.map, vec![None; 128]);
+    }
+
+    #[test]
+    fn linear_mapping_and_reference() {
+        let mapping = KeyboardMapping::linear(60, 69, 442.0);
+        let tuning = Tuning::from_scale(&Scale::equal(12), &mapping).unwrap();
+        let equal = Tuning::equal(442.0);
+        for key in 0..128 {
+            let (a, b) = (
+                tuning.frequency(key).unwrap(),
+                equal.frequency(key).unwrap(),
+            );
+            assert!((a / b - 1.0).abs() < 1e-5, "{}", key);
+        }
+        let mapping = KeyboardMapping {
+            map: vec![None],
+            ..mapping
+        };
+        assert!(matches!(
+            Tuning::from_scale(&Scale::equal(12), &mapping),
+            Err(ScalaError::UnmappedReference(69))
+        ));
+    }
+}
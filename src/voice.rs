@@ -0,0 +1,788 @@
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+use crate::{
+    envelope::Envelope,
+    filter::{svf::Svf, FilterParams},
+    modulation::{lfo::Lfo, ChannelControls, Modulation, Route, Sources, LFOS, MAX_ROUTES},
+    osc::{OscKind, Oscillator},
+    preset::{Patch, OSCILLATORS},
+    tuning::Tuning,
+};
+
+/// Number of MIDI channels
+pub const CHANNELS: usize = 16;
+
+/// Level of a voice at full patch gain, leaves headroom for chords
+const VOICE_GAIN: f32 = 0.2;
+
+/// Cutoffs of key tracking patches are set for this key, middle C
+const TRACKING_KEY: f32 = 60.0;
+
+/// Semitones of a full pitch bend until RPN 0 changes it
+pub const DEFAULT_BEND_RANGE: f32 = 2.0;
+
+/// Seconds a stolen voice fades out over instead of cutting off with a click
+const STEAL_FADE: f32 = 0.003;
+
+/// Which voice gives way when a note arrives and every voice is busy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealPolicy {
+    /// The voice started first, released voices go before held ones
+    Oldest,
+    /// The voice with the lowest output level
+    Quietest,
+    /// Retrigger the voice already playing the same note on the same channel,
+    /// a new note steals like `Oldest`
+    SameNote,
+}
+
+/// A sounding note
+#[derive(Debug, Clone)]
+pub struct Voice {
+    channel: u8,
+    key: u8,
+    velocity: u8,
+    /// note-on order, smaller is older
+    age: u64,
+    /// note-off received
+    released: bool,
+    /// note-off received while the sustain pedal was down
+    sustained: bool,
+    /// note-offs are ignored
+    one_shot: bool,
+    oscillators: [Oscillator; OSCILLATORS],
+    levels: [f32; OSCILLATORS],
+    /// the patch has no fixed pitch, the key sets it
+    keyed: bool,
+    /// oscillator transposition ratios
+    ratios: [f32; OSCILLATORS],
+    /// unmodulated frequencies and duty cycles
+    frequencies: [f32; OSCILLATORS],
+    /// semitones of pitch bend and modulation the oscillators play at
+    pitch: f32,
+    pulse_widths: [f32; OSCILLATORS],
+    filter: Option<Svf>,
+    /// unmodulated cutoff and Q
+    cutoff: f32,
+    q: f32,
+    env: Envelope,
+    gain: f32,
+    lfos: [Lfo; LFOS],
+    mod_env: Envelope,
+    /// capacity of `MAX_ROUTES`, so starting a note does not allocate
+    routes: Vec<Route>,
+    /// polyphonic key pressure `[0, 1]`
+    aftertouch: f32,
+    /// gain of a stolen voice fading out, 1 otherwise
+    fade: f32,
+}
+
+impl Voice {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            channel: 0,
+            key: 0,
+            velocity: 0,
+            age: 0,
+            released: false,
+            sustained: false,
+            one_shot: false,
+            oscillators: [
+                Oscillator::new(OscKind::Triangle, sample_rate),
+                Oscillator::new(OscKind::Triangle, sample_rate),
+            ],
+            levels: [0.0; OSCILLATORS],
+            keyed: true,
+            ratios: [1.0; OSCILLATORS],
+            frequencies: [0.0; OSCILLATORS],
+            pitch: 0.0,
+            pulse_widths: [0.5; OSCILLATORS],
+            filter: None,
+            cutoff: 0.0,
+            q: 0.0,
+            env: Envelope::new(sample_rate),
+            gain: 0.0,
+            lfos: std::array::from_fn(|_| Lfo::new(sample_rate)),
+            mod_env: Envelope::new(sample_rate),
+            routes: Vec::with_capacity(MAX_ROUTES),
+            aftertouch: 0.0,
+            fade: 1.0,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    pub fn key(&self) -> u8 {
+        self.key
+    }
+
+    pub fn velocity(&self) -> u8 {
+        self.velocity
+    }
+
+    /// Frequency of the first oscillator before modulation
+    pub fn frequency(&self) -> f32 {
+        self.frequencies[0]
+    }
+
+    /// Frequency the first oscillator plays at, bent and modulated
+    pub fn current_frequency(&self) -> f32 {
+        self.oscillators[0].frequency()
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    fn level(&self) -> f32 {
+        self.env.level()
+    }
+
+    fn start(&mut self, channel: u8, key: u8, velocity: u8, age: u64, hz: f32, patch: &Patch) {
+        self.channel = channel;
+        self.key = key;
+        self.velocity = velocity;
+        self.age = age;
+        self.released = false;
+        self.sustained = false;
+        self.one_shot = patch.one_shot;
+        self.aftertouch = 0.0;
+        self.fade = 1.0;
+        self.pitch = 0.0;
+        self.keyed = patch.pitch.is_none();
+        let hz = patch.pitch.unwrap_or(hz);
+        for (index, params) in patch.oscillators.iter().enumerate() {
+            let osc = &mut self.oscillators[index];
+            self.ratios[index] = 2f32.powf(params.transpose / 12.0);
+            self.frequencies[index] = hz * self.ratios[index];
+            self.pulse_widths[index] = params.pulse_width;
+            self.levels[index] = params.level;
+            osc.set_kind(params.kind);
+            osc.set_pulse_width(params.pulse_width);
+            osc.set_frequency(self.frequencies[index]);
+        }
+        let sample_rate = self.oscillators[0].sample_rate();
+        self.filter = patch.filter.map(|params| {
+            let tracking = patch.key_tracking.clamp(0.0, 1.0);
+            let cutoff =
+                params.cutoff * 2f32.powf(tracking * (f32::from(key) - TRACKING_KEY) / 12.0);
+            self.cutoff = cutoff;
+            self.q = params.q;
+            Svf::new(FilterParams { cutoff, ..params }, sample_rate)
+        });
+        self.gain = patch.gain * VOICE_GAIN;
+        self.env.set_params(patch.envelope);
+        self.env.gate_on(velocity, false);
+        self.routes.clear();
+        self.routes
+            .extend(patch.routes.iter().take(MAX_ROUTES).copied());
+        if !self.routes.is_empty() {
+            for (lfo, params) in self.lfos.iter_mut().zip(patch.lfos) {
+                lfo.start(params);
+            }
+            self.mod_env.set_params(patch.mod_envelope);
+            self.mod_env.gate_on(velocity, false);
+        }
+    }
+
+    fn release(&mut self) {
+        self.released = true;
+        self.sustained = false;
+        self.env.gate_off();
+        self.mod_env.gate_off();
+    }
+
+    /// Play the note at `hz` from now on, bend and modulation stay
+    fn retune(&mut self, hz: f32) {
+        for (frequency, ratio) in self.frequencies.iter_mut().zip(self.ratios) {
+            *frequency = hz * ratio;
+        }
+        self.apply_pitch();
+    }
+
+    /// Move every oscillator `semitones` away from its frequency
+    fn set_pitch(&mut self, semitones: f32) {
+        if semitones != self.pitch {
+            self.pitch = semitones;
+            self.apply_pitch();
+        }
+    }
+
+    fn apply_pitch(&mut self) {
+        let ratio = 2f32.powf(self.pitch / 12.0);
+        for (osc, hz) in self.oscillators.iter_mut().zip(self.frequencies) {
+            osc.set_frequency(hz * ratio);
+        }
+    }
+
+    /// Left and right, the same on both sides unless a route pans the voice
+    ///
+    /// `bend` is the pitch bend of the channel in semitones.
+    fn next_sample(&mut self, controls: &ChannelControls, bend: f32) -> [f32; 2] {
+        if self.routes.is_empty() {
+            self.set_pitch(bend);
+            let sample = self.mix() * self.env.next_sample() * self.gain;
+            return [sample; 2];
+        }
+        let amp_envelope = self.env.next_sample();
+        let mut sources = Sources {
+            lfos: [0.0; LFOS],
+            amp_envelope,
+            mod_envelope: self.mod_env.next_sample(),
+            velocity: f32::from(self.velocity) / 127.0,
+            key: (f32::from(self.key) - TRACKING_KEY) / 12.0,
+            aftertouch: self.aftertouch,
+            channel: *controls,
+        };
+        for (value, lfo) in sources.lfos.iter_mut().zip(self.lfos.iter_mut()) {
+            *value = lfo.next_sample();
+        }
+        let modulation = Modulation::new(&self.routes, &sources);
+        self.set_pitch(bend + modulation.pitch);
+        for (osc, pulse_width) in self.oscillators.iter_mut().zip(self.pulse_widths) {
+            osc.set_pulse_width(pulse_width + modulation.pulse_width);
+        }
+        if let Some(filter) = self.filter.as_mut() {
+            filter.set_params(FilterParams {
+                cutoff: self.cutoff * 2f32.powf(modulation.cutoff),
+                q: self.q * 2f32.powf(modulation.resonance),
+                ..*filter.params()
+            });
+        }
+        let sample = self.mix() * amp_envelope * self.gain * modulation.gain();
+        // constant power, unity on both sides in the centre
+        let angle = (modulation.pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+        [sample * SQRT_2 * angle.cos(), sample * SQRT_2 * angle.sin()]
+    }
+
+    /// Oscillators through the filter
+    fn mix(&mut self) -> f32 {
+        let mut mix = 0.0;
+        for (osc, level) in self.oscillators.iter_mut().zip(self.levels) {
+            if level != 0.0 {
+                mix += osc.next_sample() * level;
+            }
+        }
+        match self.filter.as_mut() {
+            Some(filter) => filter.process(mix),
+            None => mix,
+        }
+    }
+}
+
+/// Polyphonic voice allocator
+///
+/// Voices are preallocated, so note handling and rendering never allocate. A stolen voice
+/// fades out over [`STEAL_FADE`] on a spare voice while the new note starts.
+pub struct VoiceManager {
+    sample_rate: f32,
+    polyphony: usize,
+    policy: StealPolicy,
+    voices: Vec<Voice>,
+    /// stolen voices fading out, not counted against the polyphony
+    stolen: Vec<Voice>,
+    /// voices that are not sounding, a spare for every voice that can be stolen
+    free: Vec<Voice>,
+    /// note-on counter
+    clock: u64,
+    sustain: [bool; CHANNELS],
+    controls: [ChannelControls; CHANNELS],
+    /// semitones of a full pitch bend
+    bend_ranges: [f32; CHANNELS],
+    /// channel tuning in cents, on top of the master tuning
+    channel_tunings: [f32; CHANNELS],
+    /// master tuning in cents
+    tuning: f32,
+    /// frequency of every key before master and channel tuning
+    table: Tuning,
+}
+
+impl VoiceManager {
+    pub fn new(polyphony: usize, sample_rate: f32) -> Self {
+        let polyphony = polyphony.max(1);
+        Self {
+            sample_rate,
+            polyphony,
+            policy: StealPolicy::Oldest,
+            voices: Vec::with_capacity(polyphony),
+            stolen: Vec::with_capacity(polyphony),
+            free: (0..polyphony * 2)
+                .map(|_| Voice::new(sample_rate))
+                .collect(),
+            clock: 0,
+            sustain: [false; CHANNELS],
+            controls: [ChannelControls::default(); CHANNELS],
+            bend_ranges: [DEFAULT_BEND_RANGE; CHANNELS],
+            channel_tunings: [0.0; CHANNELS],
+            tuning: 0.0,
+            table: Tuning::default(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    pub fn polyphony(&self) -> usize {
+        self.polyphony
+    }
+
+    pub fn steal_policy(&self) -> StealPolicy {
+        self.policy
+    }
+
+    pub fn set_steal_policy(&mut self, policy: StealPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn master_tuning(&self) -> f32 {
+        self.tuning
+    }
+
+    /// Shift every note by `cents`, sounding notes keep their pitch
+    pub fn set_master_tuning(&mut self, cents: f32) {
+        self.tuning = cents;
+    }
+
+    pub fn pitch_bend_range(&self, channel: u8) -> f32 {
+        self.bend_ranges[usize::from(channel) % CHANNELS]
+    }
+
+    /// Semitones a full bend moves the notes of `channel`, sounding notes follow
+    pub fn set_pitch_bend_range(&mut self, channel: u8, semitones: f32) {
+        self.bend_ranges[usize::from(channel) % CHANNELS] = semitones;
+    }
+
+    pub fn channel_tuning(&self, channel: u8) -> f32 {
+        self.channel_tunings[usize::from(channel) % CHANNELS]
+    }
+
+    /// Shift the notes of `channel` by `cents`, sounding notes keep their pitch
+    pub fn set_channel_tuning(&mut self, channel: u8, cents: f32) {
+        self.channel_tunings[usize::from(channel) % CHANNELS] = cents;
+    }
+
+    /// Seconds per quarter note of tempo synced LFOs
+    pub fn set_tempo(&mut self, quarter_seconds: f32) {
+        for voice in self
+            .voices
+            .iter_mut()
+            .chain(self.stolen.iter_mut())
+            .chain(self.free.iter_mut())
+        {
+            for lfo in voice.lfos.iter_mut() {
+                lfo.set_tempo(quarter_seconds);
+            }
+        }
+    }
+
+    /// Pressure, mod wheel and pitch bend of `channel`, read by every voice of the channel
+    pub fn controls(&self, channel: u8) -> &ChannelControls {
+        &self.controls[usize::from(channel) % CHANNELS]
+    }
+
+    pub fn controls_mut(&mut self, channel: u8) -> &mut ChannelControls {
+        &mut self.controls[usize::from(channel) % CHANNELS]
+    }
+
+    /// Polyphonic key pressure `[0, 1]` of the voices playing `key`
+    pub fn set_aftertouch(&mut self, channel: u8, key: u8, pressure: f32) {
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.channel == channel && v.key == key)
+        {
+            voice.aftertouch = pressure;
+        }
+    }
+
+    pub fn tuning_table(&self) -> &Tuning {
+        &self.table
+    }
+
+    /// Frequencies of the keys of new notes, sounding notes keep their pitch
+    pub fn set_tuning_table(&mut self, table: Tuning) {
+        self.table = table;
+    }
+
+    /// Retune one key, sounding notes on the key follow
+    pub fn retune_key(&mut self, key: u8, frequency: Option<f32>) {
+        self.table.set_frequency(key, frequency);
+        let Some(hz) = self.table.frequency(key) else {
+            return;
+        };
+        for voice in self.voices.iter_mut().filter(|v| v.key == key && v.keyed) {
+            let cents = self.tuning + self.channel_tunings[usize::from(voice.channel) % CHANNELS];
+            voice.retune(hz * 2f32.powf(cents / 1200.0));
+        }
+    }
+
+    /// Sounding voices, including the ones fading out
+    pub fn voices(&self) -> &[Voice] {
+        &self.voices
+    }
+
+    pub fn channel_voices(&self, channel: u8) -> impl Iterator<Item = &Voice> {
+        self.voices.iter().filter(move |v| v.channel == channel)
+    }
+
+    /// Start a note with the default patch, velocity 0 is a note-off
+    pub fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        self.note_on_with(channel, key, velocity, &Patch::default());
+    }
+
+    /// Start a note playing `patch`, velocity 0 is a note-off
+    ///
+    /// Keys the tuning table leaves unmapped only sound with patches of a fixed pitch.
+    pub fn note_on_with(&mut self, channel: u8, key: u8, velocity: u8, patch: &Patch) {
+        if velocity == 0 {
+            return self.note_off(channel, key);
+        }
+        let Some(hz) = self.table.frequency(key).or(patch.pitch) else {
+            return;
+        };
+        self.clock += 1;
+        let age = self.clock;
+        let cents = self.tuning + self.channel_tuning(channel);
+        let hz = hz * 2f32.powf(cents / 1200.0);
+        if self.policy == StealPolicy::SameNote {
+            if let Some(voice) = self
+                .voices
+                .iter_mut()
+                .find(|v| v.channel == channel && v.key == key)
+            {
+                return voice.start(channel, key, velocity, age, hz, patch);
+            }
+        }
+        if self.voices.len() < self.polyphony {
+            let Some(mut voice) = self.free.pop() else {
+                return;
+            };
+            voice.start(channel, key, velocity, age, hz, patch);
+            self.voices.push(voice);
+        } else if let Some(index) = self.victim() {
+            match self.free.pop() {
+                Some(mut voice) => {
+                    self.stolen.push(self.voices.swap_remove(index));
+                    voice.start(channel, key, velocity, age, hz, patch);
+                    self.voices.push(voice);
+                }
+                // no spare while every stolen voice still fades, cut the victim off
+                None => self.voices[index].start(channel, key, velocity, age, hz, patch),
+            }
+        }
+    }
+
+    /// Release a note, deferred while the sustain pedal of the channel is down
+    ///
+    /// One shot voices play on.
+    pub fn note_off(&mut self, channel: u8, key: u8) {
+        let sustain = self.sustain[usize::from(channel) % CHANNELS];
+        for voice in self.voices.iter_mut().filter(|v| {
+            v.channel == channel && v.key == key && !v.released && !v.sustained && !v.one_shot
+        }) {
+            if sustain {
+                voice.sustained = true;
+            } else {
+                voice.release();
+            }
+        }
+    }
+
+    /// Sustain pedal, controller 64
+    pub fn set_sustain(&mut self, channel: u8, down: bool) {
+        self.sustain[usize::from(channel) % CHANNELS] = down;
+        if !down {
+            for voice in self
+                .voices
+                .iter_mut()
+                .filter(|v| v.channel == channel && v.sustained)
+            {
+                voice.release();
+            }
+        }
+    }
+
+    /// Release every note of `channel`, controller 123
+    pub fn all_notes_off(&mut self, channel: u8) {
+        for voice in self.voices.iter_mut().filter(|v| v.channel == channel) {
+            voice.release();
+        }
+    }
+
+    /// Silence `channel` immediately, controller 120
+    pub fn all_sound_off(&mut self, channel: u8) {
+        for voices in [&mut self.voices, &mut self.stolen] {
+            let mut index = 0;
+            while index < voices.len() {
+                if voices[index].channel == channel {
+                    self.free.push(voices.swap_remove(index));
+                } else {
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    fn victim(&self) -> Option<usize> {
+        let oldest = |released: bool| {
+            self.voices
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| v.released == released)
+                .min_by_key(|(_, v)| v.age)
+                .map(|(i, _)| i)
+        };
+        match self.policy {
+            StealPolicy::Oldest | StealPolicy::SameNote => oldest(true).or_else(|| oldest(false)),
+            StealPolicy::Quietest => self
+                .voices
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.level().total_cmp(&b.level()))
+                .map(|(i, _)| i),
+        }
+    }
+
+    /// Add the next stereo sample of every voice to the slot of its channel and free finished voices
+    pub fn render_frame(&mut self, channels: &mut [[f32; 2]; CHANNELS]) {
+        let fade_step = 1.0 / (STEAL_FADE * self.sample_rate);
+        for (voices, fade_step) in [(&mut self.voices, 0.0), (&mut self.stolen, fade_step)] {
+            let mut index = 0;
+            while index < voices.len() {
+                let voice = &mut voices[index];
+                let channel = usize::from(voice.channel) % CHANNELS;
+                let controls = &self.controls[channel];
+                let bend = controls.pitch_bend * self.bend_ranges[channel];
+                let [left, right] = voice.next_sample(controls, bend);
+                channels[channel][0] += left * voice.fade;
+                channels[channel][1] += right * voice.fade;
+                voice.fade -= fade_step;
+                if voice.env.is_finished() || voice.fade <= 0.0 {
+                    self.free.push(voices.swap_remove(index));
+                } else {
+                    index += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modulation::{Destination, Source};
+
+    fn keys(manager: &VoiceManager) -> Vec<u8> {
+        let mut keys = manager.voices().iter().map(Voice::key).collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    fn run(manager: &mut VoiceManager, frames: usize) {
+        let mut channels = [[0f32; 2]; CHANNELS];
+        for _ in 0..frames {
+            manager.render_frame(&mut channels);
+        }
+    }
+
+    #[test]
+    fn velocity_zero_is_note_off() {
+        let mut manager = VoiceManager::new(4, 1000.0);
+        manager.note_on(0, 60, 100);
+        manager.note_on(0, 60, 0);
+        assert!(manager.voices()[0].is_released());
+        run(&mut manager, 100);
+        assert!(manager.voices().is_empty());
+    }
+
+    #[test]
+    fn steals_oldest_released_first() {
+        let mut manager = VoiceManager::new(3, 1000.0);
+        manager.note_on(0, 60, 100);
+        manager.note_on(0, 62, 100);
+        manager.note_on(0, 64, 100);
+        manager.note_off(0, 62);
+        manager.note_on(0, 65, 100);
+        assert_eq!(keys(&manager), vec![60, 64, 65]);
+        manager.note_on(0, 67, 100);
+        assert_eq!(keys(&manager), vec![64, 65, 67]);
+    }
+
+    #[test]
+    fn stolen_voice_fades_out() {
+        let mut manager = VoiceManager::new(1, 48000.0);
+        manager.note_on(0, 60, 100);
+        run(&mut manager, 100);
+        manager.note_on(0, 64, 100);
+        assert_eq!(keys(&manager), vec![64]);
+        assert_eq!(manager.stolen.len(), 1);
+        run(&mut manager, 100);
+        assert_eq!(manager.stolen.len(), 1);
+        assert!(manager.stolen[0].fade > 0.0 && manager.stolen[0].fade < 1.0);
+        run(&mut manager, 100);
+        assert!(manager.stolen.is_empty());
+        assert_eq!(keys(&manager), vec![64]);
+    }
+
+    #[test]
+    fn steals_quietest() {
+        let mut manager = VoiceManager::new(2, 1000.0);
+        manager.set_steal_policy(StealPolicy::Quietest);
+        manager.note_on(0, 60, 20);
+        manager.note_on(0, 62, 120);
+        run(&mut manager, 10);
+        manager.note_on(0, 64, 100);
+        assert_eq!(keys(&manager), vec![62, 64]);
+    }
+
+    #[test]
+    fn same_note_retriggers() {
+        let mut manager = VoiceManager::new(4, 1000.0);
+        manager.set_steal_policy(StealPolicy::SameNote);
+        manager.note_on(1, 60, 100);
+        manager.note_on(1, 60, 50);
+        assert_eq!(manager.voices().len(), 1);
+        assert_eq!(manager.voices()[0].velocity(), 50);
+        // same key on another channel is another voice
+        manager.note_on(2, 60, 100);
+        assert_eq!(manager.channel_voices(1).count(), 1);
+        assert_eq!(manager.channel_voices(2).count(), 1);
+    }
+
+    #[test]
+    fn sustain_pedal_defers_note_off() {
+        let mut manager = VoiceManager::new(4, 1000.0);
+        manager.set_sustain(0, true);
+        manager.note_on(0, 60, 100);
+        manager.note_off(0, 60);
+        assert!(!manager.voices()[0].is_released());
+        manager.set_sustain(0, false);
+        assert!(manager.voices()[0].is_released());
+    }
+
+    #[test]
+    fn master_tuning_applies_to_new_notes() {
+        let mut manager = VoiceManager::new(4, 1000.0);
+        manager.note_on(0, 69, 100);
+        manager.set_master_tuning(-1200.0);
+        manager.note_on(0, 81, 100);
+        let hz = manager
+            .voices()
+            .iter()
+            .map(Voice::frequency)
+            .collect::<Vec<_>>();
+        assert_eq!(hz[0], 440.0);
+        assert!((hz[1] - 440.0).abs() < 1e-3);
+    }
+
+    /// Left and right peaks of `frames` frames of `channel`
+    fn peaks(manager: &mut VoiceManager, channel: u8, frames: usize) -> [f32; 2] {
+        let mut peaks = [0f32; 2];
+        for _ in 0..frames {
+            let mut channels = [[0f32; 2]; CHANNELS];
+            manager.render_frame(&mut channels);
+            for (peak, s) in peaks.iter_mut().zip(channels[usize::from(channel)]) {
+                *peak = peak.max(s.abs());
+            }
+        }
+        peaks
+    }
+
+    #[test]
+    fn mod_wheel_brings_in_vibrato() {
+        let patch = Patch {
+            routes: vec![Route::new(Source::Lfo(0), Destination::Pitch, 1.0).via(Source::ModWheel)],
+            ..Patch::default()
+        };
+        let mut manager = VoiceManager::new(4, 1000.0);
+        manager.note_on_with(0, 69, 100, &patch);
+        let current = |manager: &VoiceManager| manager.voices()[0].oscillators[0].frequency();
+        run(&mut manager, 50);
+        assert_eq!(current(&manager), 440.0);
+        manager.controls_mut(0).mod_wheel = 1.0;
+        // the peak of the 5 Hz cycle, a semitone up
+        run(&mut manager, 1);
+        assert!((current(&manager) / 440.0 - 2f32.powf(1.0 / 12.0)).abs() < 1e-3);
+        assert_eq!(manager.voices()[0].frequency(), 440.0);
+    }
+
+    #[test]
+    fn routes_pan_and_scale_voices() {
+        let patch = Patch {
+            routes: vec![
+                Route::new(Source::Key, Destination::Pan, 1.0),
+                Route::new(Source::Aftertouch, Destination::Amp, -1.0),
+            ],
+            ..Patch::default()
+        };
+        let mut manager = VoiceManager::new(4, 1000.0);
+        // an octave above middle C, hard right
+        manager.note_on_with(0, 72, 127, &patch);
+        let [left, right] = peaks(&mut manager, 0, 100);
+        assert!(left < 1e-6 && right > 0.05);
+        manager.set_aftertouch(0, 72, 1.0);
+        assert!(peaks(&mut manager, 0, 100)[1] < 1e-6);
+    }
+
+    #[test]
+    fn pitch_bend_moves_sounding_notes() {
+        let mut manager = VoiceManager::new(4, 1000.0);
+        manager.set_channel_tuning(1, 100.0);
+        manager.note_on(1, 69, 100);
+        let semitone = 2f32.powf(1.0 / 12.0);
+        assert!((manager.voices()[0].frequency() - 440.0 * semitone).abs() < 1e-3);
+        manager.set_pitch_bend_range(1, 12.0);
+        manager.controls_mut(1).pitch_bend = -0.5;
+        run(&mut manager, 1);
+        let bent = manager.voices()[0].current_frequency();
+        assert!((bent - 440.0 * semitone / 2f32.sqrt()).abs() < 1e-2);
+        manager.controls_mut(1).pitch_bend = 0.0;
+        run(&mut manager, 1);
+        assert_eq!(
+            manager.voices()[0].current_frequency(),
+            manager.voices()[0].frequency()
+        );
+    }
+
+    #[test]
+    fn notes_follow_the_tuning_table() {
+        let mut manager = VoiceManager::new(4, 1000.0);
+        let mut table = Tuning::equal(432.0);
+        table.set_frequency(61, None);
+        manager.set_tuning_table(table);
+        manager.note_on(0, 69, 100);
+        manager.note_on(0, 61, 100);
+        assert_eq!(keys(&manager), vec![69]);
+        assert_eq!(manager.voices()[0].frequency(), 432.0);
+        manager.retune_key(69, Some(430.0));
+        assert_eq!(manager.voices()[0].current_frequency(), 430.0);
+        // fixed pitch patches play unmapped keys
+        let drum = Patch {
+            pitch: Some(60.0),
+            ..Patch::default()
+        };
+        manager.note_on_with(9, 61, 100, &drum);
+        assert_eq!(manager.channel_voices(9).count(), 1);
+    }
+
+    #[test]
+    fn renders_into_channel_slots() {
+        let mut manager = VoiceManager::new(4, 1000.0);
+        manager.note_on(3, 69, 127);
+        let mut channels = [[0f32; 2]; CHANNELS];
+        for _ in 0..20 {
+            manager.render_frame(&mut channels);
+        }
+        assert!(channels[3][0] != 0.0);
+        assert_eq!(channels[3][0], channels[3][1]);
+        assert!(channels
+            .iter()
+            .enumerate()
+            .all(|(i, s)| i == 3 || *s == [0.0; 2]));
+        manager.all_sound_off(3);
+        assert!(manager.voices().is_empty());
+    }
+}
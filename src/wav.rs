@@ -0,0 +1,298 @@
+use std::io::{Error as StdIoError, Seek, SeekFrom, Write};
+
+use crate::dither::{Dither, Quantizer};
+
+/// Layout of the samples in a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: WavSampleFormat,
+}
+
+impl WavSpec {
+    /// Formats beyond 16 bit stereo need `WAVE_FORMAT_EXTENSIBLE` to be read unambiguously
+    fn is_extensible(&self) -> bool {
+        self.sample_format == WavSampleFormat::Int24 || self.channels > 2
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavSampleFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavSampleFormat {
+    fn bytes(&self) -> u16 {
+        match self {
+            Self::Int16 => 2,
+            Self::Int24 => 3,
+            Self::Float32 => 4,
+        }
+    }
+
+    fn format_tag(&self) -> u16 {
+        match self {
+            Self::Int16 | Self::Int24 => WAVE_FORMAT_PCM,
+            Self::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+        }
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// The subformat GUID is the format tag followed by these bytes
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+/// Mono is front centre, the rest front left and right, channels past them have no speaker
+const SPEAKER_FRONT_LEFT: u32 = 0x1;
+const SPEAKER_FRONT_RIGHT: u32 = 0x2;
+const SPEAKER_FRONT_CENTER: u32 = 0x4;
+
+/// Streaming RIFF/WAVE writer
+///
+/// Chunk sizes are patched on [`WavWriter::finalize`], so the writer has to be seekable.
+/// RIFF sizes are 32 bit, writing past 4 GiB is an error.
+///
+/// ```txt
+/// "RIFF" size "WAVE"
+/// "fmt " 18 format channels sample_rate byte_rate block_align bits 0
+///   or 40 0xFFFE channels sample_rate byte_rate block_align bits 22
+///         valid_bits channel_mask format subformat_guid_tail   (24 bit or more than 2 channels)
+/// ["fact" 4 frames]   (float only)
+/// "data" size samples
+/// ```
+/// [PCM / WAV 格式](https://www.cnblogs.com/renhui/p/12148330.html)
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    spec: WavSpec,
+    /// bytes before the sample data
+    header_len: u32,
+    /// bytes of sample data written so far
+    data_len: u64,
+    quantizer: Quantizer,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, spec: WavSpec) -> Result<Self, StdIoError> {
+        let bytes = spec.sample_format.bytes();
+        let block_align = spec.channels * bytes;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        let format_tag = spec.sample_format.format_tag();
+        let fmt_len: u32 = if spec.is_extensible() { 40 } else { 18 };
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&fmt_len.to_le_bytes())?;
+        if spec.is_extensible() {
+            writer.write_all(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes())?;
+        } else {
+            writer.write_all(&format_tag.to_le_bytes())?;
+        }
+        writer.write_all(&spec.channels.to_le_bytes())?;
+        writer.write_all(&spec.sample_rate.to_le_bytes())?;
+        writer.write_all(&(spec.sample_rate * u32::from(block_align)).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(bytes * 8).to_le_bytes())?;
+        if spec.is_extensible() {
+            let channel_mask = match spec.channels {
+                1 => SPEAKER_FRONT_CENTER,
+                _ => SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+            };
+            writer.write_all(&22u16.to_le_bytes())?;
+            writer.write_all(&(bytes * 8).to_le_bytes())?;
+            writer.write_all(&channel_mask.to_le_bytes())?;
+            writer.write_all(&format_tag.to_le_bytes())?;
+            writer.write_all(&SUBFORMAT_GUID_TAIL)?;
+        } else {
+            writer.write_all(&0u16.to_le_bytes())?;
+        }
+        let mut header_len = 12 + 8 + fmt_len + 8;
+        if spec.sample_format == WavSampleFormat::Float32 {
+            writer.write_all(b"fact")?;
+            writer.write_all(&4u32.to_le_bytes())?;
+            writer.write_all(&0u32.to_le_bytes())?;
+            header_len += 12;
+        }
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            spec,
+            header_len,
+            data_len: 0,
+            quantizer: Quantizer::new(Dither::None),
+        })
+    }
+
+    pub fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    /// Dither of the integer formats, none by default
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.quantizer.set_dither(dither);
+    }
+
+    /// Write interleaved samples in `[-1.0, 1.0]`, integer formats are clipped
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), StdIoError> {
+        let len = samples.len() as u64 * u64::from(self.spec.sample_format.bytes());
+        // the RIFF size counts everything after it, a pad byte included
+        if u64::from(self.header_len) - 8 + self.data_len + len + 1 > u64::from(u32::MAX) {
+            return Err(StdIoError::other("WAV files are limited to 4 GiB"));
+        }
+        for s in samples {
+            match self.spec.sample_format {
+                WavSampleFormat::Int16 => {
+                    let v = self.quantizer.quantize(*s, 16) as i16;
+                    self.writer.write_all(&v.to_le_bytes())?;
+                }
+                WavSampleFormat::Int24 => {
+                    let v = self.quantizer.quantize(*s, 24);
+                    self.writer.write_all(&v.to_le_bytes()[0..3])?;
+                }
+                WavSampleFormat::Float32 => {
+                    self.writer.write_all(&s.to_le_bytes())?;
+                }
+            }
+        }
+        self.data_len += len;
+        Ok(())
+    }
+
+    /// Patch the chunk sizes and return the inner writer
+    pub fn finalize(mut self) -> Result<W, StdIoError> {
+        let header_len = self.header_len;
+        // below 4 GiB, checked on write
+        let data_len = self.data_len as u32;
+        let pad = data_len % 2;
+        if pad == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(header_len - 8 + data_len + pad).to_le_bytes())?;
+        if self.spec.sample_format == WavSampleFormat::Float32 {
+            let block_align = u32::from(self.spec.channels * self.spec.sample_format.bytes());
+            // the frame count of the fact chunk before the data chunk header
+            self.writer
+                .seek(SeekFrom::Start(u64::from(header_len) - 12))?;
+            self.writer
+                .write_all(&(data_len / block_align).to_le_bytes())?;
+        }
+        self.writer
+            .seek(SeekFrom::Start(u64::from(header_len) - 4))?;
+        self.writer.write_all(&data_len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn write(sample_format: WavSampleFormat, samples: &[f32]) -> Vec<u8> {
+        write_channels(sample_format, 2, samples)
+    }
+
+    fn write_channels(sample_format: WavSampleFormat, channels: u16, samples: &[f32]) -> Vec<u8> {
+        let spec = WavSpec {
+            sample_rate: 48_000,
+            channels,
+            sample_format,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+        writer.write_samples(samples).unwrap();
+        writer.finalize().unwrap().into_inner()
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    #[test]
+    fn int16_layout() {
+        let bytes = write(WavSampleFormat::Int16, &[0.0, 1.0, -1.0, 2.0]);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        // block align and bits per sample
+        assert_eq!(&bytes[32..36], &[4, 0, 16, 0]);
+        assert_eq!(&bytes[38..42], b"data");
+        assert_eq!(u32_at(&bytes, 42), 8);
+        assert_eq!(&bytes[46..], &[0, 0, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn int24_is_padded_to_even_length() {
+        let bytes = write(WavSampleFormat::Int24, &[0.5]);
+        assert_eq!(&bytes[60..64], b"data");
+        assert_eq!(u32_at(&bytes, 64), 3);
+        assert_eq!(&bytes[68..71], &[0x00, 0x00, 0x40]);
+        assert_eq!(bytes.len(), 72);
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+    }
+
+    #[test]
+    fn extensible_for_int24_and_surround() {
+        let bytes = write(WavSampleFormat::Int24, &[0.5, 0.5]);
+        assert_eq!(u32_at(&bytes, 16), 40);
+        assert_eq!(u16_at(&bytes, 20), WAVE_FORMAT_EXTENSIBLE);
+        // block align, bits, extension size, valid bits and the stereo mask
+        assert_eq!(u16_at(&bytes, 32), 6);
+        assert_eq!(u16_at(&bytes, 34), 24);
+        assert_eq!(u16_at(&bytes, 36), 22);
+        assert_eq!(u16_at(&bytes, 38), 24);
+        assert_eq!(u32_at(&bytes, 40), 0x3);
+        assert_eq!(u16_at(&bytes, 44), WAVE_FORMAT_PCM);
+        assert_eq!(&bytes[46..60], &SUBFORMAT_GUID_TAIL);
+        // float keeps its fact chunk after the longer fmt chunk
+        let bytes = write_channels(WavSampleFormat::Float32, 4, &[0.25; 8]);
+        assert_eq!(u16_at(&bytes, 20), WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(u16_at(&bytes, 44), WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!(&bytes[60..64], b"fact");
+        assert_eq!(u32_at(&bytes, 68), 2);
+        assert_eq!(u32_at(&bytes, 76), 32);
+        assert_eq!(bytes.len(), 80 + 32);
+        // 16 bit stereo stays plain PCM
+        assert_eq!(
+            u16_at(&write(WavSampleFormat::Int16, &[]), 20),
+            WAVE_FORMAT_PCM
+        );
+    }
+
+    #[test]
+    fn data_past_4_gib_is_an_error() {
+        let spec = WavSpec {
+            sample_rate: 48_000,
+            channels: 2,
+            sample_format: WavSampleFormat::Int16,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+        writer.data_len = u64::from(u32::MAX) - 64;
+        assert!(writer.write_samples(&[0.0; 4]).is_ok());
+        assert!(writer.write_samples(&[0.0; 16]).is_err());
+    }
+
+    #[test]
+    fn float32_has_fact_chunk() {
+        let bytes = write(WavSampleFormat::Float32, &[0.25, -0.25, 0.5, -0.5]);
+        assert_eq!(&bytes[20..22], &[3, 0]);
+        assert_eq!(&bytes[38..42], b"fact");
+        assert_eq!(u32_at(&bytes, 46), 2);
+        assert_eq!(&bytes[50..54], b"data");
+        assert_eq!(u32_at(&bytes, 54), 16);
+        assert_eq!(&bytes[58..62], &0.25f32.to_le_bytes());
+    }
+}